@@ -0,0 +1,38 @@
+//! API key check for the admin and write routes (`serve --api-key`).
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+use crate::search::ApiError;
+
+/// Compare without returning early on the first differing byte, so response
+/// timing doesn't reveal how much of a guessed key is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Key sent as `Authorization: Bearer <key>` or `X-Api-Key: <key>`. Another
+/// `Authorization` scheme (e.g. `Basic` credentials for a proxy in front) leaves
+/// the key to `X-Api-Key`.
+fn presented_key(headers: &HeaderMap) -> Option<&[u8]> {
+    let bearer = headers.get(header::AUTHORIZATION).and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+    if let Some(key) = bearer {
+        return Some(key.trim_ascii());
+    }
+    headers.get("x-api-key").map(|value| value.as_bytes().trim_ascii())
+}
+
+/// 401 with a JSON error unless the request carries the configured key.
+pub async fn require_api_key(State(key): State<Arc<str>>, request: Request, next: Next) -> Response {
+    match presented_key(request.headers()) {
+        Some(presented) if constant_time_eq(presented, key.as_bytes()) => next.run(request).await,
+        Some(_) => ApiError::new(StatusCode::UNAUTHORIZED, "invalid API key").into_response(),
+        None => {
+            let error = "missing API key; send Authorization: Bearer <key> or X-Api-Key";
+            ApiError::new(StatusCode::UNAUTHORIZED, error).into_response()
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Compact posting list encoding for the binary index format: document IDs are
+//! sorted and delta-encoded, and every number is written as a LEB128 varint
+//! (7 bits per byte, high bit set on all but the last byte).
+
+use std::collections::HashMap;
+
+use crate::index::DocId;
+
+/// Append `value` as a varint.
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads varints from a byte slice.
+struct VarintReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl VarintReader<'_> {
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn read(&mut self) -> Result<u32, String> {
+        let mut value: u32 = 0;
+        for (i, &byte) in self.bytes.iter().enumerate().take(5) {
+            value |= u32::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.bytes = &self.bytes[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err(if self.bytes.len() < 5 {
+            "truncated varint in packed postings".to_string()
+        } else {
+            "varint too long in packed postings".to_string()
+        })
+    }
+
+    /// Next value of an ascending sequence, read as a delta from `prev`
+    /// (`None` for the first value, which is stored as is).
+    fn read_after(&mut self, prev: Option<u32>) -> Result<u32, String> {
+        let delta = self.read()?;
+        match prev {
+            None => Ok(delta),
+            Some(prev) => prev
+                .checked_add(delta)
+                .ok_or_else(|| "value overflow in packed postings".to_string()),
+        }
+    }
+}
+
+/// Document IDs of a posting list in ascending order.
+fn sorted_ids<V>(docs: &HashMap<DocId, V>) -> Vec<DocId> {
+    let mut ids: Vec<DocId> = docs.keys().copied().collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// Delta from the previous value of an ascending sequence (the first is written as is).
+fn delta(value: u32, prev: Option<u32>) -> u32 {
+    prev.map_or(value, |prev| value - prev)
+}
+
+/// Pack doc id -> tf as (doc delta, tf) varint pairs.
+pub fn pack_tf(docs: &HashMap<DocId, u32>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(docs.len() * 2);
+    let mut prev = None;
+    for id in sorted_ids(docs) {
+        write_varint(&mut out, delta(id, prev));
+        write_varint(&mut out, docs[&id]);
+        prev = Some(id);
+    }
+    out
+}
+
+/// Inverse of `pack_tf`.
+pub fn unpack_tf(bytes: &[u8]) -> Result<HashMap<DocId, u32>, String> {
+    let mut reader = VarintReader { bytes };
+    let mut docs = HashMap::new();
+    let mut prev = None;
+    while !reader.is_empty() {
+        let id = reader.read_after(prev)?;
+        docs.insert(id, reader.read()?);
+        prev = Some(id);
+    }
+    Ok(docs)
+}
+
+/// Pack doc id -> ascending positions as (doc delta, count, position deltas...) varints.
+pub fn pack_positions(docs: &HashMap<DocId, Vec<u32>>) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut prev = None;
+    for id in sorted_ids(docs) {
+        let positions = &docs[&id];
+        write_varint(&mut out, delta(id, prev));
+        write_varint(&mut out, positions.len() as u32);
+        let mut last = None;
+        for &pos in positions {
+            write_varint(&mut out, delta(pos, last));
+            last = Some(pos);
+        }
+        prev = Some(id);
+    }
+    out
+}
+
+/// Inverse of `pack_positions`.
+pub fn unpack_positions(bytes: &[u8]) -> Result<HashMap<DocId, Vec<u32>>, String> {
+    let mut reader = VarintReader { bytes };
+    let mut docs = HashMap::new();
+    let mut prev = None;
+    while !reader.is_empty() {
+        let id = reader.read_after(prev)?;
+        let count = reader.read()? as usize;
+        let mut positions = Vec::with_capacity(count.min(reader.bytes.len()));
+        let mut last = None;
+        for _ in 0..count {
+            let pos = reader.read_after(last)?;
+            positions.push(pos);
+            last = Some(pos);
+        }
+        docs.insert(id, positions);
+        prev = Some(id);
+    }
+    Ok(docs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(value: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn varints_use_seven_bits_per_byte() {
+        assert_eq!(varint(0), [0x00]);
+        assert_eq!(varint(127), [0x7f]);
+        assert_eq!(varint(128), [0x80, 0x01]);
+        assert_eq!(varint(300), [0xac, 0x02]);
+        assert_eq!(varint(16_384), [0x80, 0x80, 0x01]);
+        assert_eq!(varint(u32::MAX), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+        for value in [0, 1, 127, 128, 255, 16_383, 16_384, 1 << 21, 1 << 28, u32::MAX] {
+            let bytes = varint(value);
+            let mut reader = VarintReader { bytes: &bytes };
+            assert_eq!(reader.read(), Ok(value));
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn tf_round_trips_with_multi_byte_values_and_large_gaps() {
+        let cases: [&[(DocId, u32)]; 5] = [
+            &[],
+            &[(7, 1)],
+            &[(0, 128)],
+            &[(0, 1), (1, 127), (2, 128), (130, 300), (1_000_000, 16_384)],
+            &[(3, u32::MAX), (u32::MAX - 1, 2), (u32::MAX, 70_000)],
+        ];
+        for case in cases {
+            let docs: HashMap<DocId, u32> = case.iter().copied().collect();
+            assert_eq!(unpack_tf(&pack_tf(&docs)), Ok(docs));
+        }
+        // A single posting with small numbers takes two bytes.
+        assert_eq!(pack_tf(&HashMap::from([(5, 3)])), [5, 3]);
+        // IDs are delta-encoded: 1_000_000 then +1.
+        assert_eq!(pack_tf(&HashMap::from([(1_000_000, 1), (1_000_001, 1)])).len(), 3 + 1 + 1 + 1);
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let docs: HashMap<DocId, Vec<u32>> = HashMap::from([
+            (0, vec![0]),
+            (200, vec![1, 2, 300, 100_000]),
+            (u32::MAX, vec![u32::MAX - 1, u32::MAX]),
+            (9, Vec::new()),
+        ]);
+        assert_eq!(unpack_positions(&pack_positions(&docs)), Ok(docs));
+    }
+
+    #[test]
+    fn malformed_postings_are_errors() {
+        // Truncated varint, a doc without its tf, a six-byte varint, and an overflowing delta.
+        assert!(unpack_tf(&[0x80]).is_err());
+        assert!(unpack_tf(&[0x05]).is_err());
+        assert!(unpack_tf(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+        let mut bytes = varint(u32::MAX);
+        bytes.extend([1, 1, 1]);
+        assert!(unpack_tf(&bytes).is_err());
+        assert!(unpack_positions(&[0, 3, 1]).is_err());
+    }
+}
@@ -1,22 +1,157 @@
 //! Phase 2: Recursive crawl within the same site.
 //! Phase 5: Parallel crawl with async reqwest and Semaphore.
 
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
 use scraper::{Html, Selector};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use url::Url;
 
 const MAX_PAGES: usize = 50;
 const MAX_DEPTH: u32 = 3;
 const MAX_CONCURRENT: usize = 5;
+/// Meta refresh delays (seconds) at or below this are treated as redirects.
+const META_REFRESH_MAX_DELAY: f64 = 1.0;
 
 /// Result of crawling a single page.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CrawlResult {
+    /// Effective URL of the document; see [`effective_url`] for precedence.
     pub url: String,
+    /// URL the response was served from after HTTP redirects.
+    pub final_url: String,
+    /// Same-domain `<link rel="canonical">` target, if any.
+    pub canonical_url: Option<String>,
     pub title: String,
+    /// `<meta name="description">` (or og:description), empty if absent.
+    #[serde(default)]
+    pub description: String,
+    /// Text of the `<h1>`–`<h6>` elements, one heading per line.
+    #[serde(default)]
+    pub headings: String,
     pub body_text: String,
     pub links: Vec<String>,
+    /// `Last-Modified` header, or `article:modified_time` meta as a fallback.
+    pub last_modified: Option<DateTime<Utc>>,
+    /// Where `title` came from (`None` when no title was found).
+    pub title_source: Option<TitleSource>,
+    /// Set when the page is a meta refresh shell; `links` then holds only the target.
+    pub refresh_target: Option<String>,
+}
+
+/// Source of a page title, recorded for debugging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TitleSource {
+    /// The `<title>` element.
+    Title,
+    /// `<meta property="og:title">`.
+    OpenGraph,
+    /// `<meta name="twitter:title">`.
+    Twitter,
+    /// JSON-LD `headline` / `name` of an Article or WebPage.
+    JsonLd,
+}
+
+/// Crawl limits and filters. `None` fields fall back to the module defaults.
+#[derive(Debug, Clone, Default)]
+pub struct CrawlOptions {
+    pub max_pages: Option<usize>,
+    pub max_depth: Option<u32>,
+    pub max_concurrent: Option<usize>,
+    /// Only enqueue URLs whose decoded path starts with one of these (the start URL is always allowed).
+    pub path_prefixes: Vec<String>,
+    /// Compare path prefixes case-insensitively.
+    pub path_prefix_ignore_case: bool,
+    /// Kept up to date with the number of pages collected, for progress reports.
+    pub progress: Option<Arc<AtomicUsize>>,
+}
+
+/// Periodic snapshot hook: every `every` indexed pages, `save` is called with the
+/// results so far on a blocking thread.
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub every: usize,
+    pub save: Arc<dyn Fn(Vec<CrawlResult>) + Send + Sync>,
+}
+
+/// Pages fetched by a crawl plus counters for the summary line.
+#[derive(Debug, Default)]
+pub struct CrawlOutput {
+    pub results: Vec<CrawlResult>,
+    /// Unique same-domain links skipped by the path prefix filter.
+    pub filtered: usize,
+    /// Fetches that failed (network or parse errors).
+    pub failed: usize,
+    /// One entry per fetch attempt, including failures.
+    pub metrics: Vec<FetchMetric>,
+    /// Wall-clock duration of the whole crawl.
+    pub elapsed: Duration,
+}
+
+/// Cost of a single fetch, collected for the crawl summary.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FetchMetric {
+    pub url: String,
+    /// Response size (Content-Length, or decoded body length when absent).
+    pub bytes: u64,
+    pub elapsed_ms: f64,
+    /// Final HTTP status after redirects; `None` when no response was received.
+    pub status: Option<u16>,
+}
+
+/// Aggregate crawl metrics for the end-of-crawl report.
+#[derive(Debug, Clone)]
+pub struct MetricsSummary {
+    pub total_bytes: u64,
+    pub avg_latency_ms: f64,
+    pub p95_latency_ms: f64,
+    /// Fetch count per status class ("2xx", "3xx", "4xx", "5xx", "error").
+    pub status_classes: BTreeMap<String, usize>,
+    pub pages_per_sec: f64,
+}
+
+impl CrawlOutput {
+    /// Summarize the per-fetch metrics.
+    pub fn metrics_summary(&self) -> MetricsSummary {
+        let total_bytes = self.metrics.iter().map(|m| m.bytes).sum();
+        let mut latencies: Vec<f64> = self.metrics.iter().map(|m| m.elapsed_ms).collect();
+        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let avg_latency_ms = if latencies.is_empty() {
+            0.0
+        } else {
+            latencies.iter().sum::<f64>() / latencies.len() as f64
+        };
+        // Nearest-rank percentile.
+        let p95_latency_ms = if latencies.is_empty() {
+            0.0
+        } else {
+            let rank = (0.95 * latencies.len() as f64).ceil() as usize;
+            latencies[rank.clamp(1, latencies.len()) - 1]
+        };
+        let mut status_classes = BTreeMap::new();
+        for m in &self.metrics {
+            let class = match m.status {
+                Some(code) => format!("{}xx", code / 100),
+                None => "error".to_string(),
+            };
+            *status_classes.entry(class).or_insert(0) += 1;
+        }
+        let secs = self.elapsed.as_secs_f64();
+        let pages_per_sec = if secs > 0.0 { self.metrics.len() as f64 / secs } else { 0.0 };
+        MetricsSummary {
+            total_bytes,
+            avg_latency_ms,
+            p95_latency_ms,
+            status_classes,
+            pages_per_sec,
+        }
+    }
 }
 
 /// Normalize URL: resolve relative path, remove fragment.
@@ -32,21 +167,188 @@ fn same_domain(start: &Url, other: &Url) -> bool {
     start.host_str() == other.host_str()
 }
 
+/// Parse a meta refresh `content` value such as `0;url=/new-page` or `5; URL='/x'`.
+/// Returns the delay in seconds and the (unresolved) target, if any.
+fn parse_meta_refresh(content: &str) -> Option<(f64, &str)> {
+    let (delay, rest) = match content.find([';', ',']) {
+        Some(i) => (&content[..i], &content[i + 1..]),
+        None => (content, ""),
+    };
+    let delay: f64 = delay.trim().parse().ok()?;
+    let rest = rest.trim_start();
+    let target = match rest.get(..4) {
+        Some(key) if key.eq_ignore_ascii_case("url=") => &rest[4..],
+        _ => return None,
+    };
+    let target = target.trim().trim_matches(|c: char| c == '\'' || c == '"').trim();
+    if target.is_empty() {
+        return None;
+    }
+    Some((delay, target))
+}
+
+/// Find an immediate meta refresh target in the document (normalized, same domain).
+fn meta_refresh_target(document: &Html, base_url: &Url) -> Option<Url> {
+    let selector = Selector::parse("meta[http-equiv][content]").unwrap();
+    document
+        .select(&selector)
+        .filter(|el| {
+            el.value()
+                .attr("http-equiv")
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case("refresh"))
+        })
+        .filter_map(|el| parse_meta_refresh(el.value().attr("content")?))
+        .filter(|(delay, _)| *delay <= META_REFRESH_MAX_DELAY)
+        .filter_map(|(_, target)| normalize_url(base_url, target))
+        .find(|target| same_domain(base_url, target))
+}
+
+/// Content of the first non-empty `<meta>` matching `selector`.
+fn meta_content(document: &Html, selector: &str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+    document
+        .select(&selector)
+        .filter_map(|el| el.value().attr("content"))
+        .map(|c| c.trim().to_string())
+        .find(|c| !c.is_empty())
+}
+
+/// Check whether a JSON-LD `@type` (string or array) names an Article or WebPage.
+fn is_article_or_webpage(ty: Option<&serde_json::Value>) -> bool {
+    let matches = |v: &serde_json::Value| matches!(v.as_str(), Some("Article" | "WebPage"));
+    match ty {
+        Some(serde_json::Value::Array(types)) => types.iter().any(matches),
+        Some(v) => matches(v),
+        None => false,
+    }
+}
+
+/// Find `headline` (or `name`) of an Article/WebPage in a JSON-LD value.
+/// Walks arrays and `@graph` containers.
+fn json_ld_title(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Array(items) => items.iter().find_map(json_ld_title),
+        serde_json::Value::Object(obj) => {
+            if is_article_or_webpage(obj.get("@type")) {
+                let title = ["headline", "name"]
+                    .iter()
+                    .filter_map(|key| obj.get(*key)?.as_str())
+                    .map(|t| t.trim())
+                    .find(|t| !t.is_empty());
+                if let Some(title) = title {
+                    return Some(title.to_string());
+                }
+            }
+            obj.get("@graph").and_then(json_ld_title)
+        }
+        _ => None,
+    }
+}
+
+/// Extract the page title: `<title>`, then og:title, twitter:title, then JSON-LD.
+/// Invalid JSON-LD blocks are ignored.
+fn extract_title(document: &Html) -> Option<(String, TitleSource)> {
+    let title = document
+        .select(&Selector::parse("title").unwrap())
+        .next()
+        .map(|el| el.text().collect::<String>().trim().to_string())
+        .filter(|t| !t.is_empty());
+    if let Some(title) = title {
+        return Some((title, TitleSource::Title));
+    }
+    if let Some(title) = meta_content(document, r#"meta[property="og:title"]"#) {
+        return Some((title, TitleSource::OpenGraph));
+    }
+    if let Some(title) = meta_content(document, r#"meta[name="twitter:title"]"#) {
+        return Some((title, TitleSource::Twitter));
+    }
+    let json_ld = Selector::parse(r#"script[type="application/ld+json"]"#).unwrap();
+    document
+        .select(&json_ld)
+        .filter_map(|el| serde_json::from_str::<serde_json::Value>(&el.text().collect::<String>()).ok())
+        .find_map(|value| json_ld_title(&value))
+        .map(|title| (title, TitleSource::JsonLd))
+}
+
+/// Same-domain `<link rel="canonical">` of the document, normalized.
+fn canonical_link(document: &Html, base_url: &Url) -> Option<Url> {
+    let selector = Selector::parse(r#"link[rel="canonical"][href]"#).unwrap();
+    let href = document.select(&selector).next()?.value().attr("href")?;
+    let canonical = normalize_url(base_url, href.trim())?;
+    same_domain(base_url, &canonical).then_some(canonical)
+}
+
+/// URL a page is recorded (and deduplicated) under.
+/// Precedence: canonical link > final redirect URL > requested URL.
+pub fn effective_url<'a>(requested: &'a str, final_url: Option<&'a str>, canonical: Option<&'a str>) -> &'a str {
+    canonical.or(final_url).unwrap_or(requested)
+}
+
+/// Parse an HTTP-date `Last-Modified` header value.
+fn parse_last_modified(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim()).ok().map(|d| d.with_timezone(&Utc))
+}
+
+/// `article:modified_time` meta (ISO 8601 / RFC 3339).
+fn meta_modified_time(document: &Html) -> Option<DateTime<Utc>> {
+    let value = meta_content(document, r#"meta[property="article:modified_time"]"#)?;
+    DateTime::parse_from_rfc3339(&value).ok().map(|d| d.with_timezone(&Utc))
+}
+
 /// Fetch a single page (async).
 async fn fetch_page_async(
     client: &reqwest::Client,
     url: &str,
+    metric: &mut FetchMetric,
 ) -> Result<CrawlResult, Box<dyn std::error::Error + Send + Sync>> {
-    let body = client.get(url).send().await?.text().await?;
+    let response = client.get(url).send().await?;
+    metric.status = Some(response.status().as_u16());
+    // Relative links resolve against the post-redirect URL.
+    let base_url = response.url().clone();
+    let header_modified = response
+        .headers()
+        .get(reqwest::header::LAST_MODIFIED)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_last_modified);
+    let content_length = response.content_length();
+    let body = response.text().await?;
+    metric.bytes = content_length.unwrap_or(body.len() as u64);
     let document = Html::parse_document(&body);
-    let base_url = Url::parse(url)?;
+    let final_url = base_url.to_string();
 
-    let title = document
-        .select(&Selector::parse("title").unwrap())
-        .next()
-        .map(|el| el.text().collect::<String>().trim().to_string())
+    // Meta refresh shell: follow the target instead of indexing the (empty) page.
+    if let Some(target) = meta_refresh_target(&document, &base_url) {
+        return Ok(CrawlResult {
+            url: url.to_string(),
+            final_url,
+            canonical_url: None,
+            title: String::new(),
+            description: String::new(),
+            headings: String::new(),
+            body_text: String::new(),
+            last_modified: header_modified,
+            links: vec![target.to_string()],
+            title_source: None,
+            refresh_target: Some(target.to_string()),
+        });
+    }
+
+    let (title, title_source) = match extract_title(&document) {
+        Some((title, source)) => (title, Some(source)),
+        None => (String::new(), None),
+    };
+
+    let description = meta_content(&document, r#"meta[name="description"]"#)
+        .or_else(|| meta_content(&document, r#"meta[property="og:description"]"#))
         .unwrap_or_default();
 
+    let headings = document
+        .select(&Selector::parse("h1, h2, h3, h4, h5, h6").unwrap())
+        .map(|el| el.text().collect::<String>().trim().to_string())
+        .filter(|h| !h.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
     let body_text = document
         .select(&Selector::parse("body").unwrap())
         .next()
@@ -67,35 +369,165 @@ async fn fetch_page_async(
         }
     }
 
+    let canonical_url = canonical_link(&document, &base_url).map(|u| u.to_string());
+    let url = effective_url(url, Some(final_url.as_str()), canonical_url.as_deref()).to_string();
+
+    let last_modified = header_modified.or_else(|| meta_modified_time(&document));
+
     Ok(CrawlResult {
-        url: url.to_string(),
+        url,
+        final_url,
+        canonical_url,
         title,
+        description,
+        headings,
         body_text,
+        last_modified,
         links,
+        title_source,
+        refresh_target: None,
+    })
+}
+
+/// Write crawl results as a JSONL document dump (one `CrawlResult` per line).
+pub fn save_dump(results: &[CrawlResult], path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for result in results {
+        serde_json::to_writer(&mut writer, result)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a JSONL document dump written by [`save_dump`]. Blank lines are ignored.
+pub fn load_dump(path: &Path) -> Result<Vec<CrawlResult>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = DumpReader::new(BufReader::new(File::open(path)?)).strict(true);
+    let results = reader.by_ref().collect();
+    match reader.error {
+        Some(e) => Err(e),
+        None => Ok(results),
+    }
+}
+
+/// Streams documents from a JSONL dump one line at a time. Malformed lines are
+/// skipped with a warning and counted in `skipped`; in strict mode the first one
+/// ends iteration instead and is left in `error`, as is any read error.
+pub struct DumpReader<R> {
+    lines: std::io::Lines<R>,
+    line_no: usize,
+    strict: bool,
+    /// Documents returned so far.
+    pub read: usize,
+    /// Malformed lines skipped (non-strict mode).
+    pub skipped: usize,
+    /// Error that stopped iteration, if any.
+    pub error: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl<R: BufRead> DumpReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_no: 0,
+            strict: false,
+            read: 0,
+            skipped: 0,
+            error: None,
+        }
+    }
+
+    /// Stop at the first malformed line instead of skipping it.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+}
+
+impl<R: BufRead> Iterator for DumpReader<R> {
+    type Item = CrawlResult;
+
+    fn next(&mut self) -> Option<CrawlResult> {
+        if self.error.is_some() {
+            return None;
+        }
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => {
+                    self.error = Some(e.into());
+                    return None;
+                }
+            };
+            self.line_no += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(result) => {
+                    self.read += 1;
+                    return Some(result);
+                }
+                Err(e) if self.strict => {
+                    self.error = Some(format!("line {}: {}", self.line_no, e).into());
+                    return None;
+                }
+                Err(e) => {
+                    eprintln!("Warning: skipping malformed line {}: {}", self.line_no, e);
+                    self.skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Check whether the decoded path of `url` starts with one of the allowed prefixes.
+/// An empty prefix list allows everything.
+fn path_allowed(url: &str, options: &CrawlOptions) -> bool {
+    if options.path_prefixes.is_empty() {
+        return true;
+    }
+    let Ok(parsed) = Url::parse(url) else {
+        return false;
+    };
+    let path = percent_decode_str(parsed.path()).decode_utf8_lossy();
+    options.path_prefixes.iter().any(|prefix| {
+        if options.path_prefix_ignore_case {
+            path.to_lowercase().starts_with(&prefix.to_lowercase())
+        } else {
+            path.starts_with(prefix.as_str())
+        }
     })
 }
 
 /// Crawl starting from `start_url`, staying on the same domain (async, parallel).
-async fn crawl_async(
+/// For callers already running on a tokio runtime, such as the server.
+pub async fn crawl_async(
     start_url: &str,
-    max_pages: Option<usize>,
-    max_depth: Option<u32>,
-    max_concurrent: Option<usize>,
-) -> Result<Vec<CrawlResult>, Box<dyn std::error::Error + Send + Sync>> {
-    let max_pages = max_pages.unwrap_or(MAX_PAGES);
-    let max_depth = max_depth.unwrap_or(MAX_DEPTH);
-    let max_concurrent = max_concurrent.unwrap_or(MAX_CONCURRENT);
+    options: &CrawlOptions,
+    checkpoint: Option<Checkpoint>,
+) -> Result<CrawlOutput, Box<dyn std::error::Error + Send + Sync>> {
+    let max_pages = options.max_pages.unwrap_or(MAX_PAGES);
+    let max_depth = options.max_depth.unwrap_or(MAX_DEPTH);
+    let max_concurrent = options.max_concurrent.unwrap_or(MAX_CONCURRENT);
 
     let _start = Url::parse(start_url)?;
     let client = reqwest::Client::new();
     let sem = Arc::new(tokio::sync::Semaphore::new(max_concurrent));
 
     let mut visited: HashSet<String> = HashSet::new();
+    // Effective URLs of the pages in `results`.
+    let mut indexed: HashSet<String> = HashSet::new();
+    let mut filtered: HashSet<String> = HashSet::new();
+    let mut failed = 0;
     let mut queue: VecDeque<(String, u32)> = VecDeque::new();
     queue.push_back((start_url.to_string(), 0));
 
     let mut results = Vec::new();
     let mut join_set = tokio::task::JoinSet::new();
+    let mut pending_checkpoint: Option<tokio::task::JoinHandle<()>> = None;
+    let mut metrics = Vec::new();
+    let crawl_started = Instant::now();
 
     loop {
         // Spawn up to max_concurrent tasks
@@ -114,8 +546,16 @@ async fn crawl_async(
             let url2 = url.clone();
             join_set.spawn(async move {
                 let _permit = permit;
-                let r = fetch_page_async(&client, &url2).await;
-                (r, depth)
+                let started = Instant::now();
+                let mut metric = FetchMetric {
+                    url: url2.clone(),
+                    bytes: 0,
+                    elapsed_ms: 0.0,
+                    status: None,
+                };
+                let r = fetch_page_async(&client, &url2, &mut metric).await;
+                metric.elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+                (r, url2, depth, metric)
             });
         }
 
@@ -126,30 +566,222 @@ async fn crawl_async(
         let Some(join_result) = join_set.join_next().await else {
             break;
         };
-        let (res, depth) = join_result.map_err(|e| e.to_string())?;
+        let (res, requested, depth, metric) = join_result.map_err(|e| e.to_string())?;
         let result = match res {
-            Ok(r) => r,
-            Err(_) => continue,
+            Ok(r) => {
+                tracing::debug!(
+                    url = %requested,
+                    status = metric.status,
+                    bytes = metric.bytes,
+                    elapsed_ms = metric.elapsed_ms,
+                    "fetched"
+                );
+                metrics.push(metric);
+                r
+            }
+            Err(e) => {
+                tracing::warn!(url = %requested, status = metric.status, error = %e, "fetch failed");
+                metrics.push(metric);
+                failed += 1;
+                continue;
+            }
+        };
+        // The requested URL was marked visited when spawned; also claim the effective and
+        // final URLs so later direct links to them are skipped. If a page was already
+        // indexed under the effective URL, this one is a duplicate: keep its links, drop its
+        // body. Merely visited is not enough: that fetch may have failed or been a shell.
+        let duplicate = indexed.contains(&result.url);
+        visited.insert(result.url.clone());
+        visited.insert(result.final_url.clone());
+        // A meta refresh behaves like a redirect: same depth, and the shell is not indexed.
+        let (next_depth, redirect) = match result.refresh_target {
+            Some(_) => (depth, true),
+            None => (depth + 1, false),
         };
-        results.push(result.clone());
         for link in &result.links {
-            if !visited.contains(link) {
-                queue.push_back((link.clone(), depth + 1));
+            if visited.contains(link) {
+                continue;
+            }
+            if !path_allowed(link, options) {
+                filtered.insert(link.clone());
+                continue;
+            }
+            if redirect {
+                queue.push_front((link.clone(), next_depth));
+            } else {
+                queue.push_back((link.clone(), next_depth));
             }
         }
+        if !redirect && !duplicate {
+            indexed.insert(result.url.clone());
+            results.push(result);
+            if let Some(progress) = &options.progress {
+                progress.store(results.len(), Ordering::Relaxed);
+            }
+            if let Some(cp) = &checkpoint {
+                // Skip this checkpoint if the previous one is still being written.
+                let idle = pending_checkpoint.as_ref().is_none_or(|h| h.is_finished());
+                if cp.every > 0 && results.len() % cp.every == 0 && idle {
+                    let snapshot = results.clone();
+                    let save = cp.save.clone();
+                    pending_checkpoint = Some(tokio::task::spawn_blocking(move || save(snapshot)));
+                }
+            }
+        }
+    }
+
+    // Let an in-flight checkpoint finish so it cannot overwrite the final save.
+    if let Some(handle) = pending_checkpoint {
+        handle.await.map_err(|e| e.to_string())?;
     }
 
-    Ok(results)
+    Ok(CrawlOutput {
+        results,
+        filtered: filtered.len(),
+        failed,
+        metrics,
+        elapsed: crawl_started.elapsed(),
+    })
 }
 
 /// Crawl starting from `start_url`, staying on the same domain.
 /// Returns at most `max_pages` results, with depth limited by `max_depth`.
-/// Uses parallel async fetching (Phase 5).
+/// Uses parallel async fetching (Phase 5) on a runtime of its own.
 pub fn crawl(
     start_url: &str,
-    max_pages: Option<usize>,
-    max_depth: Option<u32>,
-) -> Result<Vec<CrawlResult>, Box<dyn std::error::Error + Send + Sync>> {
+    options: &CrawlOptions,
+    checkpoint: Option<Checkpoint>,
+) -> Result<CrawlOutput, Box<dyn std::error::Error + Send + Sync>> {
     let rt = tokio::runtime::Runtime::new()?;
-    rt.block_on(crawl_async(start_url, max_pages, max_depth, None))
+    rt.block_on(crawl_async(start_url, options, checkpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::{Html as HtmlResponse, Redirect};
+    use axum::routing::get;
+
+    /// Serve `pages` (path -> HTML) on a local port, plus `/a` redirecting to `/b`.
+    async fn serve_site(pages: &'static [(&'static str, &'static str)]) -> String {
+        let mut app = axum::Router::new().route("/a", get(|| async { Redirect::permanent("/b") }));
+        for &(path, html) in pages {
+            app = app.route(path, get(move || async move { HtmlResponse(html) }));
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/", addr)
+    }
+
+    #[test]
+    fn parses_meta_refresh_content() {
+        assert_eq!(parse_meta_refresh("0;url=/new-page"), Some((0.0, "/new-page")));
+        assert_eq!(parse_meta_refresh("5; URL='/new-page'"), Some((5.0, "/new-page")));
+        assert_eq!(parse_meta_refresh("5; URL=/new-page"), Some((5.0, "/new-page")));
+        assert_eq!(parse_meta_refresh("0"), None);
+        assert_eq!(parse_meta_refresh("soon;url=/new-page"), None);
+    }
+
+    #[test]
+    fn follows_only_immediate_same_domain_meta_refresh() {
+        let base = Url::parse("https://a.example/old/").unwrap();
+        let target = |content: &str| {
+            let html = format!(r#"<head><meta http-equiv="Refresh" content="{}"></head>"#, content);
+            meta_refresh_target(&Html::parse_document(&html), &base).map(|u| u.to_string())
+        };
+        assert_eq!(target("0;url=/new-page").as_deref(), Some("https://a.example/new-page"));
+        assert_eq!(target("0;url=next#top").as_deref(), Some("https://a.example/old/next"));
+        // A delayed refresh is a notice the reader sees, not a redirect.
+        assert_eq!(target("5; URL=/new-page"), None);
+        assert_eq!(target("0;url=https://b.example/"), None);
+    }
+
+    #[tokio::test]
+    async fn meta_refresh_shell_links_only_to_its_target() {
+        static PAGES: &[(&str, &str)] = &[
+            (
+                "/shell",
+                r#"<head><meta http-equiv="refresh" content="0;url=/new-page"></head>
+                   <body>Moved. <a href="/elsewhere">elsewhere</a></body>"#,
+            ),
+            (
+                "/delayed",
+                r#"<head><title>Delayed</title><meta http-equiv="refresh" content="5; URL=/new-page"></head>
+                   <body>Moving soon. <a href="/elsewhere">elsewhere</a></body>"#,
+            ),
+        ];
+        let base = serve_site(PAGES).await;
+        let client = reqwest::Client::new();
+        let mut metric = FetchMetric {
+            url: String::new(),
+            bytes: 0,
+            elapsed_ms: 0.0,
+            status: None,
+        };
+        let url = |path: &str| format!("{}{}", base, path.trim_start_matches('/'));
+        let shell = fetch_page_async(&client, &url("/shell"), &mut metric).await.unwrap();
+        assert_eq!(shell.refresh_target, Some(url("/new-page")));
+        assert_eq!(shell.links, [url("/new-page")]);
+        assert_eq!(shell.body_text, "");
+        let delayed = fetch_page_async(&client, &url("/delayed"), &mut metric).await.unwrap();
+        assert_eq!(delayed.refresh_target, None);
+        assert_eq!(delayed.links, [url("/elsewhere")]);
+        assert_eq!(delayed.title, "Delayed");
+    }
+
+    #[test]
+    fn effective_url_prefers_canonical_then_final_then_requested() {
+        let cases = [
+            (None, None, "https://a.example/requested"),
+            (Some("https://a.example/final"), None, "https://a.example/final"),
+            (None, Some("https://a.example/canonical"), "https://a.example/canonical"),
+            (Some("https://a.example/final"), Some("https://a.example/canonical"), "https://a.example/canonical"),
+        ];
+        for (final_url, canonical, expected) in cases {
+            assert_eq!(effective_url("https://a.example/requested", final_url, canonical), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn crawl_indexes_each_effective_url_once() {
+        static PAGES: &[(&str, &str)] = &[
+            (
+                "/",
+                r#"<a href="/a">a</a> <a href="/b">b</a> <a href="/c">c</a>
+                   <a href="/shell">shell</a> <a href="/d">d</a>"#,
+            ),
+            ("/b", "<body>page b</body>"),
+            (
+                "/c",
+                r#"<head><link rel="canonical" href="/b"></head><body>copy of b</body>"#,
+            ),
+            (
+                "/shell",
+                r#"<head><meta http-equiv="refresh" content="0;url=/target"></head>"#,
+            ),
+            ("/target", "<body>target</body>"),
+            (
+                "/d",
+                r#"<head><link rel="canonical" href="/shell"></head><body>page d</body>"#,
+            ),
+        ];
+        let base = serve_site(PAGES).await;
+        // One fetch at a time, so `/b` and `/shell` are processed before `/c` and `/d`.
+        let options = CrawlOptions {
+            max_concurrent: Some(1),
+            ..Default::default()
+        };
+        let output = crawl_async(&base, &options, None).await.unwrap();
+        let mut urls: Vec<&str> = output.results.iter().map(|r| r.url.strip_prefix(&base).unwrap()).collect();
+        urls.sort_unstable();
+        // `/a` redirects to `/b` and is indexed under it, so the direct link to `/b` is
+        // skipped and `/c` (canonical `/b`) is a duplicate. `/shell` was visited but
+        // not indexed, so `/d` is indexed under its canonical `/shell`.
+        assert_eq!(urls, ["", "b", "shell", "target"]);
+        let page = |path: &str| output.results.iter().find(|r| r.url == format!("{}{}", base, path)).unwrap();
+        assert_eq!(page("b").final_url, format!("{}b", base));
+        assert_eq!(page("b").body_text, "page b");
+        assert_eq!(page("shell").body_text, "page d");
+    }
 }
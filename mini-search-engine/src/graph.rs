@@ -0,0 +1,116 @@
+//! Intra-site link graph built from crawl results, exported as DOT or JSON.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use url::Url;
+
+use crate::crawler::CrawlResult;
+
+/// A page in the link graph.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GraphNode {
+    pub url: String,
+    /// URL path (plus query), used as the display label.
+    pub label: String,
+    /// False for URLs that were linked to but never fetched.
+    pub fetched: bool,
+    /// Outgoing links (sorted, deduplicated). Always empty for unfetched nodes.
+    pub links: Vec<String>,
+}
+
+/// Link graph of a crawl, keyed by URL.
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    nodes: BTreeMap<String, GraphNode>,
+}
+
+/// Label for a node: path and query of the URL, or the URL itself if unparsable.
+fn path_label(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(u) => match u.query() {
+            Some(q) => format!("{}?{}", u.path(), q),
+            None => u.path().to_string(),
+        },
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Escape a string for a double-quoted DOT identifier.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl LinkGraph {
+    /// Build from crawl results. Links to a page's post-redirect URL are
+    /// attributed to the page itself.
+    pub fn build(results: &[CrawlResult]) -> Self {
+        let mut aliases: HashMap<&str, &str> = HashMap::new();
+        for r in results {
+            aliases.insert(r.final_url.as_str(), r.url.as_str());
+            aliases.insert(r.url.as_str(), r.url.as_str());
+        }
+        let mut nodes = BTreeMap::new();
+        for r in results {
+            let links: BTreeSet<String> = r
+                .links
+                .iter()
+                .map(|l| aliases.get(l.as_str()).copied().unwrap_or(l.as_str()).to_string())
+                .collect();
+            nodes.insert(
+                r.url.clone(),
+                GraphNode {
+                    url: r.url.clone(),
+                    label: path_label(&r.url),
+                    fetched: true,
+                    links: links.into_iter().collect(),
+                },
+            );
+        }
+        let unfetched: Vec<String> = nodes
+            .values()
+            .flat_map(|n| n.links.iter())
+            .filter(|l| !nodes.contains_key(*l))
+            .cloned()
+            .collect();
+        for url in unfetched {
+            nodes.entry(url.clone()).or_insert_with(|| GraphNode {
+                label: path_label(&url),
+                url,
+                fetched: false,
+                links: Vec::new(),
+            });
+        }
+        Self { nodes }
+    }
+
+    /// Graphviz DOT. Unfetched nodes and the edges to them are dashed and grey.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph site {\n  node [shape=box];\n");
+        for node in self.nodes.values() {
+            let style = if node.fetched { "" } else { ", style=dashed, color=gray" };
+            let _ = writeln!(
+                out,
+                "  \"{}\" [label=\"{}\"{}];",
+                dot_escape(&node.url),
+                dot_escape(&node.label),
+                style
+            );
+        }
+        for node in self.nodes.values() {
+            for target in &node.links {
+                let fetched = self.nodes.get(target).is_some_and(|n| n.fetched);
+                let style = if fetched { "" } else { " [style=dashed, color=gray]" };
+                let _ = writeln!(out, "  \"{}\" -> \"{}\"{};", dot_escape(&node.url), dot_escape(target), style);
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// JSON adjacency list: one entry per node, sorted by URL.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        let nodes: Vec<&GraphNode> = self.nodes.values().collect();
+        serde_json::to_string_pretty(&nodes)
+    }
+}
@@ -1,134 +1,4076 @@
 //! Phase 3: Inverted index (word -> set of URLs). Phase 4: save/load. Phase 6: TF-IDF ranking.
 
-use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Duration, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::borrow::Borrow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use rayon::prelude::*;
+
+use crate::codec;
 use crate::crawler::CrawlResult;
-use crate::tokenize;
+use crate::query::{self, DocSet, Query, QueryError, Scope, Scoped};
+use crate::spell;
+use crate::synonyms::Synonyms;
+use crate::tokenize::{self, Analyzer, AnalyzerConfig, TokenRejections};
 
 /// Inverted index: word -> URLs containing that word (backward compat / simple search).
 pub type InvertedIndex = HashMap<String, HashSet<String>>;
 
+/// Number of body characters kept per document for snippets.
+const STORED_TEXT_CHARS: usize = 500;
+
+/// Documents analyzed in parallel at a time by `IndexWithTf::build_from_iter`.
+const BUILD_BATCH: usize = 256;
+
+/// Per-document metadata kept alongside the postings.
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone, Debug)]
+pub struct DocMeta {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    /// First ~500 characters of the body text.
+    #[serde(default)]
+    pub text: String,
+    /// Last modification time reported by the server or page.
+    #[serde(default)]
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl DocMeta {
+    /// Metadata for a crawled page.
+    fn from_result(result: &CrawlResult) -> Self {
+        Self {
+            title: result.title.clone(),
+            description: result.description.clone(),
+            text: result.body_text.chars().take(STORED_TEXT_CHARS).collect(),
+            last_modified: result.last_modified,
+        }
+    }
+}
+
+/// Compact document identifier: index into `IndexWithTf::urls`.
+pub type DocId = u32;
+
+/// term -> doc id -> count
+pub type Postings = HashMap<String, HashMap<DocId, u32>>;
+
 /// Index with term frequency per document for TF-IDF ranking.
-#[derive(serde::Serialize, serde::Deserialize, Default)]
+/// Documents are referred to by `DocId`; the URL table maps IDs back to URLs.
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone)]
 pub struct IndexWithTf {
-    /// term -> url -> term count in that document
-    pub term_tf: HashMap<String, HashMap<String, u32>>,
+    /// doc id -> url (empty string for removed documents)
+    pub urls: Vec<String>,
+    /// url -> doc id, rebuilt from `urls` on load
+    #[serde(skip)]
+    url_ids: HashMap<String, DocId>,
+    /// term -> doc id -> term count in that document
+    pub term_tf: Postings,
     /// Total number of documents
     pub doc_count: usize,
+    /// doc id -> document metadata
+    #[serde(default)]
+    pub docs: HashMap<DocId, DocMeta>,
+    /// doc id -> number of tokens in the document
+    #[serde(default)]
+    pub doc_lengths: HashMap<DocId, u32>,
+    /// Average document length in tokens (0 when unknown)
+    #[serde(default)]
+    pub avg_doc_len: f64,
+    /// term -> doc id -> sorted token positions
+    #[serde(default)]
+    pub positions: HashMap<String, HashMap<DocId, Vec<u32>>>,
+    /// field -> term -> doc id -> term count in that field (title, headings, URL)
+    #[serde(default)]
+    pub fields: HashMap<Field, Postings>,
+    /// Analyzer used at build time; queries go through the same one.
+    #[serde(default)]
+    pub analyzer: AnalyzerConfig,
+    /// duplicate url -> url of the indexed document with the same body
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// term -> IDF of its body postings, rebuilt by `finalize`
+    #[serde(skip)]
+    idf: HashMap<String, TermIdf>,
+    /// field -> term -> IDF of its postings in that field, rebuilt by `finalize`
+    #[serde(skip)]
+    field_idf: HashMap<Field, HashMap<String, TermIdf>>,
+    /// Sorted terms of every field for prefix lookups, rebuilt by `finalize`
+    #[serde(skip)]
+    vocabulary: Vec<String>,
+    /// doc id -> norm of its body TF-IDF vector, rebuilt by `finalize`
+    #[serde(skip)]
+    doc_norms: HashMap<DocId, f64>,
+    /// Tokens dropped by the analyzer's token filter while adding documents
+    #[serde(skip)]
+    rejected: TokenRejections,
 }
 
-impl IndexWithTf {
-    /// Build from crawl results.
-    pub fn build(results: &[CrawlResult]) -> Self {
-        let mut term_tf: HashMap<String, HashMap<String, u32>> = HashMap::new();
-        for result in results {
-            let words = tokenize::tokenize(&result.body_text);
-            for word in words {
-                *term_tf
-                    .entry(word)
-                    .or_default()
-                    .entry(result.url.clone())
-                    .or_insert(0) += 1;
+/// A term's IDF under each ranker, computed once per index change instead of per query.
+#[derive(Debug, Clone, Copy)]
+struct TermIdf {
+    tfidf: f64,
+    bm25: f64,
+}
+
+impl TermIdf {
+    fn new(df: usize, n: f64) -> Self {
+        let df = df as f64;
+        Self {
+            tfidf: idf(Ranker::Tfidf, df, n),
+            bm25: idf(Ranker::Bm25, df, n),
+        }
+    }
+
+    fn get(self, ranker: Ranker) -> f64 {
+        match ranker {
+            Ranker::Tfidf | Ranker::Cosine => self.tfidf,
+            Ranker::Bm25 => self.bm25,
+        }
+    }
+}
+
+/// URL-keyed layout written before document IDs were introduced. Converted on load.
+#[derive(serde::Deserialize)]
+struct LegacyIndexWithTf {
+    term_tf: HashMap<String, HashMap<String, u32>>,
+    doc_count: usize,
+}
+
+impl LegacyIndexWithTf {
+    /// Assign document IDs (in URL order) and re-key the postings by ID.
+    fn into_current(self) -> IndexWithTf {
+        let mut urls: Vec<&String> = self.term_tf.values().flat_map(|m| m.keys()).collect();
+        urls.sort();
+        urls.dedup();
+        let mut index = IndexWithTf::default();
+        for url in urls {
+            index.intern(url);
+        }
+        let term_tf = self
+            .term_tf
+            .into_iter()
+            .map(|(term, docs)| (term, docs.into_iter().map(|(url, tf)| (index.url_ids[&url], tf)).collect()))
+            .collect();
+        index.term_tf = term_tf;
+        index.doc_count = self.doc_count;
+        index.add_url_postings();
+        index
+    }
+}
+
+/// A broken invariant found by `IndexWithTf::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// `doc_count` differs from the number of documents in the URL table.
+    DocCount { doc_count: usize, documents: usize },
+    /// A posting has a term frequency of zero.
+    ZeroTf { term: String, doc: DocId },
+    /// A posting list is keyed by the empty string.
+    EmptyTerm,
+    /// A document URL doesn't parse.
+    InvalidUrl { url: String, reason: String },
+    /// A map refers to a document ID that isn't in the URL table.
+    UnknownDocument { map: &'static str, doc: DocId },
+    /// Two live document IDs share a URL.
+    DuplicateUrl { url: String },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DocCount { doc_count, documents } => {
+                write!(f, "doc_count is {} but the index holds {} documents", doc_count, documents)
             }
+            Self::ZeroTf { term, doc } => write!(f, "term {:?} has tf 0 in document {}", term, doc),
+            Self::EmptyTerm => write!(f, "empty term in postings"),
+            Self::InvalidUrl { url, reason } => write!(f, "invalid URL {:?}: {}", url, reason),
+            Self::UnknownDocument { map, doc } => write!(f, "{} refers to unknown document {}", map, doc),
+            Self::DuplicateUrl { url } => write!(f, "URL {:?} appears under more than one document ID", url),
         }
-        let doc_count = results.len();
-        Self { term_tf, doc_count }
     }
+}
 
-    /// As simple InvertedIndex (word -> set of URLs) for backward compat.
-    pub fn as_inverted(&self) -> InvertedIndex {
-        self.term_tf
+/// Number of most frequent terms listed in `IndexStats`.
+const STATS_TOP_TERMS: usize = 20;
+
+/// Summary of an index for sanity checks. `None` marks figures the index format
+/// doesn't record (e.g. term frequencies in a legacy `InvertedIndex`).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct IndexStats {
+    pub documents: usize,
+    /// Distinct body terms.
+    pub vocabulary: usize,
+    /// (term, document) pairs.
+    pub total_postings: usize,
+    /// Sum of all body term frequencies.
+    pub total_tokens: Option<u64>,
+    /// Average body length in tokens.
+    pub avg_doc_len: Option<f64>,
+    /// Median body length in tokens.
+    pub median_doc_len: Option<f64>,
+    /// Terms with the highest document frequency, as (term, df).
+    pub top_terms: Vec<(String, usize)>,
+    /// Index file size in bytes.
+    pub file_size: Option<u64>,
+    /// Stopword list the index was built with (`english (127 words)`).
+    pub stopwords: Option<String>,
+}
+
+impl IndexStats {
+    /// Statistics of a legacy InvertedIndex (no term frequencies or lengths).
+    pub fn from_inverted(index: &InvertedIndex) -> Self {
+        let documents: HashSet<&String> = index.values().flatten().collect();
+        Self {
+            documents: documents.len(),
+            vocabulary: index.len(),
+            total_postings: index.values().map(|urls| urls.len()).sum(),
+            total_tokens: None,
+            avg_doc_len: None,
+            median_doc_len: None,
+            top_terms: Self::top_terms(index.iter().map(|(term, urls)| (term.as_str(), urls.len()))),
+            file_size: None,
+            stopwords: None,
+        }
+    }
+
+    /// Highest document frequencies first; ties in term order.
+    fn top_terms<'a>(df: impl Iterator<Item = (&'a str, usize)>) -> Vec<(String, usize)> {
+        let mut terms: Vec<(&str, usize)> = df.collect();
+        terms.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        terms
+            .into_iter()
+            .take(STATS_TOP_TERMS)
+            .map(|(term, df)| (term.to_string(), df))
+            .collect()
+    }
+}
+
+/// Number of largest document frequency changes listed in `IndexDiff`.
+const DIFF_TOP_CHANGES: usize = 20;
+
+/// A body term's document frequency in two indexes.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DfChange {
+    pub term: String,
+    pub df_a: usize,
+    pub df_b: usize,
+}
+
+/// Differences between two indexes (A and B), e.g. before and after a tokenizer change.
+/// Lists are sorted; each side is read once into a hash set, so comparing is linear.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct IndexDiff {
+    pub docs_only_in_a: Vec<String>,
+    pub docs_only_in_b: Vec<String>,
+    /// Body terms in B but not A.
+    pub terms_added: Vec<String>,
+    /// Body terms in A but not B.
+    pub terms_removed: Vec<String>,
+    /// Terms in both with the largest absolute df change, largest first.
+    pub df_changes: Vec<DfChange>,
+}
+
+impl IndexDiff {
+    pub fn between(a: &IndexWithTf, b: &IndexWithTf) -> Self {
+        fn only_in<'a>(ours: &HashSet<&'a str>, theirs: &HashSet<&'a str>) -> Vec<String> {
+            let mut only: Vec<String> = ours.difference(theirs).map(|s| s.to_string()).collect();
+            only.sort();
+            only
+        }
+        let docs_a: HashSet<&str> = a.url_ids.keys().map(String::as_str).collect();
+        let docs_b: HashSet<&str> = b.url_ids.keys().map(String::as_str).collect();
+        let terms_a: HashSet<&str> = a.term_tf.keys().map(String::as_str).collect();
+        let terms_b: HashSet<&str> = b.term_tf.keys().map(String::as_str).collect();
+        let mut df_changes: Vec<DfChange> = terms_a
+            .intersection(&terms_b)
+            .map(|&term| DfChange {
+                term: term.to_string(),
+                df_a: a.term_tf[term].len(),
+                df_b: b.term_tf[term].len(),
+            })
+            .filter(|change| change.df_a != change.df_b)
+            .collect();
+        df_changes.sort_by(|x, y| {
+            let delta = |c: &DfChange| c.df_a.abs_diff(c.df_b);
+            delta(y).cmp(&delta(x)).then_with(|| x.term.cmp(&y.term))
+        });
+        df_changes.truncate(DIFF_TOP_CHANGES);
+        Self {
+            docs_only_in_a: only_in(&docs_a, &docs_b),
+            docs_only_in_b: only_in(&docs_b, &docs_a),
+            terms_added: only_in(&terms_b, &terms_a),
+            terms_removed: only_in(&terms_a, &terms_b),
+            df_changes,
+        }
+    }
+}
+
+/// Hash of a document's token sequence, used to detect exact duplicates.
+/// Documents without any tokens have no hash (they are never collapsed).
+fn body_hash(body: &str) -> Option<u64> {
+    let tokens = tokenize::tokenize(body);
+    if tokens.is_empty() {
+        return None;
+    }
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    tokens.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Result type of `IndexReader` (storage backends can fail on every read).
+pub type ReadResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// One document's occurrences of a body term.
+#[derive(Debug, Clone)]
+pub struct Posting {
+    pub doc: DocId,
+    pub tf: u32,
+    /// Token positions (empty for indexes without positions).
+    pub positions: Vec<u32>,
+}
+
+/// A stored document: URL, body length, metadata (if recorded) and the norm of its
+/// body TF-IDF vector over the whole index (for cosine ranking).
+#[derive(Debug, Clone)]
+pub struct StoredDocument {
+    pub url: String,
+    pub length: Option<u32>,
+    pub meta: Option<DocMeta>,
+    pub norm: Option<f64>,
+}
+
+/// Read access to an index, whether it's the in-memory `IndexWithTf` or a storage
+/// backend. `IndexWithTf::for_query` loads what a query needs from any reader, so
+/// ranking runs the same code for every backend.
+pub trait IndexReader {
+    fn doc_count(&self) -> usize;
+    fn avg_doc_len(&self) -> f64;
+    fn analyzer(&self) -> &AnalyzerConfig;
+    /// Body postings of a term.
+    fn postings(&self, term: &str) -> ReadResult<Vec<Posting>>;
+    /// Postings of a term in another field, as (doc, tf).
+    fn field_postings(&self, field: Field, term: &str) -> ReadResult<Vec<(DocId, u32)>>;
+    /// Up to `MAX_PREFIX_EXPANSIONS` terms starting with `prefix`, most frequent first.
+    fn prefix_terms(&self, prefix: &str) -> ReadResult<Vec<String>>;
+    fn document(&self, id: DocId) -> ReadResult<Option<StoredDocument>>;
+}
+
+impl IndexReader for IndexWithTf {
+    fn doc_count(&self) -> usize {
+        self.doc_count
+    }
+
+    fn avg_doc_len(&self) -> f64 {
+        self.avg_doc_len
+    }
+
+    fn analyzer(&self) -> &AnalyzerConfig {
+        &self.analyzer
+    }
+
+    fn postings(&self, term: &str) -> ReadResult<Vec<Posting>> {
+        let Some(docs) = self.term_tf.get(term) else {
+            return Ok(Vec::new());
+        };
+        let positions = self.positions.get(term);
+        Ok(docs
+            .iter()
+            .map(|(&doc, &tf)| Posting {
+                doc,
+                tf,
+                positions: positions.and_then(|m| m.get(&doc)).cloned().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    fn field_postings(&self, field: Field, term: &str) -> ReadResult<Vec<(DocId, u32)>> {
+        Ok(self
+            .field(field)
+            .and_then(|postings| postings.get(term))
+            .map(|docs| docs.iter().map(|(&doc, &tf)| (doc, tf)).collect())
+            .unwrap_or_default())
+    }
+
+    fn prefix_terms(&self, prefix: &str) -> ReadResult<Vec<String>> {
+        Ok(self.complete_prefix(prefix))
+    }
+
+    fn document(&self, id: DocId) -> ReadResult<Option<StoredDocument>> {
+        let Some(url) = self.urls.get(id as usize).filter(|url| !url.is_empty()) else {
+            return Ok(None);
+        };
+        Ok(Some(StoredDocument {
+            url: url.clone(),
+            length: self.doc_lengths.get(&id).copied(),
+            meta: self.docs.get(&id).cloned(),
+            norm: self.doc_norm(id),
+        }))
+    }
+}
+
+/// Type-ahead completions of a partly typed query: its last word completed to
+/// up to `limit` vocabulary terms starting with it, most frequent first, each
+/// after the earlier words as typed (`rust tok` -> `rust tokio`). The prefix is
+/// only lower-cased, not analyzed, since stemming a partial word makes little
+/// sense; with stemming the completions are stems. Nothing is completed after
+/// a trailing space.
+pub fn complete_query(reader: &impl IndexReader, query: &str, limit: usize) -> ReadResult<Vec<String>> {
+    if query.ends_with(char::is_whitespace) {
+        return Ok(Vec::new());
+    }
+    let (head, last) = match query.trim().rsplit_once(char::is_whitespace) {
+        Some((head, last)) => (head.trim_end(), last),
+        None => ("", query.trim()),
+    };
+    let prefix = last.trim_start_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+    if prefix.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut terms = reader.prefix_terms(&prefix)?;
+    terms.truncate(limit);
+    Ok(terms
+        .into_iter()
+        .map(|term| if head.is_empty() { term } else { format!("{} {}", head, term) })
+        .collect())
+}
+
+/// One term's share of a document's score.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScoreContribution {
+    pub term: String,
+    /// `body`, `title`, `headings`, `url` or `phrase`.
+    pub field: &'static str,
+    pub tf: u32,
+    pub df: usize,
+    pub idf: f64,
+    /// Multiplier applied (field boost, phrase boost, synonym weight).
+    pub weight: f64,
+    pub score: f64,
+}
+
+/// A contribution as produced by the scoring loops, before it is owned.
+#[derive(Clone, Copy)]
+struct TermScore<'a> {
+    term: &'a str,
+    field: &'static str,
+    tf: u32,
+    df: usize,
+    idf: f64,
+    weight: f64,
+    score: f64,
+}
+
+impl<'a> TermScore<'a> {
+    fn new(term: &'a str, field: &'static str, tf: u32, df: usize, idf: f64, weight: f64, score: f64) -> Self {
+        Self {
+            term,
+            field,
+            tf,
+            df,
+            idf,
+            weight,
+            score,
+        }
+    }
+}
+
+impl From<TermScore<'_>> for ScoreContribution {
+    fn from(part: TermScore<'_>) -> Self {
+        Self {
+            term: part.term.to_string(),
+            field: part.field,
+            tf: part.tf,
+            df: part.df,
+            idf: part.idf,
+            weight: part.weight,
+            score: part.score,
+        }
+    }
+}
+
+/// Per-term breakdown of a hit's score: `score` is the sum of the contributions'
+/// scores times `recency_factor` and `proximity_factor`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Explanation {
+    pub url: String,
+    pub score: f64,
+    pub recency_factor: f64,
+    pub proximity_factor: f64,
+    pub contributions: Vec<ScoreContribution>,
+}
+
+/// Requirements a scored document must meet to be a hit.
+struct QueryMatch {
+    /// Documents matching each phrase and field clause (all are required).
+    required_sets: Vec<HashSet<DocId>>,
+    /// Documents containing an excluded term or matching an excluded field clause.
+    excluded: HashSet<DocId>,
+    /// Term groups a document must match, and how many each document does.
+    required: usize,
+    term_counts: HashMap<DocId, usize>,
+    /// Words of each term group, for `proximity_factor` (empty when it's off).
+    proximity_groups: Vec<Vec<String>>,
+    /// Documents satisfying a boolean query's operators.
+    boolean: Option<DocSet>,
+}
+
+impl QueryMatch {
+    fn accepts(&self, id: DocId) -> bool {
+        self.boolean.as_ref().is_none_or(|docs| docs.contains(id))
+            && self.required_sets.iter().all(|set| set.contains(&id))
+            && !self.excluded.contains(&id)
+            && (self.required <= 1 || self.term_counts.get(&id).copied().unwrap_or(0) >= self.required)
+    }
+}
+
+/// A ranked result with its stored metadata (if the index has any).
+#[derive(Debug, Clone)]
+pub struct RankedHit<'a> {
+    pub url: String,
+    pub score: f64,
+    pub meta: Option<&'a DocMeta>,
+    /// With `SearchOptions::collapse`, on the best hit of a group: how many more
+    /// hits of that group were left out.
+    pub collapsed: usize,
+}
+
+/// One page of ranked results.
+#[derive(Debug, Clone)]
+pub struct SearchPage<'a> {
+    /// Number of matching documents, before `offset` and `limit`.
+    pub total: usize,
+    pub hits: Vec<RankedHit<'a>>,
+    /// For each of `SearchOptions::facets`, the most common values among all
+    /// matching documents (not just this page).
+    pub facets: BTreeMap<Facet, Vec<FacetBucket>>,
+}
+
+/// One way `search_relaxed` loosened a query that matched nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RelaxStep {
+    /// Matched any term instead of all of them (or `mm`).
+    AnyTerm,
+    /// Left out the word found in the fewest documents.
+    DropTerm,
+    /// Replaced unknown words with the closest indexed terms.
+    Fuzzy,
+}
+
+/// A relaxation step and the query it left.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, utoipa::ToSchema)]
+pub struct Relaxation {
+    pub step: RelaxStep,
+    /// The query searched after this step.
+    pub query: String,
+}
+
+/// Results of a relaxed query: the first non-empty page, with how it was found.
+#[derive(Debug, Clone)]
+pub struct RelaxedSearch<'a> {
+    pub page: SearchPage<'a>,
+    /// Steps applied, in order; each builds on the ones before it.
+    pub steps: Vec<Relaxation>,
+    /// The query and options the page was found with.
+    pub query: String,
+    pub options: SearchOptions,
+}
+
+/// Matching documents found by `rank_page`.
+#[derive(Default)]
+struct RankedPage {
+    /// Number of matches after collapsing, before `offset` and `limit`.
+    total: usize,
+    ranked: Vec<(DocId, f64)>,
+    facets: BTreeMap<Facet, Vec<FacetBucket>>,
+    /// Documents whose group was collapsed, with the number dropped.
+    collapsed: HashMap<DocId, usize>,
+}
+
+/// Keep only the best `size` (at least 1) hits per value of a facet (`collapse=host`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Collapse {
+    pub by: Facet,
+    pub size: usize,
+}
+
+/// Drop hits scoring below a threshold (`min_score`), before `total` is counted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinScore {
+    /// At least this ranker score.
+    Raw(f64),
+    /// At least this fraction of the best hit's score. When the best score isn't
+    /// positive every hit counts as 1.0, as with `normalize=max`.
+    Relative(f64),
+}
+
+impl MinScore {
+    fn passes(self, score: f64, top: f64) -> bool {
+        match self {
+            MinScore::Raw(min) => score >= min,
+            MinScore::Relative(min) if top > 0.0 => score / top >= min,
+            MinScore::Relative(min) => min <= 1.0,
+        }
+    }
+}
+
+/// What ranked results are ordered by (`SearchOptions::sort`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Ranking score.
+    #[default]
+    Score,
+    /// URL, byte-wise.
+    Url,
+    /// Last modification time (`DocMeta::last_modified`); documents without one
+    /// come last in either order.
+    Time,
+}
+
+impl SortBy {
+    /// Order used when none is given: best score and newest first, URLs A to Z.
+    pub fn default_order(self) -> SortOrder {
+        match self {
+            SortBy::Score | SortBy::Time => SortOrder::Desc,
+            SortBy::Url => SortOrder::Asc,
+        }
+    }
+}
+
+/// Direction of a `Sort`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn apply(self, ordering: Ordering) -> Ordering {
+        match self {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+/// Order of ranked results. Ties fall back to score order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sort {
+    pub by: SortBy,
+    pub order: SortOrder,
+}
+
+/// Most values returned per facet.
+pub const MAX_FACET_BUCKETS: usize = 10;
+
+/// A property of a document's URL that search results can be counted by.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Facet {
+    /// The host (`docs.example.com`).
+    Host,
+    /// The first path segment (`docs` in `/docs/intro`); pages at the root have none.
+    Path1,
+}
+
+impl Facet {
+    pub const ALL: [Facet; 2] = [Facet::Host, Facet::Path1];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Facet::Host => "host",
+            Facet::Path1 => "path1",
+        }
+    }
+
+    fn value(self, url: &url::Url) -> Option<String> {
+        match self {
+            Facet::Host => url.host_str().map(str::to_string),
+            Facet::Path1 => url.path_segments()?.next().filter(|s| !s.is_empty()).map(|segment| {
+                percent_encoding::percent_decode_str(segment).decode_utf8_lossy().into_owned()
+            }),
+        }
+    }
+}
+
+/// A facet value and the number of matching documents that have it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, utoipa::ToSchema)]
+pub struct FacetBucket {
+    pub value: String,
+    pub count: usize,
+}
+
+/// Default weight of title matches relative to body matches.
+pub const DEFAULT_TITLE_BOOST: f64 = 2.0;
+
+/// Default weight of heading matches.
+pub const DEFAULT_HEADINGS_BOOST: f64 = 1.5;
+
+/// Default weight of URL path matches.
+pub const DEFAULT_URL_BOOST: f64 = 1.0;
+
+/// An indexed field besides the body. The body keeps its own postings with
+/// positions and lengths; these fields store term counts only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    /// The page title.
+    Title,
+    /// `<h1>`–`<h6>` text.
+    Headings,
+    /// Words of the URL path.
+    Url,
+}
+
+impl Field {
+    pub const ALL: [Field; 3] = [Field::Title, Field::Headings, Field::Url];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Headings => "headings",
+            Field::Url => "url",
+        }
+    }
+}
+
+/// Query-time weight of each field: a document's score is the sum over fields of
+/// boost × field score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldBoosts {
+    pub body: f64,
+    pub title: f64,
+    pub headings: f64,
+    pub url: f64,
+}
+
+impl Default for FieldBoosts {
+    fn default() -> Self {
+        Self {
+            body: 1.0,
+            title: DEFAULT_TITLE_BOOST,
+            headings: DEFAULT_HEADINGS_BOOST,
+            url: DEFAULT_URL_BOOST,
+        }
+    }
+}
+
+impl FieldBoosts {
+    pub fn get(&self, field: Field) -> f64 {
+        match field {
+            Field::Title => self.title,
+            Field::Headings => self.headings,
+            Field::Url => self.url,
+        }
+    }
+
+    /// Set the boost of a field by name (`body`, `title`, `headings` or `url`).
+    pub fn set(&mut self, name: &str, boost: f64) -> Result<(), String> {
+        if !boost.is_finite() || boost < 0.0 {
+            return Err(format!("boost for {:?} must be a non-negative number", name));
+        }
+        let slot = match name {
+            "body" => &mut self.body,
+            "title" => &mut self.title,
+            "headings" => &mut self.headings,
+            "url" => &mut self.url,
+            _ => return Err(format!("unknown field {:?} (expected body, title, headings or url)", name)),
+        };
+        *slot = boost;
+        Ok(())
+    }
+}
+
+/// The text of a document, per field.
+pub struct DocumentText<'a> {
+    pub url: &'a str,
+    pub title: &'a str,
+    pub headings: &'a str,
+    pub body: &'a str,
+}
+
+/// A document run through the analyzer, ready to be added to the postings.
+/// Analysis needs only the analyzer, so documents can be analyzed in parallel.
+struct AnalyzedDoc {
+    fields: Vec<(Field, Vec<String>)>,
+    /// Body tokens with their positions.
+    body: Vec<(String, u32)>,
+    rejected: TokenRejections,
+}
+
+impl AnalyzedDoc {
+    fn new(analyzer: &dyn Analyzer, text: &DocumentText<'_>) -> Self {
+        let mut rejected = TokenRejections::default();
+        let url = url_path_text(text.url);
+        let fields = [(Field::Title, text.title), (Field::Headings, text.headings), (Field::Url, url.as_str())]
+            .into_iter()
+            .map(|(field, text)| (field, analyzer.analyze_counting(text, &mut rejected)))
+            .collect();
+        let body = analyzer.analyze_with_positions(text.body, &mut rejected);
+        Self { fields, body, rejected }
+    }
+}
+
+/// Words of a URL's path (host and query are left out: the host matches every page
+/// of a site). Separators become spaces, so `/blog/async-rust.html` gives `blog`,
+/// `async`, `rust` and `html` whatever the tokenizer.
+fn url_path_text(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(parsed) => percent_encoding::percent_decode_str(parsed.path())
+            .decode_utf8_lossy()
+            .replace(['/', '-', '_', '.', '~', '+'], " "),
+        Err(_) => String::new(),
+    }
+}
+
+/// Score multiplier for terms matched as part of a quoted phrase.
+const PHRASE_BOOST: f64 = 1.2;
+
+/// Default weight of the proximity boost: documents with all query terms adjacent
+/// score up to 1.5 times as much.
+pub const DEFAULT_PROXIMITY_BOOST: f64 = 0.5;
+
+/// Number of a document's top terms used as the `more_like_this` query.
+const MLT_QUERY_TERMS: usize = 20;
+
+/// Most documents of a term sampled by `related_terms`.
+const RELATED_SAMPLE_DOCS: usize = 500;
+
+/// Terms in more than this fraction of documents are never suggested as related.
+const RELATED_MAX_DF_RATIO: f64 = 0.5;
+
+/// Most vocabulary terms a prefix query expands to.
+pub const MAX_PREFIX_EXPANSIONS: usize = 50;
+
+/// Queries with more words than this are only relaxed to match any term: dropping
+/// one word of many rarely helps, and correcting each is a vocabulary scan.
+const RELAX_MAX_WORDS: usize = 8;
+
+/// A query term and its alternatives (synonyms, prefix completions), each with a score weight.
+/// A document matches the group if it contains any alternative.
+type TermGroup = Vec<(String, f64)>;
+
+/// A query split into bare terms, double-quoted phrases and field clauses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedQuery {
+    /// Terms outside quotes (any may match).
+    pub terms: Vec<String>,
+    /// Quoted phrases, tokenized (all must match, adjacent and in order).
+    pub phrases: Vec<Vec<String>>,
+    /// Terms written as `-word`: documents containing any of them are dropped.
+    pub excluded: Vec<String>,
+    /// `title:rust` or `url:"blog posts"` clauses (all must match); their words
+    /// score in that field only, with its boost. A prefix that isn't a field name
+    /// is part of the word, so `re:invent` stays a plain term.
+    pub scoped: Vec<Scoped>,
+    /// Field clauses written as `-title:word`: documents matching any are dropped.
+    pub excluded_scoped: Vec<Scoped>,
+    /// A query with `AND`/`OR`/`NOT` or parentheses (see `query`). Matching
+    /// documents must satisfy it; `terms` and `scoped` then hold its non-negated
+    /// words and field clauses, which score as usual.
+    pub boolean: Option<Query>,
+}
+
+impl ParsedQuery {
+    /// Analyzed words that count as matches in a snippet: bare terms, phrase words
+    /// and the words of field clauses.
+    pub fn highlight_terms(&self) -> HashSet<&str> {
+        self.terms
             .iter()
-            .map(|(k, v)| (k.clone(), v.keys().cloned().collect()))
+            .chain(self.phrases.iter().flatten())
+            .chain(self.scoped.iter().flat_map(|scoped| &scoped.tokens))
+            .map(String::as_str)
             .collect()
     }
 
-    /// Search with TF-IDF ranking. Returns (url, score) sorted by score descending.
-    pub fn search_ranked(&self, query: &str) -> Vec<(String, f64)> {
-        let words = tokenize::tokenize(query);
-        if words.is_empty() || self.doc_count == 0 {
-            return Vec::new();
+    /// Parse with the index's analyzer. A query made only of stopwords
+    /// keeps them rather than becoming empty. A query of only exclusions is empty.
+    /// Malformed boolean syntax is searched as plain words; use `try_parse_with`
+    /// to report it instead.
+    pub fn parse_with(query: &str, analyzer: &dyn Analyzer) -> Self {
+        Self::try_parse_with(query, analyzer)
+            .unwrap_or_else(|_| Self::parse_plain(&analyzer.normalize(query), analyzer))
+    }
+
+    /// Like `parse_with`, failing on malformed boolean syntax. The whole query is
+    /// normalized first, so full-width quotes, colons and operators work too;
+    /// error positions count characters of the normalized query.
+    pub fn try_parse_with(query: &str, analyzer: &dyn Analyzer) -> Result<Self, QueryError> {
+        let query = analyzer.normalize(query);
+        let query = query.as_ref();
+        if !query::is_boolean(query) {
+            return Ok(Self::parse_plain(query, analyzer));
         }
-        let n = self.doc_count as f64;
-        let mut url_scores: HashMap<String, f64> = HashMap::new();
-        for word in &words {
-            let url_counts = match self.term_tf.get(word) {
-                Some(m) => m,
-                None => continue,
-            };
-            let df = url_counts.len() as f64;
-            let idf = (n + 1.0) / (df + 1.0);
-            let idf = idf.ln() + 1.0;
-            for (url, &tf) in url_counts {
-                *url_scores.entry(url.clone()).or_insert(0.0) += (tf as f64) * idf;
+        let parsed = Self::from_boolean(query::parse(query, |part| analyzer.analyze(part))?);
+        if parsed.is_empty() {
+            let keep_stopwords = analyzer.keeping_stopwords();
+            return Ok(Self::from_boolean(query::parse(query, |part| keep_stopwords.analyze(part))?));
+        }
+        Ok(parsed)
+    }
+
+    fn from_boolean(tree: Query) -> Self {
+        Self {
+            terms: tree.positive_words(),
+            scoped: tree.positive_scoped(),
+            boolean: Some(tree),
+            ..Self::default()
+        }
+    }
+
+    fn parse_plain(query: &str, analyzer: &dyn Analyzer) -> Self {
+        let parsed = Self::split(query, |part| analyzer.analyze(part));
+        if parsed.is_empty() {
+            let keep_stopwords = analyzer.keeping_stopwords();
+            return Self::split(query, |part| keep_stopwords.analyze(part));
+        }
+        parsed
+    }
+
+    fn split(query: &str, analyze: impl Fn(&str) -> Vec<String>) -> Self {
+        let mut parsed = Self::default();
+        let parts: Vec<&str> = query.split('"').collect();
+        // Set when a `title:` (or `-title:`) touches the quote opening the next phrase.
+        let mut phrase_scope = None;
+        for (i, part) in parts.iter().enumerate() {
+            if i % 2 == 0 {
+                let mut words: Vec<&str> = part.split_whitespace().collect();
+                phrase_scope = None;
+                if i + 1 < parts.len() && !part.ends_with(char::is_whitespace) {
+                    if let Some(&last) = words.last() {
+                        let (negated, word) = query::strip_negation(last);
+                        if let Some((scope, "")) = query::split_scope(word) {
+                            phrase_scope = Some((negated, scope));
+                            words.pop();
+                        }
+                    }
+                }
+                for word in words {
+                    let (negated, word) = query::strip_negation(word);
+                    match query::split_scope(word) {
+                        Some((scope, rest)) if !rest.is_empty() => {
+                            let tokens = analyze(rest);
+                            parsed.add_scoped(negated, Scoped { scope, tokens, phrase: false });
+                        }
+                        _ if negated => parsed.excluded.extend(analyze(word)),
+                        _ => parsed.terms.extend(analyze(word)),
+                    }
+                }
+            } else {
+                let phrase = analyze(part);
+                match phrase_scope {
+                    Some((negated, scope)) => {
+                        parsed.add_scoped(negated, Scoped { scope, tokens: phrase, phrase: true });
+                    }
+                    None if !phrase.is_empty() => parsed.phrases.push(phrase),
+                    None => {}
+                }
             }
         }
-        let mut v: Vec<(String, f64)> = url_scores.into_iter().collect();
-        v.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-        v
+        parsed
+    }
+
+    /// Keep a field clause unless it analyzed to nothing (only stopwords).
+    fn add_scoped(&mut self, negated: bool, scoped: Scoped) {
+        if scoped.tokens.is_empty() {
+            return;
+        }
+        if negated {
+            self.excluded_scoped.push(scoped);
+        } else {
+            self.scoped.push(scoped);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty() && self.phrases.is_empty() && self.scoped.is_empty()
+    }
+
+    /// Bare terms without repeats, in query order.
+    pub fn distinct_terms(&self) -> Vec<&String> {
+        let mut seen = HashSet::new();
+        self.terms.iter().filter(|t| seen.insert(*t)).collect()
     }
 }
 
-/// Build inverted index from crawl results (simple, no TF).
-pub fn build_index(results: &[CrawlResult]) -> InvertedIndex {
-    IndexWithTf::build(results).as_inverted()
+/// Ranking function used by `search_ranked_with`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Ranker {
+    /// Raw term frequency times smoothed IDF.
+    #[default]
+    Tfidf,
+    /// Okapi BM25.
+    Bm25,
+    /// Cosine similarity of query and document TF-IDF vectors (body only), in [0, 1]
+    /// before any recency or proximity boost. Rewards focused documents.
+    Cosine,
 }
 
-/// Build index with TF for ranking; save as IndexWithTf.
-pub fn build_index_with_tf(results: &[CrawlResult]) -> IndexWithTf {
-    IndexWithTf::build(results)
-}
-
-/// Look up URLs that contain the given query (simple, no ranking).
-pub fn search(index: &InvertedIndex, query: &str) -> Vec<String> {
-    let words = tokenize::tokenize(query);
-    if words.is_empty() {
-        return Vec::new();
-    }
-    let mut urls: Option<HashSet<String>> = None;
-    for word in &words {
-        let set = index.get(word).cloned().unwrap_or_default();
-        urls = Some(match urls {
-            None => set,
-            Some(u) => u.intersection(&set).cloned().collect(),
-        });
+/// How TF-IDF scales raw term frequency (BM25 has its own normalization).
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TfNorm {
+    /// Raw count: long documents that repeat a term win.
+    #[default]
+    Raw,
+    /// Count divided by document length relative to the average length.
+    Length,
+    /// `1 + ln(tf)`: diminishing returns for repeats.
+    Log,
+}
+
+/// How many of the bare query terms a document must contain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// At least one (the terms are ORed).
+    #[default]
+    Any,
+    /// Every term.
+    All,
+    /// At least this many distinct terms (all of them if the query has fewer).
+    MinShouldMatch(usize),
+}
+
+impl MatchMode {
+    /// Number of distinct terms required out of `terms`.
+    fn required(self, terms: usize) -> usize {
+        match self {
+            Self::Any => 1,
+            Self::All => terms,
+            Self::MinShouldMatch(n) => n.min(terms),
+        }
     }
-    urls.map(|u| {
-        let mut v: Vec<String> = u.into_iter().collect();
-        v.sort();
-        v
-    }).unwrap_or_default()
 }
 
-/// Save index (simple InvertedIndex) to JSON file.
-pub fn save_index(index: &InvertedIndex, path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let json = serde_json::to_string_pretty(index)?;
-    fs::write(path, json)?;
-    Ok(())
+/// BM25 parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct Bm25Params {
+    /// Term frequency saturation.
+    pub k1: f64,
+    /// Length normalization strength (0 = none, 1 = full).
+    pub b: f64,
 }
 
-/// Save IndexWithTf to JSON file.
-pub fn save_index_with_tf(index: &IndexWithTf, path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let json = serde_json::to_string_pretty(index)?;
-    fs::write(path, json)?;
-    Ok(())
+impl Default for Bm25Params {
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+/// Multiply scores of recently modified documents.
+#[derive(Debug, Clone, Copy)]
+pub struct RecencyBoost {
+    /// Documents modified within this many days are boosted.
+    pub days: u32,
+    /// Score multiplier for those documents.
+    pub factor: f64,
 }
 
-/// Load index from JSON file. Tries IndexWithTf first, then falls back to InvertedIndex.
-pub fn load_index(path: &Path) -> Result<InvertedIndex, Box<dyn std::error::Error + Send + Sync>> {
-    let json = fs::read_to_string(path)?;
-    if let Ok(with_tf) = serde_json::from_str::<IndexWithTf>(&json) {
-        return Ok(with_tf.as_inverted());
+/// Options for ranked search. The default is TF-IDF with the default field boosts.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Ranking function.
+    pub ranker: Ranker,
+    /// Parameters used when `ranker` is `Bm25`.
+    pub bm25: Bm25Params,
+    /// TF scaling used when `ranker` is `Tfidf`.
+    pub tf_norm: TfNorm,
+    /// Required number of matching query terms.
+    pub match_mode: MatchMode,
+    /// Optional recency boost; documents without a date get no boost.
+    pub recency: Option<RecencyBoost>,
+    /// Per-field weights: `score = Σ boost(field) * field_score`.
+    pub boosts: FieldBoosts,
+    /// Return only the best this many hits (selected without a full sort).
+    pub limit: Option<usize>,
+    /// Skip this many of the best hits first (for pagination); `limit` counts after them.
+    pub offset: usize,
+    /// Treat the last bare query term as a prefix.
+    pub prefix: bool,
+    /// Expand bare terms with their synonyms, scored at `Synonyms::weight`.
+    pub synonyms: Option<Arc<Synonyms>>,
+    /// Weight of the proximity boost for multi-term queries (0 disables it); see
+    /// `proximity_factor`.
+    pub proximity: f64,
+    /// Only documents whose URL passes this filter are scored and returned.
+    pub filter: UrlFilter,
+    /// Count the matching documents by these facets (see `SearchPage::facets`).
+    pub facets: Vec<Facet>,
+    /// Keep only the best few hits per host (or other facet value). Facets
+    /// count the matches before collapsing.
+    pub collapse: Option<Collapse>,
+    /// Leave out hits scoring below this; applied after recency and proximity.
+    pub min_score: Option<MinScore>,
+    /// Order of the hits, applied to all matches before `offset` and `limit`.
+    pub sort: Sort,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            ranker: Ranker::default(),
+            bm25: Bm25Params::default(),
+            tf_norm: TfNorm::default(),
+            match_mode: MatchMode::default(),
+            recency: None,
+            boosts: FieldBoosts::default(),
+            limit: None,
+            offset: 0,
+            prefix: false,
+            synonyms: None,
+            proximity: DEFAULT_PROXIMITY_BOOST,
+            filter: UrlFilter::default(),
+            facets: Vec::new(),
+            collapse: None,
+            min_score: None,
+            sort: Sort::default(),
+        }
     }
-    let index: InvertedIndex = serde_json::from_str(&json)?;
-    Ok(index)
 }
 
-/// Load IndexWithTf from JSON file (for ranked search).
-pub fn load_index_with_tf(path: &Path) -> Result<IndexWithTf, Box<dyn std::error::Error + Send + Sync>> {
-    let json = fs::read_to_string(path)?;
-    let index = serde_json::from_str(&json)?;
-    Ok(index)
+/// Restricts search results by URL. The default allows every document.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UrlFilter {
+    /// Host the URL must be on, or a subdomain of it (`example.com` also allows
+    /// `docs.example.com`).
+    pub site: Option<String>,
+    /// The URL must start with any of these. A prefix starting with `/` is
+    /// matched against the URL's path instead (`/docs/`).
+    pub url_prefixes: Vec<String>,
+}
+
+impl UrlFilter {
+    pub fn is_empty(&self) -> bool {
+        self.site.is_none() && self.url_prefixes.is_empty()
+    }
+
+    pub fn allows(&self, url: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let parsed = url::Url::parse(url).ok();
+        // `Url` lower-cases the host of http(s) URLs.
+        let host = parsed.as_ref().and_then(|u| u.host_str()).unwrap_or("");
+        let on_site = |site: &String| {
+            let site = site.trim_end_matches('.').to_ascii_lowercase();
+            host == site || host.ends_with(&format!(".{}", site))
+        };
+        let path = parsed.as_ref().map_or("", |u| u.path());
+        let under_prefix = |prefix: &String| {
+            if prefix.starts_with('/') {
+                path.starts_with(prefix.as_str())
+            } else {
+                url.starts_with(prefix.as_str())
+            }
+        };
+        self.site.as_ref().is_none_or(on_site)
+            && (self.url_prefixes.is_empty() || self.url_prefixes.iter().any(under_prefix))
+    }
+}
+
+/// Inverse document frequency of a term with document frequency `df` among `n` documents.
+fn idf(ranker: Ranker, df: f64, n: f64) -> f64 {
+    match ranker {
+        Ranker::Tfidf | Ranker::Cosine => ((n + 1.0) / (df + 1.0)).ln() + 1.0,
+        Ranker::Bm25 => ((n - df + 0.5) / (df + 0.5) + 1.0).ln(),
+    }
+}
+
+/// Term frequency weight. `len_ratio` is document length over average length
+/// (1.0 when unknown, which makes length normalization a no-op).
+fn tf_weight(tf: u32, len_ratio: f64, options: &SearchOptions) -> f64 {
+    let tf = tf as f64;
+    match options.ranker {
+        Ranker::Tfidf => match options.tf_norm {
+            TfNorm::Raw => tf,
+            TfNorm::Length => tf / len_ratio,
+            TfNorm::Log => 1.0 + tf.ln(),
+        },
+        Ranker::Cosine => tf,
+        Ranker::Bm25 => {
+            let Bm25Params { k1, b } = options.bm25;
+            tf * (k1 + 1.0) / (tf + k1 * (1.0 - b + b * len_ratio))
+        }
+    }
+}
+
+impl IndexWithTf {
+    /// Build from crawl results with the given analyzer, which is recorded in the index. `doc_count` is the
+    /// number of unique documents, not of result entries.
+    pub fn build_with(results: &[CrawlResult], analyzer: &dyn Analyzer) -> Self {
+        Self::build_from_iter(results, analyzer)
+    }
+
+    /// Like `build_with`, consuming results one at a time so only a batch of
+    /// documents (plus the postings) is held in memory, e.g. while streaming a
+    /// JSONL dump.
+    pub fn build_from_iter<R>(results: impl IntoIterator<Item = R>, analyzer: &dyn Analyzer) -> Self
+    where
+        R: Borrow<CrawlResult> + Sync,
+    {
+        let mut index = Self {
+            analyzer: analyzer.config(),
+            ..Self::default()
+        };
+        // body hash -> first document with that body
+        let mut seen: HashMap<u64, DocId> = HashMap::new();
+        let mut pending: Vec<(DocId, R)> = Vec::with_capacity(BUILD_BATCH);
+        for item in results {
+            let result = item.borrow();
+            // The same URL can appear more than once (redirect aliases, repeated seeds):
+            // index it once, from its first entry, so neither N nor its tf is inflated.
+            if index.doc_id(&result.url).is_some() || index.aliases.contains_key(&result.url) {
+                continue;
+            }
+            let hash = body_hash(&result.body_text);
+            if let Some(&canonical) = hash.as_ref().and_then(|hash| seen.get(hash)) {
+                let canonical = index.url(canonical).to_string();
+                index.aliases.insert(result.url.clone(), canonical);
+                continue;
+            }
+            let id = index.intern(&result.url);
+            if let Some(hash) = hash {
+                seen.insert(hash, id);
+            }
+            pending.push((id, item));
+            if pending.len() >= BUILD_BATCH {
+                index.add_batch(&mut pending);
+            }
+        }
+        index.add_batch(&mut pending);
+        index.doc_count = index.url_ids.len();
+        index.refresh_avg_doc_len();
+        index.finalize();
+        index
+    }
+
+    /// Analyze a batch of interned documents and add their postings, emptying `pending`.
+    fn add_batch<R: Borrow<CrawlResult> + Sync>(&mut self, pending: &mut Vec<(DocId, R)>) {
+        // Tokenizing dominates build time and each document is independent, so
+        // analyze in parallel, then merge in document order (the same result as a
+        // serial build).
+        let analyzed: Vec<AnalyzedDoc> = pending
+            .par_iter()
+            .map(|(id, result)| {
+                let result = result.borrow();
+                let text = DocumentText {
+                    url: self.url(*id),
+                    title: &result.title,
+                    headings: &result.headings,
+                    body: &result.body_text,
+                };
+                AnalyzedDoc::new(&self.analyzer, &text)
+            })
+            .collect();
+        for ((id, result), doc) in pending.drain(..).zip(analyzed) {
+            let len = self.insert_postings(id, doc);
+            self.doc_lengths.insert(id, len);
+            self.docs.insert(id, DocMeta::from_result(result.borrow()));
+        }
+    }
+
+    /// Drop terms by document frequency (in any field): below `min_df`, or in more
+    /// than `max_df_ratio` of all documents. Terms exactly at either bound are kept.
+    /// Document counts and lengths are unchanged.
+    pub fn prune(&mut self, options: &PruneOptions) -> PruneStats {
+        let n = self.doc_count as f64;
+        let mut df: HashMap<&str, HashSet<DocId>> = HashMap::new();
+        for postings in self.all_postings() {
+            for (term, docs) in postings {
+                df.entry(term.as_str()).or_default().extend(docs.keys().copied());
+            }
+        }
+        let mut stats = PruneStats::default();
+        let mut pruned: HashSet<String> = HashSet::new();
+        for (term, docs) in df {
+            let df = docs.len();
+            if df < options.min_df {
+                stats.rare += 1;
+            } else if options.max_df_ratio.is_some_and(|ratio| df as f64 > ratio * n) {
+                stats.common += 1;
+            } else {
+                continue;
+            }
+            pruned.insert(term.to_string());
+        }
+        self.term_tf.retain(|term, _| !pruned.contains(term));
+        for postings in self.fields.values_mut() {
+            postings.retain(|term, _| !pruned.contains(term));
+        }
+        self.positions.retain(|term, _| !pruned.contains(term));
+        self.finalize();
+        stats
+    }
+
+    /// Build from a legacy InvertedIndex (every posting gets tf = 1).
+    pub fn from_inverted(simple: InvertedIndex) -> Self {
+        let mut index = Self::default();
+        for (term, urls) in simple {
+            let postings = urls.iter().map(|url| (index.intern(url), 1)).collect();
+            index.term_tf.insert(term, postings);
+        }
+        index.doc_count = index.url_ids.len();
+        index.add_url_postings();
+        index.finalize();
+        index
+    }
+
+    /// A small index holding just what `query` needs from `reader`: the postings of
+    /// every query term (with synonyms, prefix completions and exclusions) and the
+    /// documents they reference. Document count, average length and document norms are
+    /// the full index's, so scores equal those of searching the whole index.
+    pub fn for_query(reader: &impl IndexReader, query: &str, options: &SearchOptions) -> ReadResult<Self> {
+        let mut index = Self {
+            analyzer: reader.analyzer().clone(),
+            doc_count: reader.doc_count(),
+            avg_doc_len: reader.avg_doc_len(),
+            ..Self::default()
+        };
+        let parsed = ParsedQuery::parse_with(query, &index.analyzer);
+        let mut terms: Vec<String> = parsed
+            .terms
+            .iter()
+            .chain(parsed.phrases.iter().flatten())
+            .chain(&parsed.excluded)
+            .chain(parsed.scoped.iter().chain(&parsed.excluded_scoped).flat_map(|scoped| &scoped.tokens))
+            .cloned()
+            .chain(parsed.boolean.iter().flat_map(Query::words))
+            .collect();
+        if let Some(synonyms) = &options.synonyms {
+            for term in &parsed.terms {
+                terms.extend(synonyms.expand(term).iter().cloned());
+            }
+        }
+        if options.prefix {
+            if let Some(last) = parsed.distinct_terms().last() {
+                terms.extend(reader.prefix_terms(last)?);
+            }
+        }
+        terms.sort();
+        terms.dedup();
+        // reader doc id -> id in this index
+        let mut ids: HashMap<DocId, DocId> = HashMap::new();
+        for term in terms {
+            for posting in reader.postings(&term)? {
+                let id = index.load_document(reader, posting.doc, &mut ids)?;
+                index.term_tf.entry(term.clone()).or_default().insert(id, posting.tf);
+                if !posting.positions.is_empty() {
+                    index.positions.entry(term.clone()).or_default().insert(id, posting.positions);
+                }
+            }
+            for field in Field::ALL {
+                for (doc, tf) in reader.field_postings(field, &term)? {
+                    let id = index.load_document(reader, doc, &mut ids)?;
+                    let postings = index.fields.entry(field).or_default();
+                    postings.entry(term.clone()).or_default().insert(id, tf);
+                }
+            }
+        }
+        // finalize only sees the loaded terms; keep the norms over every term.
+        let norms = std::mem::take(&mut index.doc_norms);
+        index.finalize();
+        index.doc_norms = norms;
+        Ok(index)
+    }
+
+    /// Copy document `doc` of `reader` into this index (once), returning its local ID.
+    fn load_document(
+        &mut self,
+        reader: &impl IndexReader,
+        doc: DocId,
+        ids: &mut HashMap<DocId, DocId>,
+    ) -> ReadResult<DocId> {
+        if let Some(&id) = ids.get(&doc) {
+            return Ok(id);
+        }
+        let stored = reader
+            .document(doc)?
+            .ok_or_else(|| format!("posting references missing document {}", doc))?;
+        let id = self.intern(&stored.url);
+        if let Some(length) = stored.length {
+            self.doc_lengths.insert(id, length);
+        }
+        if let Some(meta) = stored.meta {
+            self.docs.insert(id, meta);
+        }
+        if let Some(norm) = stored.norm {
+            self.doc_norms.insert(id, norm);
+        }
+        ids.insert(doc, id);
+        Ok(id)
+    }
+
+    /// Rebuild the URL -> ID lookup after deserialization.
+    fn rebuild_url_ids(&mut self) {
+        self.url_ids = self
+            .urls
+            .iter()
+            .enumerate()
+            .filter(|(_, url)| !url.is_empty())
+            .map(|(id, url)| (url.clone(), id as DocId))
+            .collect();
+    }
+
+    /// ID of an indexed URL.
+    pub fn doc_id(&self, url: &str) -> Option<DocId> {
+        self.url_ids.get(url).copied()
+    }
+
+    /// The indexed URL for `url`: itself, or the document it duplicates.
+    pub fn canonical_url<'a>(&'a self, url: &'a str) -> &'a str {
+        self.aliases.get(url).map_or(url, String::as_str)
+    }
+
+    /// URL of a document ID.
+    pub fn url(&self, id: DocId) -> &str {
+        &self.urls[id as usize]
+    }
+
+    /// ID for `url`, allocating a new one if the URL is not in the table yet.
+    fn intern(&mut self, url: &str) -> DocId {
+        if let Some(&id) = self.url_ids.get(url) {
+            return id;
+        }
+        let id = self.urls.len() as DocId;
+        self.urls.push(url.to_string());
+        self.url_ids.insert(url.to_string(), id);
+        id
+    }
+
+    /// Add (or replace) a single document. Re-adding a URL first subtracts its
+    /// previous postings, so the document is counted once; the description and
+    /// last-modified time it was crawled with are kept, since the text has neither.
+    #[cfg(test)]
+    pub fn add_document(&mut self, url: &str, title: &str, headings: &str, body: &str) {
+        self.put_document(url, title, headings, body);
+        self.refresh_avg_doc_len();
+        self.finalize();
+    }
+
+    /// Add (or replace) several documents, refreshing the query caches once at the end.
+    pub fn add_documents(&mut self, docs: &[DocumentText]) {
+        for doc in docs {
+            self.put_document(doc.url, doc.title, doc.headings, doc.body);
+        }
+        self.refresh_avg_doc_len();
+        self.finalize();
+    }
+
+    /// `add_document` without refreshing `avg_doc_len` and the query caches.
+    fn put_document(&mut self, url: &str, title: &str, headings: &str, body: &str) {
+        self.aliases.remove(url);
+        let existed = self.doc_id(url).is_some();
+        let id = self.intern(url);
+        self.remove_postings(id);
+        let len = self.add_postings(id, title, headings, body);
+        self.doc_lengths.insert(id, len);
+        let previous = self.docs.remove(&id).unwrap_or_default();
+        let meta = DocMeta {
+            title: title.to_string(),
+            text: body.chars().take(STORED_TEXT_CHARS).collect(),
+            ..previous
+        };
+        self.docs.insert(id, meta);
+        if !existed {
+            self.doc_count += 1;
+        }
+    }
+
+    /// Remove a document and all of its postings and metadata.
+    /// Returns false (leaving the index untouched) if the URL is not indexed.
+    /// The ID is retired, not reused.
+    pub fn remove_document(&mut self, url: &str) -> bool {
+        if !self.detach_document(url) {
+            return false;
+        }
+        self.refresh_avg_doc_len();
+        self.finalize();
+        true
+    }
+
+    /// Remove every document whose URL starts with `prefix`, returning how many
+    /// were removed. Caches are refreshed once at the end, not per document.
+    pub fn remove_by_prefix(&mut self, prefix: &str) -> usize {
+        let urls: Vec<String> = self.url_ids.keys().filter(|url| url.starts_with(prefix)).cloned().collect();
+        let removed = urls.iter().filter(|url| self.detach_document(url)).count();
+        if removed > 0 {
+            self.refresh_avg_doc_len();
+            self.finalize();
+        }
+        removed
+    }
+
+    /// `remove_document` without refreshing `avg_doc_len` and the query caches.
+    fn detach_document(&mut self, url: &str) -> bool {
+        let Some(id) = self.url_ids.remove(url) else {
+            return false;
+        };
+        self.urls[id as usize].clear();
+        self.remove_postings(id);
+        self.docs.remove(&id);
+        self.doc_lengths.remove(&id);
+        self.aliases.retain(|_, canonical| canonical.as_str() != url);
+        self.doc_count = self.doc_count.saturating_sub(1);
+        true
+    }
+
+    /// Merge another index into this one. A URL present in both is treated as one
+    /// document: `other`'s version replaces this one's. `doc_count` becomes the
+    /// number of unique URLs. An empty index adopts `other`'s analyzer.
+    pub fn merge(&mut self, other: IndexWithTf) {
+        if self.url_ids.is_empty() {
+            self.analyzer = other.analyzer.clone();
+        }
+        // Map other's IDs into this index's ID space.
+        let mut id_map: HashMap<DocId, DocId> = HashMap::new();
+        for (other_id, url) in other.urls.iter().enumerate() {
+            if url.is_empty() {
+                continue;
+            }
+            let id = self.intern(url);
+            self.remove_postings(id);
+            self.docs.remove(&id);
+            self.doc_lengths.remove(&id);
+            id_map.insert(other_id as DocId, id);
+        }
+        self.aliases.extend(other.aliases);
+        self.aliases.retain(|alias, _| !self.url_ids.contains_key(alias));
+        fn remap<V>(id_map: &HashMap<DocId, DocId>, map: HashMap<DocId, V>) -> Vec<(DocId, V)> {
+            map.into_iter().map(|(id, v)| (id_map[&id], v)).collect()
+        }
+        for (term, docs) in other.term_tf {
+            self.term_tf.entry(term).or_default().extend(remap(&id_map, docs));
+        }
+        for (field, postings) in other.fields {
+            let ours = self.fields.entry(field).or_default();
+            for (term, docs) in postings {
+                ours.entry(term).or_default().extend(remap(&id_map, docs));
+            }
+        }
+        for (term, docs) in other.positions {
+            self.positions.entry(term).or_default().extend(remap(&id_map, docs));
+        }
+        self.docs.extend(remap(&id_map, other.docs));
+        self.doc_lengths.extend(remap(&id_map, other.doc_lengths));
+        self.doc_count = self.url_ids.len();
+        self.refresh_avg_doc_len();
+        self.finalize();
+    }
+
+    /// Median body length in tokens (`None` without stored lengths).
+    pub fn median_doc_len(&self) -> Option<f64> {
+        let mut lengths: Vec<u32> = self.doc_lengths.values().copied().collect();
+        lengths.sort_unstable();
+        let mid = lengths.len() / 2;
+        match lengths.len() {
+            0 => None,
+            n if n % 2 == 1 => Some(lengths[mid] as f64),
+            _ => Some((lengths[mid - 1] as f64 + lengths[mid] as f64) / 2.0),
+        }
+    }
+
+    /// Fill in missing document lengths (indexes from older builds lack them) with
+    /// the sum of each document's body term frequencies. This undercounts terms
+    /// removed by pruning, but is close enough for BM25 and length normalization.
+    /// Returns false if nothing was missing.
+    fn approximate_doc_lengths(&mut self) -> bool {
+        if self.doc_count == 0 || !self.doc_lengths.is_empty() {
+            return false;
+        }
+        for docs in self.term_tf.values() {
+            for (&id, &tf) in docs {
+                *self.doc_lengths.entry(id).or_insert(0) += tf;
+            }
+        }
+        for &id in self.docs.keys() {
+            self.doc_lengths.entry(id).or_insert(0);
+        }
+        self.refresh_avg_doc_len();
+        true
+    }
+
+    /// Check the index's invariants, returning every violation found (empty if valid).
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let live = |id: DocId| self.urls.get(id as usize).is_some_and(|url| !url.is_empty());
+        let mut seen = HashSet::new();
+        for url in self.urls.iter().filter(|url| !url.is_empty()) {
+            if !seen.insert(url) {
+                errors.push(ValidationError::DuplicateUrl { url: url.clone() });
+            }
+            if let Err(e) = url::Url::parse(url) {
+                errors.push(ValidationError::InvalidUrl {
+                    url: url.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+        if self.doc_count != seen.len() {
+            errors.push(ValidationError::DocCount {
+                doc_count: self.doc_count,
+                documents: seen.len(),
+            });
+        }
+        let fields = self.fields.iter().map(|(field, postings)| (field.name(), postings));
+        for (map, postings) in std::iter::once(("term_tf", &self.term_tf)).chain(fields) {
+            for (term, docs) in postings {
+                if term.is_empty() {
+                    errors.push(ValidationError::EmptyTerm);
+                }
+                for (&doc, &tf) in docs {
+                    if !live(doc) {
+                        errors.push(ValidationError::UnknownDocument { map, doc });
+                    } else if tf == 0 {
+                        errors.push(ValidationError::ZeroTf { term: term.clone(), doc });
+                    }
+                }
+            }
+        }
+        let unknown = |map: &'static str, docs: &mut dyn Iterator<Item = DocId>| {
+            docs.filter(|&doc| !live(doc))
+                .map(|doc| ValidationError::UnknownDocument { map, doc })
+                .collect::<Vec<_>>()
+        };
+        errors.extend(unknown("positions", &mut self.positions.values().flat_map(|m| m.keys().copied())));
+        errors.extend(unknown("docs", &mut self.docs.keys().copied()));
+        errors.extend(unknown("doc_lengths", &mut self.doc_lengths.keys().copied()));
+        errors
+    }
+
+    /// Every body term with its document frequency and summed term frequency,
+    /// by document frequency descending, then term.
+    pub fn term_frequencies(&self) -> Vec<(&str, usize, u64)> {
+        let mut terms: Vec<(&str, usize, u64)> = self
+            .term_tf
+            .iter()
+            .map(|(term, docs)| (term.as_str(), docs.len(), docs.values().map(|&tf| u64::from(tf)).sum()))
+            .collect();
+        terms.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        terms
+    }
+
+    /// Summary statistics. `file_size` is left for the caller to fill in.
+    pub fn stats(&self) -> IndexStats {
+        let df = self.term_tf.iter().map(|(term, docs)| (term.as_str(), docs.len()));
+        IndexStats {
+            documents: self.doc_count,
+            vocabulary: self.term_tf.len(),
+            total_postings: self.term_tf.values().map(|docs| docs.len()).sum(),
+            total_tokens: Some(self.term_tf.values().flat_map(|docs| docs.values()).map(|&tf| u64::from(tf)).sum()),
+            avg_doc_len: (!self.doc_lengths.is_empty()).then_some(self.avg_doc_len),
+            median_doc_len: self.median_doc_len(),
+            top_terms: IndexStats::top_terms(df),
+            file_size: None,
+            stopwords: Some(self.analyzer.standard().stopwords.to_string()),
+        }
+    }
+
+    /// Tokenize a document and add its postings in every field. Returns the body token count.
+    fn add_postings(&mut self, id: DocId, title: &str, headings: &str, body: &str) -> u32 {
+        let text = DocumentText {
+            url: self.url(id),
+            title,
+            headings,
+            body,
+        };
+        let doc = AnalyzedDoc::new(&self.analyzer, &text);
+        self.insert_postings(id, doc)
+    }
+
+    /// Add the postings of an analyzed document. Returns the body token count.
+    fn insert_postings(&mut self, id: DocId, doc: AnalyzedDoc) -> u32 {
+        self.rejected += doc.rejected;
+        for (field, words) in doc.fields {
+            self.insert_field_words(field, id, words);
+        }
+        let len = doc.body.len() as u32;
+        for (word, pos) in doc.body {
+            *self.term_tf.entry(word.clone()).or_default().entry(id).or_insert(0) += 1;
+            self.positions
+                .entry(word)
+                .or_default()
+                .entry(id)
+                .or_default()
+                .push(pos);
+        }
+        len
+    }
+
+    fn add_field_postings(&mut self, field: Field, id: DocId, text: &str) {
+        let words = self.analyzer.analyze_counting(text, &mut self.rejected);
+        self.insert_field_words(field, id, words);
+    }
+
+    fn insert_field_words(&mut self, field: Field, id: DocId, words: Vec<String>) {
+        if words.is_empty() {
+            return;
+        }
+        let postings = self.fields.entry(field).or_default();
+        for word in words {
+            *postings.entry(word).or_default().entry(id).or_insert(0) += 1;
+        }
+    }
+
+    /// Index the URL path of every document (for indexes written before the URL field).
+    fn add_url_postings(&mut self) {
+        for id in 0..self.urls.len() as DocId {
+            if !self.url(id).is_empty() {
+                let text = url_path_text(self.url(id));
+                self.add_field_postings(Field::Url, id, &text);
+            }
+        }
+    }
+
+    /// Postings of a field other than the body.
+    pub fn field(&self, field: Field) -> Option<&Postings> {
+        self.fields.get(&field)
+    }
+
+    /// Body postings followed by the postings of every other field.
+    fn all_postings(&self) -> impl Iterator<Item = &Postings> {
+        std::iter::once(&self.term_tf).chain(self.fields.values())
+    }
+
+    /// Remove every posting of a document, dropping terms left without postings.
+    /// Returns whether any posting was removed. Counts and metadata are untouched.
+    fn remove_postings(&mut self, id: DocId) -> bool {
+        let mut removed = false;
+        for postings in std::iter::once(&mut self.term_tf).chain(self.fields.values_mut()) {
+            postings.retain(|_, docs| {
+                removed |= docs.remove(&id).is_some();
+                !docs.is_empty()
+            });
+        }
+        self.positions.retain(|_, docs| {
+            docs.remove(&id);
+            !docs.is_empty()
+        });
+        removed
+    }
+
+    /// Recompute `avg_doc_len` from `doc_lengths`.
+    fn refresh_avg_doc_len(&mut self) {
+        self.avg_doc_len = if self.doc_lengths.is_empty() {
+            0.0
+        } else {
+            self.doc_lengths.values().map(|&l| l as f64).sum::<f64>() / self.doc_lengths.len() as f64
+        };
+    }
+
+    /// Documents where `phrase` occurs with its terms at consecutive positions.
+    /// Indexes without positions fall back to requiring all phrase terms.
+    fn phrase_matches(&self, phrase: &[String]) -> HashSet<DocId> {
+        let Some(first) = self.term_tf.get(&phrase[0]) else {
+            return HashSet::new();
+        };
+        let mut candidates: HashSet<DocId> = first.keys().copied().collect();
+        for word in &phrase[1..] {
+            match self.term_tf.get(word) {
+                Some(m) => candidates.retain(|id| m.contains_key(id)),
+                None => return HashSet::new(),
+            }
+        }
+        if self.positions.is_empty() || phrase.len() == 1 {
+            return candidates;
+        }
+        candidates.retain(|id| {
+            let Some(lists) = phrase
+                .iter()
+                .map(|w| self.positions.get(w).and_then(|m| m.get(id)))
+                .collect::<Option<Vec<&Vec<u32>>>>()
+            else {
+                return false;
+            };
+            lists[0].iter().any(|&start| {
+                lists[1..]
+                    .iter()
+                    .enumerate()
+                    .all(|(i, list)| list.binary_search(&(start + i as u32 + 1)).is_ok())
+            })
+        });
+        candidates
+    }
+
+    /// Document length relative to the average. Without stored lengths (old index
+    /// files), every document counts as average.
+    fn len_ratio(&self, id: DocId) -> f64 {
+        match self.doc_lengths.get(&id) {
+            Some(&len) if self.avg_doc_len > 0.0 => len as f64 / self.avg_doc_len,
+            _ => 1.0,
+        }
+    }
+
+    /// Score contribution of one body term in one document.
+    fn term_score(&self, id: DocId, tf: u32, idf: f64, options: &SearchOptions) -> f64 {
+        idf * tf_weight(tf, self.len_ratio(id), options)
+    }
+
+    /// Documents containing any of `terms` in any field.
+    fn docs_containing(&self, terms: &[String]) -> HashSet<DocId> {
+        let mut docs = HashSet::new();
+        for term in terms {
+            for postings in self.all_postings() {
+                if let Some(m) = postings.get(term) {
+                    docs.extend(m.keys().copied());
+                }
+            }
+        }
+        docs
+    }
+
+    /// Documents matching a field clause (which has at least one token). Synonyms
+    /// don't apply: the clause asks for these words in this field.
+    fn scoped_matches(&self, scoped: &Scoped) -> HashSet<DocId> {
+        let postings = match scoped.scope {
+            Scope::Body if scoped.phrase => return self.phrase_matches(&scoped.tokens),
+            Scope::Body => Some(&self.term_tf),
+            Scope::Field(field) => self.field(field),
+        };
+        let mut words = scoped.tokens.iter();
+        let Some((postings, first)) = postings.zip(words.next()) else {
+            return HashSet::new();
+        };
+        let mut docs: HashSet<DocId> = postings.get(first).map(|m| m.keys().copied().collect()).unwrap_or_default();
+        for word in words {
+            match postings.get(word) {
+                Some(m) => docs.retain(|id| m.contains_key(id)),
+                None => return HashSet::new(),
+            }
+        }
+        docs
+    }
+
+    /// Documents matching a boolean query. A word matches in any field, directly or
+    /// through a synonym; a stopword matches every document.
+    fn boolean_matches(&self, tree: &Query, options: &SearchOptions) -> DocSet {
+        match tree {
+            Query::Term(tokens) => tokens.iter().fold(DocSet::all(), |docs, token| {
+                let mut alternatives = vec![token.clone()];
+                if let Some(synonyms) = &options.synonyms {
+                    alternatives.extend(synonyms.expand(token).iter().cloned());
+                }
+                docs.and(DocSet::Only(self.docs_containing(&alternatives)))
+            }),
+            Query::Phrase(tokens) if tokens.is_empty() => DocSet::all(),
+            Query::Phrase(tokens) => DocSet::Only(self.phrase_matches(tokens)),
+            Query::Scoped(scoped) if scoped.tokens.is_empty() => DocSet::all(),
+            Query::Scoped(scoped) => DocSet::Only(self.scoped_matches(scoped)),
+            Query::And(clauses) => clauses
+                .iter()
+                .fold(DocSet::all(), |docs, clause| docs.and(self.boolean_matches(clause, options))),
+            Query::Or(clauses) => clauses.iter().fold(DocSet::Only(HashSet::new()), |docs, clause| {
+                docs.or(self.boolean_matches(clause, options))
+            }),
+            Query::Not(inner) => self.boolean_matches(inner, options).not(),
+        }
+    }
+
+    /// Bare query terms as groups of alternatives: each term with its synonyms, and
+    /// with `options.prefix`, the last term as its most frequent vocabulary completions.
+    fn term_groups(&self, parsed: &ParsedQuery, options: &SearchOptions) -> Vec<TermGroup> {
+        let mut terms = parsed.distinct_terms();
+        let prefix = if options.prefix { terms.pop() } else { None };
+        let mut groups: Vec<TermGroup> = terms
+            .into_iter()
+            .map(|t| {
+                let mut group = vec![(t.clone(), 1.0)];
+                if let Some(synonyms) = &options.synonyms {
+                    group.extend(synonyms.expand(t).iter().map(|s| (s.clone(), synonyms.weight)));
+                }
+                group
+            })
+            .collect();
+        if let Some(prefix) = prefix {
+            let completions = self.complete_prefix(prefix);
+            if completions.is_empty() {
+                groups.push(vec![(prefix.clone(), 1.0)]);
+            } else {
+                groups.push(completions.into_iter().map(|t| (t, 1.0)).collect());
+            }
+        }
+        groups
+    }
+
+    /// Up to `MAX_PREFIX_EXPANSIONS` vocabulary terms starting with `prefix`, most
+    /// frequent first. Uses the sorted vocabulary built by `finalize`.
+    pub fn complete_prefix(&self, prefix: &str) -> Vec<String> {
+        let start = self.vocabulary.partition_point(|t| t.as_str() < prefix);
+        let mut matches: Vec<(&String, usize)> = self.vocabulary[start..]
+            .iter()
+            .take_while(|t| t.starts_with(prefix))
+            .map(|t| (t, self.max_df(t)))
+            .collect();
+        matches.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        matches
+            .into_iter()
+            .take(MAX_PREFIX_EXPANSIONS)
+            .map(|(t, _)| t.clone())
+            .collect()
+    }
+
+    /// For each document, how many of the term groups it matches (in any field).
+    fn matched_term_counts(&self, groups: &[TermGroup]) -> HashMap<DocId, usize> {
+        let mut counts: HashMap<DocId, usize> = HashMap::new();
+        for group in groups {
+            let words: Vec<String> = group.iter().map(|(word, _)| word.clone()).collect();
+            for id in self.docs_containing(&words) {
+                *counts.entry(id).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Recompute the cached per-term IDF values, the sorted vocabulary and document
+    /// vector norms. Runs after loading and after every change to the documents;
+    /// queries then only look them up.
+    pub fn finalize(&mut self) {
+        let n = self.doc_count as f64;
+        let cache = |postings: &Postings| -> HashMap<String, TermIdf> {
+            postings
+                .iter()
+                .map(|(term, docs)| (term.clone(), TermIdf::new(docs.len(), n)))
+                .collect()
+        };
+        self.idf = cache(&self.term_tf);
+        self.field_idf = self.fields.iter().map(|(&field, postings)| (field, cache(postings))).collect();
+        let mut vocabulary: Vec<String> = self.all_postings().flat_map(|postings| postings.keys()).cloned().collect();
+        vocabulary.sort();
+        vocabulary.dedup();
+        self.vocabulary = vocabulary;
+        let mut squares: HashMap<DocId, f64> = HashMap::new();
+        for (term, docs) in &self.term_tf {
+            let idf = self.idf[term].tfidf;
+            for (&id, &tf) in docs {
+                *squares.entry(id).or_insert(0.0) += (tf as f64 * idf).powi(2);
+            }
+        }
+        self.doc_norms = squares.into_iter().map(|(id, sum)| (id, sum.sqrt())).collect();
+    }
+
+    /// Largest document frequency of a term across fields (orders prefix completions).
+    pub fn max_df(&self, term: &str) -> usize {
+        self.all_postings()
+            .map(|postings| postings.get(term).map_or(0, |docs| docs.len()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Tokens the token filter dropped while documents were added to this index
+    /// (since it was built or loaded).
+    pub fn rejected_tokens(&self) -> TokenRejections {
+        self.rejected
+    }
+
+    /// Euclidean norm of a document's body TF-IDF vector (for cosine ranking).
+    pub fn doc_norm(&self, id: DocId) -> Option<f64> {
+        self.doc_norms.get(&id).copied()
+    }
+
+    /// IDF of a term from `cache`, computed directly if the index wasn't finalized.
+    fn cached_idf(&self, cache: &HashMap<String, TermIdf>, term: &str, df: usize, ranker: Ranker) -> f64 {
+        match cache.get(term) {
+            Some(idf) => idf.get(ranker),
+            None => idf(ranker, df as f64, self.doc_count as f64),
+        }
+    }
+
+    /// As simple InvertedIndex (word -> set of URLs) for backward compat.
+    pub fn as_inverted(&self) -> InvertedIndex {
+        self.term_tf
+            .iter()
+            .map(|(k, v)| (k.clone(), v.keys().map(|&id| self.url(id).to_string()).collect()))
+            .collect()
+    }
+
+    /// Search with TF-IDF ranking. Returns (url, score) sorted by score descending;
+    /// equal scores are ordered by URL ascending.
+    #[cfg(test)]
+    pub fn search_ranked(&self, query: &str) -> Vec<(String, f64)> {
+        self.search_ranked_with(query, &SearchOptions::default())
+    }
+
+    /// Like `search_ranked_with`, with each hit's document metadata attached and
+    /// the total number of matches (for pagination).
+    pub fn search_page(&self, query: &str, options: &SearchOptions) -> SearchPage<'_> {
+        let page = self.rank_page(&ParsedQuery::parse_with(query, &self.analyzer), options);
+        let hits = page
+            .ranked
+            .into_iter()
+            .map(|(id, score)| RankedHit {
+                url: self.url(id).to_string(),
+                score,
+                meta: self.docs.get(&id),
+                collapsed: page.collapsed.get(&id).copied().unwrap_or(0),
+            })
+            .collect();
+        SearchPage {
+            total: page.total,
+            hits,
+            facets: page.facets,
+        }
+    }
+
+    /// Search a query that matched nothing again, relaxing it step by step until
+    /// something matches: any term instead of all, then without its rarest word,
+    /// then (with `fuzzy`) with unknown words corrected. Each step is one more
+    /// `search_page`, so at most three; steps that change nothing are skipped.
+    /// None if no step finds anything.
+    pub fn search_relaxed(&self, query: &str, options: &SearchOptions, fuzzy: bool) -> Option<RelaxedSearch<'_>> {
+        let mut query = query.to_string();
+        let mut options = options.clone();
+        let mut steps = Vec::new();
+        let words = query.split_whitespace().count();
+        for step in [RelaxStep::AnyTerm, RelaxStep::DropTerm, RelaxStep::Fuzzy] {
+            let relaxed = match step {
+                RelaxStep::AnyTerm if options.match_mode != MatchMode::Any => {
+                    options.match_mode = MatchMode::Any;
+                    true
+                }
+                RelaxStep::DropTerm if words <= RELAX_MAX_WORDS => match self.without_rarest_word(&query) {
+                    Some(shorter) => {
+                        query = shorter;
+                        true
+                    }
+                    None => false,
+                },
+                RelaxStep::Fuzzy if fuzzy && words <= RELAX_MAX_WORDS => match self.corrected_query(&query) {
+                    Some(corrected) => {
+                        query = corrected;
+                        true
+                    }
+                    None => false,
+                },
+                _ => false,
+            };
+            if !relaxed {
+                continue;
+            }
+            steps.push(Relaxation {
+                step,
+                query: query.clone(),
+            });
+            let page = self.search_page(&query, &options);
+            if page.total > 0 {
+                return Some(RelaxedSearch {
+                    page,
+                    steps,
+                    query,
+                    options,
+                });
+            }
+        }
+        None
+    }
+
+    /// `query` without the plain word (not negated, quoted or grouped) found in
+    /// the fewest documents, the last of equally rare ones. None unless at least
+    /// two such words are left, or if the result doesn't parse.
+    fn without_rarest_word(&self, query: &str) -> Option<String> {
+        let chunks: Vec<&str> = query.split_whitespace().collect();
+        let rarest = chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| !chunk.starts_with(['-', '(', '"']) && !chunk.ends_with([')', '"']))
+            .filter_map(|(i, chunk)| {
+                let (start, end) = query_word(chunk)?;
+                let [token]: [String; 1] = self.analyzer.analyze(&chunk[start..end]).try_into().ok()?;
+                Some((self.max_df(&token), i))
+            })
+            .collect::<Vec<_>>();
+        if rarest.len() < 2 {
+            return None;
+        }
+        let (_, drop) = rarest.into_iter().min_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))?;
+        let shorter = chunks
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != drop)
+            .map(|(_, chunk)| *chunk)
+            .collect::<Vec<_>>()
+            .join(" ");
+        ParsedQuery::try_parse_with(&shorter, &self.analyzer).is_ok().then_some(shorter)
+    }
+
+    /// "Did you mean": `query` with each word missing from the vocabulary replaced
+    /// by the closest term that is in it (see `spell::closest`), if that query has
+    /// more matches than `total`, the count for `query` itself. Operators, quotes
+    /// and field prefixes are kept. Words are compared after analysis, so with
+    /// stemming a correction is a stem.
+    pub fn suggest_query(&self, query: &str, options: &SearchOptions, total: usize) -> Option<String> {
+        let suggestion = self.corrected_query(query)?;
+        let count_only = SearchOptions {
+            limit: Some(0),
+            offset: 0,
+            facets: Vec::new(),
+            ..options.clone()
+        };
+        (self.search_page(&suggestion, &count_only).total > total).then_some(suggestion)
+    }
+
+    /// `query` with every unknown word corrected, or None if there's nothing to correct.
+    fn corrected_query(&self, query: &str) -> Option<String> {
+        let mut corrected = false;
+        let words: Vec<String> = query
+            .split_whitespace()
+            .map(|chunk| match self.correct_word(chunk) {
+                Some(word) => {
+                    corrected = true;
+                    word
+                }
+                None => chunk.to_string(),
+            })
+            .collect();
+        corrected.then(|| words.join(" "))
+    }
+
+    /// A whitespace-separated chunk of a query with its word corrected, or None
+    /// if the word is known, an operator or not a single token.
+    fn correct_word(&self, chunk: &str) -> Option<String> {
+        let (start, end) = query_word(chunk)?;
+        let word = &chunk[start..end];
+        let [token]: [String; 1] = self.analyzer.analyze(word).try_into().ok()?;
+        if self.max_df(&token) > 0 {
+            return None;
+        }
+        let correction = spell::closest(&token, &self.vocabulary, |term| self.max_df(term))?;
+        Some(format!("{}{}{}", &chunk[..start], correction, &chunk[end..]))
+    }
+
+    /// Documents most similar to the indexed `url`: its `MLT_QUERY_TERMS` highest
+    /// TF-IDF body terms, run as a query (any term matches) with `options`, without
+    /// the document itself. None if `url` isn't indexed.
+    pub fn more_like_this(&self, url: &str, top_k: usize, options: &SearchOptions) -> Option<Vec<RankedHit<'_>>> {
+        let source = self.doc_id(self.canonical_url(url))?;
+        // Terms are already analyzed, so build the query directly instead of parsing text.
+        let parsed = ParsedQuery {
+            terms: self
+                .top_document_terms(source, MLT_QUERY_TERMS)
+                .into_iter()
+                .map(|(term, _, _)| term.to_string())
+                .collect(),
+            ..ParsedQuery::default()
+        };
+        let options = SearchOptions {
+            match_mode: MatchMode::Any,
+            prefix: false,
+            proximity: 0.0,
+            limit: Some(top_k.saturating_add(1)),
+            offset: 0,
+            ..options.clone()
+        };
+        let hits = self
+            .rank_parsed(&parsed, &options)
+            .into_iter()
+            .filter(|&(id, _)| id != source)
+            .take(top_k)
+            .map(|(id, score)| RankedHit {
+                url: self.url(id).to_string(),
+                score,
+                meta: self.docs.get(&id),
+                collapsed: 0,
+            })
+            .collect();
+        Some(hits)
+    }
+
+    /// The `limit` body terms of document `id` with the highest TF-IDF, best first,
+    /// as (term, tf, tf × idf). Postings are kept by term, so this scans the whole
+    /// vocabulary.
+    pub fn top_document_terms(&self, id: DocId, limit: usize) -> Vec<(&str, u32, f64)> {
+        let mut weighted: Vec<(&str, u32, f64)> = self
+            .term_tf
+            .iter()
+            .filter_map(|(term, docs)| {
+                let tf = *docs.get(&id)?;
+                let idf = self.cached_idf(&self.idf, term, docs.len(), Ranker::Tfidf);
+                Some((term.as_str(), tf, tf as f64 * idf))
+            })
+            .collect();
+        weighted.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+        weighted.truncate(limit);
+        weighted
+    }
+
+    /// Terms that most often share documents with `term` (analyzed like a query
+    /// word), best first. Score: number of shared documents × IDF of the other
+    /// term, so rarer companions rank above generic ones; terms in more than
+    /// `RELATED_MAX_DF_RATIO` of all documents are skipped. Large posting lists are
+    /// sampled (the `RELATED_SAMPLE_DOCS` lowest document IDs).
+    pub fn related_terms(&self, term: &str, limit: usize) -> Vec<(String, f64)> {
+        let Some(term) = self.analyzer.analyze(term).into_iter().next() else {
+            return Vec::new();
+        };
+        let Some(docs) = self.term_tf.get(&term) else {
+            return Vec::new();
+        };
+        let mut sample: Vec<DocId> = docs.keys().copied().collect();
+        sample.sort_unstable();
+        sample.truncate(RELATED_SAMPLE_DOCS);
+        let sample: HashSet<DocId> = sample.into_iter().collect();
+        let max_df = RELATED_MAX_DF_RATIO * self.doc_count as f64;
+        let mut related: Vec<(String, f64)> = self
+            .term_tf
+            .iter()
+            .filter(|(other, postings)| **other != term && postings.len() as f64 <= max_df)
+            .filter_map(|(other, postings)| {
+                let shared = postings.keys().filter(|id| sample.contains(id)).count();
+                if shared == 0 {
+                    return None;
+                }
+                let idf = self.cached_idf(&self.idf, other, postings.len(), Ranker::Tfidf);
+                Some((other.clone(), shared as f64 * idf))
+            })
+            .collect();
+        related.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        related.truncate(limit);
+        related
+    }
+
+    /// Search with the ranker and options given: (url, score) sorted by score
+    /// descending. The server pages with `search_page` instead.
+    #[cfg(test)]
+    pub fn search_ranked_with(&self, query: &str, options: &SearchOptions) -> Vec<(String, f64)> {
+        self.rank_parsed(&ParsedQuery::parse_with(query, &self.analyzer), options)
+            .into_iter()
+            .map(|(id, score)| (self.url(id).to_string(), score))
+            .collect()
+    }
+
+    /// Run the scoring loops of `parsed`, passing every per-term contribution to `add`.
+    /// Both ranking and `explain` go through here, so explanations can't drift from
+    /// real scores. Returns the filters a document must pass, or None for an empty query.
+    fn score_query(
+        &self,
+        parsed: &ParsedQuery,
+        options: &SearchOptions,
+        mut add: impl FnMut(DocId, TermScore<'_>),
+    ) -> Option<QueryMatch> {
+        if parsed.is_empty() || self.doc_count == 0 {
+            return None;
+        }
+        // Boolean queries say exactly what must match: no prefix completion, and
+        // `match_mode` doesn't apply.
+        let boolean_options;
+        let options = match &parsed.boolean {
+            Some(_) => {
+                boolean_options = SearchOptions {
+                    prefix: false,
+                    match_mode: MatchMode::Any,
+                    ..options.clone()
+                };
+                &boolean_options
+            }
+            None => options,
+        };
+        let groups = self.term_groups(parsed, options);
+        let cosine = options.ranker == Ranker::Cosine;
+        // Cosine: the query vector holds weight * idf for each known term and phrase word.
+        let query_norm = if cosine {
+            let body_scoped = parsed.scoped.iter().filter(|scoped| scoped.scope == Scope::Body);
+            let phrase_words = parsed
+                .phrases
+                .iter()
+                .flatten()
+                .chain(body_scoped.flat_map(|scoped| &scoped.tokens))
+                .map(|word| (word.clone(), 1.0));
+            groups
+                .iter()
+                .flatten()
+                .cloned()
+                .chain(phrase_words)
+                .filter_map(|(word, weight)| {
+                    let docs = self.term_tf.get(&word)?;
+                    Some((weight * self.cached_idf(&self.idf, &word, docs.len(), options.ranker)).powi(2))
+                })
+                .sum::<f64>()
+                .sqrt()
+        } else {
+            1.0
+        };
+        let cosine_score = |id: DocId, tf: u32, idf: f64, weight: f64| {
+            let doc_norm = self.doc_norm(id).filter(|&n| n > 0.0).unwrap_or(1.0);
+            weight * idf * (tf as f64 * idf) / (query_norm * doc_norm)
+        };
+        for (word, weight) in groups.iter().flatten() {
+            let doc_counts = match self.term_tf.get(word) {
+                Some(m) => m,
+                None => continue,
+            };
+            let df = doc_counts.len();
+            let idf = self.cached_idf(&self.idf, word, df, options.ranker);
+            for (&id, &tf) in doc_counts {
+                let score = if cosine {
+                    cosine_score(id, tf, idf, *weight)
+                } else {
+                    weight * options.boosts.body * self.term_score(id, tf, idf, options)
+                };
+                let weight = if cosine { *weight } else { weight * options.boosts.body };
+                add(id, TermScore::new(word, "body", tf, df, idf, weight, score));
+            }
+        }
+        // Other fields: short, so no length normalization. Cosine compares body
+        // vectors only.
+        let no_cache = HashMap::new();
+        for field in Field::ALL.into_iter().filter(|_| !cosine) {
+            let boost = options.boosts.get(field);
+            let Some(postings) = self.field(field).filter(|_| boost != 0.0) else {
+                continue;
+            };
+            let cache = self.field_idf.get(&field).unwrap_or(&no_cache);
+            for (word, weight) in groups.iter().flatten() {
+                let Some(doc_counts) = postings.get(word) else {
+                    continue;
+                };
+                let df = doc_counts.len();
+                let idf = self.cached_idf(cache, word, df, options.ranker);
+                let weight = weight * boost;
+                for (&id, &tf) in doc_counts {
+                    let score = weight * idf * tf_weight(tf, 1.0, options);
+                    add(id, TermScore::new(word, field.name(), tf, df, idf, weight, score));
+                }
+            }
+        }
+        // Phrases: every phrase must match; matched phrase terms score with a small boost
+        // (under cosine they are plain query-vector dimensions instead).
+        let mut required_sets = Vec::with_capacity(parsed.phrases.len() + parsed.scoped.len());
+        for phrase in &parsed.phrases {
+            let matches = self.phrase_matches(phrase);
+            for word in phrase {
+                let Some(doc_counts) = self.term_tf.get(word) else {
+                    continue;
+                };
+                let df = doc_counts.len();
+                let idf = self.cached_idf(&self.idf, word, df, options.ranker);
+                let weight = if cosine { 1.0 } else { PHRASE_BOOST * options.boosts.body };
+                for &id in &matches {
+                    if let Some(&tf) = doc_counts.get(&id) {
+                        let score = if cosine {
+                            cosine_score(id, tf, idf, weight)
+                        } else {
+                            weight * self.term_score(id, tf, idf, options)
+                        };
+                        add(id, TermScore::new(word, "phrase", tf, df, idf, weight, score));
+                    }
+                }
+            }
+            required_sets.push(matches);
+        }
+        // Field clauses: the words of matching documents score in that field only,
+        // with its boost. Cosine compares body vectors, so there other fields only
+        // filter. In a boolean query the tree decides what matches.
+        for scoped in &parsed.scoped {
+            let matches = self.scoped_matches(scoped);
+            let (postings, cache, boost) = match scoped.scope {
+                Scope::Body => (Some(&self.term_tf), &self.idf, options.boosts.body),
+                Scope::Field(field) => (
+                    self.field(field),
+                    self.field_idf.get(&field).unwrap_or(&no_cache),
+                    options.boosts.get(field),
+                ),
+            };
+            for word in &scoped.tokens {
+                let Some(doc_counts) = postings.and_then(|postings| postings.get(word)) else {
+                    continue;
+                };
+                let df = doc_counts.len();
+                let idf = self.cached_idf(cache, word, df, options.ranker);
+                for &id in &matches {
+                    let Some(&tf) = doc_counts.get(&id) else {
+                        continue;
+                    };
+                    let (weight, score) = match scoped.scope {
+                        Scope::Body if cosine => (1.0, cosine_score(id, tf, idf, 1.0)),
+                        Scope::Field(_) if cosine => (0.0, 0.0),
+                        Scope::Body => (boost, boost * self.term_score(id, tf, idf, options)),
+                        Scope::Field(_) => (boost, boost * idf * tf_weight(tf, 1.0, options)),
+                    };
+                    add(id, TermScore::new(word, scoped.scope.name(), tf, df, idf, weight, score));
+                }
+            }
+            if parsed.boolean.is_none() {
+                required_sets.push(matches);
+            }
+        }
+        let mut excluded = self.docs_containing(&parsed.excluded);
+        for scoped in &parsed.excluded_scoped {
+            excluded.extend(self.scoped_matches(scoped));
+        }
+        let required = options.match_mode.required(groups.len());
+        // Single-term queries have no proximity to measure; skip collecting groups.
+        let proximity_groups = if options.proximity != 0.0 && groups.len() > 1 {
+            groups.iter().map(|group| group.iter().map(|(word, _)| word.clone()).collect()).collect()
+        } else {
+            Vec::new()
+        };
+        Some(QueryMatch {
+            required_sets,
+            excluded,
+            required,
+            term_counts: if required > 1 { self.matched_term_counts(&groups) } else { HashMap::new() },
+            proximity_groups,
+            boolean: parsed.boolean.as_ref().map(|tree| self.boolean_matches(tree, options)),
+        })
+    }
+
+    /// Score multiplier for how close together the query terms occur in a document's
+    /// body: `1 + proximity * terms / window`, where `window` is the length of the
+    /// shortest span holding one occurrence of every term group. Adjacent terms get
+    /// the full boost; it decays as they spread out. 1.0 for documents missing a
+    /// group, indexes without positions, or single-term queries.
+    fn proximity_factor(&self, id: DocId, matched: &QueryMatch, options: &SearchOptions) -> f64 {
+        let groups = &matched.proximity_groups;
+        if groups.len() < 2 {
+            return 1.0;
+        }
+        // (position, group) for every occurrence, in position order.
+        let mut occurrences: Vec<(u32, usize)> = Vec::new();
+        for (group, words) in groups.iter().enumerate() {
+            let before = occurrences.len();
+            for word in words {
+                if let Some(positions) = self.positions.get(word).and_then(|m| m.get(&id)) {
+                    occurrences.extend(positions.iter().map(|&pos| (pos, group)));
+                }
+            }
+            if occurrences.len() == before {
+                return 1.0;
+            }
+        }
+        occurrences.sort_unstable();
+        // Sliding window over the occurrences, shrunk from the left while it still
+        // covers every group.
+        let mut counts = vec![0usize; groups.len()];
+        let mut covered = 0;
+        let mut start = 0;
+        let mut best = u32::MAX;
+        for &(pos, group) in &occurrences {
+            counts[group] += 1;
+            if counts[group] == 1 {
+                covered += 1;
+            }
+            while covered == groups.len() {
+                let (first, first_group) = occurrences[start];
+                best = best.min(pos - first + 1);
+                counts[first_group] -= 1;
+                if counts[first_group] == 0 {
+                    covered -= 1;
+                }
+                start += 1;
+            }
+        }
+        1.0 + options.proximity * groups.len() as f64 / best.max(groups.len() as u32) as f64
+    }
+
+    /// Score multiplier from `options.recency` (1.0 when not boosted).
+    fn recency_factor(&self, id: DocId, options: &SearchOptions) -> f64 {
+        let Some(recency) = options.recency else {
+            return 1.0;
+        };
+        let cutoff = Utc::now() - Duration::days(i64::from(recency.days));
+        let modified = self.docs.get(&id).and_then(|m| m.last_modified);
+        if modified.is_some_and(|t| t >= cutoff) {
+            recency.factor
+        } else {
+            1.0
+        }
+    }
+
+    /// Why `url` scores what it does for `query` under the default options.
+    /// None if the document isn't indexed or doesn't match.
+    #[cfg(test)]
+    pub fn explain(&self, query: &str, url: &str) -> Option<Explanation> {
+        self.explain_with(query, url, &SearchOptions::default())
+    }
+
+    /// Like `explain`, with explicit search options.
+    pub fn explain_with(&self, query: &str, url: &str, options: &SearchOptions) -> Option<Explanation> {
+        let target = self.doc_id(url)?;
+        let mut contributions = Vec::new();
+        let parsed = ParsedQuery::parse_with(query, &self.analyzer);
+        let matched = self.score_query(&parsed, options, |id, part| {
+            if id == target {
+                contributions.push(ScoreContribution::from(part));
+            }
+        })?;
+        if contributions.is_empty() || !matched.accepts(target) {
+            return None;
+        }
+        let recency_factor = self.recency_factor(target, options);
+        let proximity_factor = self.proximity_factor(target, &matched, options);
+        // Same summation order as `rank`, so the total matches the hit's score exactly.
+        let score = contributions.iter().fold(0.0, |sum, part| sum + part.score) * recency_factor * proximity_factor;
+        Some(Explanation {
+            url: url.to_string(),
+            score,
+            recency_factor,
+            proximity_factor,
+            contributions,
+        })
+    }
+
+    fn rank_parsed(&self, parsed: &ParsedQuery, options: &SearchOptions) -> Vec<(DocId, f64)> {
+        self.rank_page(parsed, options).ranked
+    }
+
+    /// Like `rank_parsed`, also returning the number of matching documents before
+    /// `offset` and `limit` are applied, their `options.facets` counts and what
+    /// `options.collapse` dropped.
+    fn rank_page(&self, parsed: &ParsedQuery, options: &SearchOptions) -> RankedPage {
+        let mut doc_scores: HashMap<DocId, f64> = HashMap::new();
+        // Each candidate's URL is checked once, and filtered-out documents never
+        // get a score, so a selective filter leaves little to rank.
+        let mut allowed: HashMap<DocId, bool> = HashMap::new();
+        let Some(matched) = self.score_query(parsed, options, |id, part| {
+            let allowed = options.filter.is_empty()
+                || *allowed.entry(id).or_insert_with(|| options.filter.allows(self.url(id)));
+            if allowed {
+                *doc_scores.entry(id).or_insert(0.0) += part.score;
+            }
+        }) else {
+            return RankedPage::default();
+        };
+        doc_scores.retain(|&id, _| matched.accepts(id));
+        for (&id, score) in doc_scores.iter_mut() {
+            *score *= self.recency_factor(id, options);
+            *score *= self.proximity_factor(id, &matched, options);
+        }
+        if let Some(min_score) = options.min_score {
+            let top = doc_scores.values().copied().fold(f64::NEG_INFINITY, f64::max);
+            doc_scores.retain(|_, score| min_score.passes(*score, top));
+        }
+        let facets = self.facet_counts(doc_scores.keys().copied(), &options.facets);
+        let collapsed = match options.collapse {
+            Some(collapse) => self.collapse(&mut doc_scores, collapse),
+            None => HashMap::new(),
+        };
+        let total = doc_scores.len();
+        let mut ranked = match options.limit {
+            Some(k) if options.sort == Sort::default() => self.top_k(doc_scores, options.offset.saturating_add(k)),
+            _ => {
+                let mut v: Vec<(DocId, f64)> = doc_scores.into_iter().collect();
+                self.sort_hits(&mut v, options.sort);
+                if let Some(k) = options.limit {
+                    v.truncate(options.offset.saturating_add(k));
+                }
+                v
+            }
+        };
+        ranked.drain(..options.offset.min(ranked.len()));
+        RankedPage {
+            total,
+            ranked,
+            facets,
+            collapsed,
+        }
+    }
+
+    /// Sort (doc id, score) pairs by `sort`, ties by score (best first) and URL.
+    fn sort_hits(&self, hits: &mut [(DocId, f64)], sort: Sort) {
+        let by_score = |a: &(DocId, f64), b: &(DocId, f64)| cmp_hits((b.1, self.url(b.0)), (a.1, self.url(a.0)));
+        let modified = |id: DocId| self.docs.get(&id).and_then(|meta| meta.last_modified);
+        match sort.by {
+            SortBy::Score => hits.sort_by(|a, b| sort.order.apply(by_score(b, a))),
+            SortBy::Url => hits.sort_by(|a, b| sort.order.apply(self.url(a.0).cmp(self.url(b.0)))),
+            SortBy::Time => hits.sort_by(|a, b| {
+                let time = match (modified(a.0), modified(b.0)) {
+                    (Some(x), Some(y)) => sort.order.apply(x.cmp(&y)),
+                    (x, y) => x.is_none().cmp(&y.is_none()),
+                };
+                time.then_with(|| by_score(a, b))
+            }),
+        }
+    }
+
+    /// Keep the best `collapse.size` documents of each group, dropping the rest
+    /// from `doc_scores`. Returns, for the best document of each group that lost
+    /// some, how many were dropped. Documents without a value (such as pages at
+    /// the root for `path1`) aren't grouped.
+    fn collapse(&self, doc_scores: &mut HashMap<DocId, f64>, collapse: Collapse) -> HashMap<DocId, usize> {
+        let mut groups: HashMap<String, Vec<(DocId, f64)>> = HashMap::new();
+        for (&id, &score) in doc_scores.iter() {
+            let value = url::Url::parse(self.url(id)).ok().and_then(|url| collapse.by.value(&url));
+            if let Some(value) = value {
+                groups.entry(value).or_default().push((id, score));
+            }
+        }
+        let size = collapse.size.max(1);
+        let mut collapsed = HashMap::new();
+        for mut hits in groups.into_values().filter(|hits| hits.len() > size) {
+            hits.sort_by(|a, b| cmp_hits((b.1, self.url(b.0)), (a.1, self.url(a.0))));
+            for (id, _) in &hits[size..] {
+                doc_scores.remove(id);
+            }
+            collapsed.insert(hits[0].0, hits.len() - size);
+        }
+        collapsed
+    }
+
+    /// Count `ids` by each of `facets` in one pass, keeping each facet's
+    /// `MAX_FACET_BUCKETS` largest counts (ties by value).
+    fn facet_counts(&self, ids: impl Iterator<Item = DocId>, facets: &[Facet]) -> BTreeMap<Facet, Vec<FacetBucket>> {
+        if facets.is_empty() {
+            return BTreeMap::new();
+        }
+        let mut counts: Vec<HashMap<String, usize>> = vec![HashMap::new(); facets.len()];
+        for id in ids {
+            let Ok(url) = url::Url::parse(self.url(id)) else {
+                continue;
+            };
+            for (facet, counts) in facets.iter().zip(&mut counts) {
+                if let Some(value) = facet.value(&url) {
+                    *counts.entry(value).or_insert(0) += 1;
+                }
+            }
+        }
+        facets
+            .iter()
+            .zip(counts)
+            .map(|(&facet, counts)| {
+                let mut buckets: Vec<FacetBucket> =
+                    counts.into_iter().map(|(value, count)| FacetBucket { value, count }).collect();
+                buckets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+                buckets.truncate(MAX_FACET_BUCKETS);
+                (facet, buckets)
+            })
+            .collect()
+    }
+
+    /// The best `k` scores in the same order as a full sort, keeping at most `k`
+    /// candidates in a min-heap instead of sorting every match.
+    fn top_k(&self, doc_scores: HashMap<DocId, f64>, k: usize) -> Vec<(DocId, f64)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut heap: BinaryHeap<Reverse<HeapHit<'_>>> = BinaryHeap::with_capacity(k.min(doc_scores.len()) + 1);
+        for (id, score) in doc_scores {
+            let hit = HeapHit { score, url: self.url(id), id };
+            if heap.len() < k {
+                heap.push(Reverse(hit));
+            } else if heap.peek().is_some_and(|worst| hit > worst.0) {
+                heap.pop();
+                heap.push(Reverse(hit));
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(hit)| (hit.id, hit.score))
+            .collect()
+    }
+
+    /// Search keeping only the best `k` hits (same order as `search_ranked`).
+    #[cfg(test)]
+    pub fn search_ranked_top_k(&self, query: &str, k: usize) -> Vec<(String, f64)> {
+        let options = SearchOptions {
+            limit: Some(k),
+            ..Default::default()
+        };
+        self.search_ranked_with(query, &options)
+    }
+}
+
+/// Byte range of the word in a whitespace-separated query chunk: what's left
+/// without a leading `-`, `(` or quote, a closing `)` or quote, and a field
+/// prefix. None for an operator or a chunk of punctuation only.
+fn query_word(chunk: &str) -> Option<(usize, usize)> {
+    let end = chunk.trim_end_matches([')', '"']).len();
+    let mut start = chunk.len() - chunk.trim_start_matches(['-', '(', '"']).len();
+    if start >= end {
+        return None;
+    }
+    if let Some((_, rest)) = query::split_scope(&chunk[start..end]) {
+        start = end - rest.len();
+    }
+    (!matches!(&chunk[start..end], "AND" | "OR" | "NOT")).then_some((start, end))
+}
+
+/// Scores closer than this compare as equal, so floating-point noise from different
+/// summation orders can't reorder otherwise tied hits.
+const SCORE_EPSILON: f64 = 1e-9;
+
+/// Score on the `SCORE_EPSILON` grid, for comparisons.
+fn score_key(score: f64) -> f64 {
+    (score / SCORE_EPSILON).round()
+}
+
+/// Result order: greater is better. Higher score (to `SCORE_EPSILON`) first, then
+/// the lexicographically smaller URL, so tied hits always come back in URL order.
+fn cmp_hits(a: (f64, &str), b: (f64, &str)) -> Ordering {
+    score_key(a.0).total_cmp(&score_key(b.0)).then_with(|| b.1.cmp(a.1))
+}
+
+/// Heap entry for top-k selection, ordered by `cmp_hits` like the full sort.
+struct HeapHit<'a> {
+    score: f64,
+    url: &'a str,
+    id: DocId,
+}
+
+impl Ord for HeapHit<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_hits((self.score, self.url), (other.score, other.url))
+    }
+}
+
+impl PartialOrd for HeapHit<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapHit<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapHit<'_> {}
+
+/// Document frequency bounds for `IndexWithTf::prune`.
+#[derive(Debug, Clone, Copy)]
+pub struct PruneOptions {
+    /// Drop terms found in fewer documents than this (1 = keep all).
+    pub min_df: usize,
+    /// Drop terms found in more than this fraction of documents.
+    pub max_df_ratio: Option<f64>,
+}
+
+impl Default for PruneOptions {
+    fn default() -> Self {
+        Self {
+            min_df: 1,
+            max_df_ratio: None,
+        }
+    }
+}
+
+/// Terms dropped by `IndexWithTf::prune`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PruneStats {
+    /// Terms below `min_df`.
+    pub rare: usize,
+    /// Terms above `max_df_ratio`.
+    pub common: usize,
+}
+
+/// Options for building an index from crawl results.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Analyzer for documents, saved in the index for query time.
+    pub analyzer: AnalyzerConfig,
+    /// Term pruning applied after all documents are added.
+    pub prune: PruneOptions,
+}
+
+/// Build index with TF for ranking; save as IndexWithTf.
+pub fn build_index_with_tf(results: &[CrawlResult], options: &BuildOptions) -> (IndexWithTf, PruneStats) {
+    let mut index = IndexWithTf::build_with(results, &options.analyzer);
+    let stats = index.prune(&options.prune);
+    (index, stats)
+}
+
+/// On-disk encoding of an IndexWithTf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IndexFormat {
+    /// Pretty-printed JSON.
+    Json,
+    /// bincode with packed posting lists, prefixed with `BINARY_MAGIC`.
+    Bin,
+}
+
+impl IndexFormat {
+    /// Pick the format from the file extension (ignoring a trailing `.gz`):
+    /// `.bin` is binary, anything else JSON.
+    pub fn from_path(path: &Path) -> Self {
+        let path = match path.file_stem() {
+            Some(stem) if is_gzip_path(path) => Path::new(stem),
+            _ => path,
+        };
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("bin") => Self::Bin,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// How to write an index file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveOptions {
+    pub format: IndexFormat,
+    /// Gzip the output.
+    pub compress: bool,
+}
+
+impl SaveOptions {
+    /// Derive options from the file name: `.gz` compresses, `.bin` (before any `.gz`) is binary.
+    pub fn for_path(path: &Path) -> Self {
+        Self {
+            format: IndexFormat::from_path(path),
+            compress: is_gzip_path(path),
+        }
+    }
+}
+
+/// Index file format version written by this build.
+///
+/// - v0: plain `InvertedIndex` JSON (no term frequencies)
+/// - v1: unversioned URL-keyed `IndexWithTf` JSON (term frequencies only)
+/// - v2: `IndexWithTf` with document IDs, fields, positions and the analyzer, as
+///   JSON in a versioned envelope or binary with packed posting lists
+///
+/// Binary payloads are positional, so a field added to a stored struct changes
+/// the layout and needs a new version.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Header of a versioned binary index file; followed by the version (u32 LE),
+/// flags (u32 LE), then the bincode payload.
+const BINARY_MAGIC: &[u8; 8] = b"RSIDXVER";
+
+/// Binary header flag: the payload is a `PackedIndexRef`, with every posting
+/// list delta/varint packed by `codec`.
+const FLAG_PACKED_POSTINGS: u32 = 1;
+
+/// Body or field postings serialized as term -> packed (doc, tf) list.
+struct PackedPostings<'a>(&'a Postings);
+
+impl serde::Serialize for PackedPostings<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(term, docs)| (term, codec::pack_tf(docs))))
+    }
+}
+
+/// Per-field postings, each packed.
+struct PackedFields<'a>(&'a HashMap<Field, Postings>);
+
+impl serde::Serialize for PackedFields<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(field, postings)| (field, PackedPostings(postings))))
+    }
+}
+
+/// Positions serialized as term -> packed (doc, positions) list.
+struct PackedPositions<'a>(&'a HashMap<String, HashMap<DocId, Vec<u32>>>);
+
+impl serde::Serialize for PackedPositions<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(term, docs)| (term, codec::pack_positions(docs))))
+    }
+}
+
+/// `IndexWithTf` as written with `FLAG_PACKED_POSTINGS`. Lists are packed while
+/// serializing, so the whole packed index is never held in memory.
+#[derive(serde::Serialize)]
+struct PackedIndexRef<'a> {
+    urls: &'a [String],
+    term_tf: PackedPostings<'a>,
+    doc_count: usize,
+    docs: &'a HashMap<DocId, DocMeta>,
+    doc_lengths: &'a HashMap<DocId, u32>,
+    avg_doc_len: f64,
+    positions: PackedPositions<'a>,
+    fields: PackedFields<'a>,
+    analyzer: &'a AnalyzerConfig,
+    aliases: &'a HashMap<String, String>,
+}
+
+impl<'a> PackedIndexRef<'a> {
+    fn new(index: &'a IndexWithTf) -> Self {
+        Self {
+            urls: &index.urls,
+            term_tf: PackedPostings(&index.term_tf),
+            doc_count: index.doc_count,
+            docs: &index.docs,
+            doc_lengths: &index.doc_lengths,
+            avg_doc_len: index.avg_doc_len,
+            positions: PackedPositions(&index.positions),
+            fields: PackedFields(&index.fields),
+            analyzer: &index.analyzer,
+            aliases: &index.aliases,
+        }
+    }
+}
+
+/// Owned counterpart of `PackedIndexRef`, unpacked by `into_current`.
+#[derive(serde::Deserialize)]
+struct PackedIndex {
+    urls: Vec<String>,
+    term_tf: HashMap<String, Vec<u8>>,
+    doc_count: usize,
+    docs: HashMap<DocId, DocMeta>,
+    doc_lengths: HashMap<DocId, u32>,
+    avg_doc_len: f64,
+    positions: HashMap<String, Vec<u8>>,
+    fields: HashMap<Field, HashMap<String, Vec<u8>>>,
+    analyzer: AnalyzerConfig,
+    aliases: HashMap<String, String>,
+}
+
+/// Unpack term -> packed (doc, tf) lists.
+fn unpack_postings(packed: HashMap<String, Vec<u8>>) -> Result<Postings, String> {
+    packed
+        .into_iter()
+        .map(|(term, bytes)| Ok((term, codec::unpack_tf(&bytes)?)))
+        .collect()
+}
+
+impl PackedIndex {
+    fn into_current(self) -> Result<IndexWithTf, String> {
+        let positions = self
+            .positions
+            .into_iter()
+            .map(|(term, bytes)| Ok((term, codec::unpack_positions(&bytes)?)))
+            .collect::<Result<_, String>>()?;
+        let fields = self
+            .fields
+            .into_iter()
+            .map(|(field, postings)| Ok((field, unpack_postings(postings)?)))
+            .collect::<Result<_, String>>()?;
+        Ok(IndexWithTf {
+            urls: self.urls,
+            term_tf: unpack_postings(self.term_tf)?,
+            doc_count: self.doc_count,
+            docs: self.docs,
+            doc_lengths: self.doc_lengths,
+            avg_doc_len: self.avg_doc_len,
+            positions,
+            fields,
+            analyzer: self.analyzer,
+            aliases: self.aliases,
+            ..IndexWithTf::default()
+        })
+    }
+}
+
+/// JSON envelope: `{ "version": 2, "data": ... }`.
+#[derive(serde::Serialize)]
+struct EnvelopeRef<'a> {
+    version: u32,
+    data: &'a IndexWithTf,
+}
+
+#[derive(serde::Deserialize)]
+struct Envelope<T> {
+    data: T,
+}
+
+/// Just enough of a JSON file to tell an envelope from an unversioned index.
+/// A v0 index may have a term named "version", but its value is then an array.
+#[derive(serde::Deserialize)]
+struct VersionProbe {
+    #[serde(default)]
+    version: Option<serde_json::Value>,
+    #[serde(default)]
+    data: Option<serde::de::IgnoredAny>,
+}
+
+/// Leading bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// True if the path ends in `.gz`.
+pub fn is_gzip_path(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "gz")
+}
+
+/// Save IndexWithTf, choosing format and compression from the file name.
+pub fn save_index_with_tf(index: &IndexWithTf, path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    save_index_with_tf_as(index, path, SaveOptions::for_path(path))
+}
+
+/// Serialize IndexWithTf to a writer in the given format.
+fn write_index_with_tf<W: Write>(
+    index: &IndexWithTf,
+    mut writer: W,
+    format: IndexFormat,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match format {
+        IndexFormat::Json => {
+            let envelope = EnvelopeRef {
+                version: FORMAT_VERSION,
+                data: index,
+            };
+            serde_json::to_writer_pretty(&mut writer, &envelope)?
+        }
+        IndexFormat::Bin => {
+            writer.write_all(BINARY_MAGIC)?;
+            writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+            writer.write_all(&FLAG_PACKED_POSTINGS.to_le_bytes())?;
+            bincode::serialize_into(&mut writer, &PackedIndexRef::new(index))?;
+        }
+    }
+    Ok(())
+}
+
+/// Save IndexWithTf with explicit options. Output is streamed, never buffered whole,
+/// and written atomically (see `write_atomic`).
+pub fn save_index_with_tf_as(
+    index: &IndexWithTf,
+    path: &Path,
+    options: SaveOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    write_atomic(path, |writer| {
+        if options.compress {
+            let mut encoder = GzEncoder::new(writer, Compression::default());
+            write_index_with_tf(index, &mut encoder, options.format)?;
+            encoder.finish()?;
+        } else {
+            write_index_with_tf(index, writer, options.format)?;
+        }
+        Ok(())
+    })
+}
+
+/// Temporary file next to `path` (same directory, so the final rename stays on one filesystem).
+pub fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// Create `path`, write it and fsync before returning.
+fn write_synced(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<fs::File>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    write(&mut writer)?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Write a file via a temporary file: write, fsync, then rename over `path`, so
+/// readers see either the old file or the complete new one, never a partial write.
+/// `fs::rename` replaces an existing target on Windows as well (MoveFileEx with
+/// MOVEFILE_REPLACE_EXISTING). On failure the temporary file is removed.
+fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<fs::File>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let tmp = temp_path(path);
+    if let Err(e) = write_synced(&tmp, write).and_then(|()| Ok(fs::rename(&tmp, path)?)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    // Persist the rename itself; directories can't be opened for syncing on Windows.
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Open an index file for reading, transparently gunzipping gzip content.
+fn open_index_reader(path: &Path) -> io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        return Ok(Box::new(BufReader::new(GzDecoder::new(reader))));
+    }
+    Ok(Box::new(reader))
+}
+
+/// Error for a file written by a newer build.
+fn newer_version_error(version: u32) -> Box<dyn std::error::Error + Send + Sync> {
+    format!(
+        "index was written by a newer version (format v{}; this build reads up to v{})",
+        version, FORMAT_VERSION
+    )
+    .into()
+}
+
+/// Decode IndexWithTf from a reader, returning it with the format version it was
+/// stored in. Older formats are converted to the current in-memory layout.
+fn read_index_with_tf(mut reader: Box<dyn Read>) -> Result<(IndexWithTf, u32), Box<dyn std::error::Error + Send + Sync>> {
+    let mut header = Vec::with_capacity(BINARY_MAGIC.len());
+    (&mut reader).take(BINARY_MAGIC.len() as u64).read_to_end(&mut header)?;
+    let (mut index, version) = if header == BINARY_MAGIC.as_slice() {
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version > FORMAT_VERSION {
+            return Err(newer_version_error(version));
+        }
+        if version < FORMAT_VERSION {
+            // Binary files were introduced with the current version.
+            return Err(format!("unexpected binary index file of format v{}", version).into());
+        }
+        let mut flags = [0u8; 4];
+        reader.read_exact(&mut flags)?;
+        let flags = u32::from_le_bytes(flags);
+        if flags & !FLAG_PACKED_POSTINGS != 0 {
+            return Err(format!("index file has unknown header flags {:#x}", flags).into());
+        }
+        if flags & FLAG_PACKED_POSTINGS == 0 {
+            // Every build writes packed posting lists.
+            return Err("index file without packed postings".into());
+        }
+        (bincode::deserialize_from::<_, PackedIndex>(reader)?.into_current()?, version)
+    } else {
+        let mut json = header;
+        reader.read_to_end(&mut json)?;
+        read_json_index(&json)?
+    };
+    if version < FORMAT_VERSION {
+        eprintln!(
+            "Note: converting index from format v{} to v{}; save it again to upgrade the file",
+            version, FORMAT_VERSION
+        );
+    }
+    index.analyzer.standard().check()?;
+    if let Some(warning) = index.analyzer.standard().segmentation_mismatch() {
+        eprintln!("Warning: {}", warning);
+    }
+    index.rebuild_url_ids();
+    if index.approximate_doc_lengths() {
+        eprintln!("Note: index has no document lengths; approximating them from term frequencies");
+    }
+    index.finalize();
+    Ok((index, version))
+}
+
+/// Decode a JSON index: a versioned envelope, or an unversioned v1/v0 file.
+fn read_json_index(json: &[u8]) -> Result<(IndexWithTf, u32), Box<dyn std::error::Error + Send + Sync>> {
+    let probe: VersionProbe = serde_json::from_slice(json)?;
+    if let (Some(version), Some(_)) = (probe.version.as_ref().and_then(|v| v.as_u64()), probe.data) {
+        let version = u32::try_from(version).unwrap_or(u32::MAX);
+        return match version {
+            FORMAT_VERSION => Ok((serde_json::from_slice::<Envelope<IndexWithTf>>(json)?.data, version)),
+            v if v > FORMAT_VERSION => Err(newer_version_error(v)),
+            _ => Err(format!("unexpected envelope for index format v{}", version).into()),
+        };
+    }
+    // Unversioned: URL-keyed IndexWithTf, then a plain InvertedIndex.
+    let err = match serde_json::from_slice::<LegacyIndexWithTf>(json) {
+        Ok(legacy) => return Ok((legacy.into_current(), 1)),
+        Err(e) => e,
+    };
+    if let Ok(simple) = serde_json::from_slice::<InvertedIndex>(json) {
+        return Ok((IndexWithTf::from_inverted(simple), 0));
+    }
+    Err(err.into())
+}
+
+/// Load IndexWithTf along with the format version of the file (v0 files carry no real term frequencies).
+pub fn load_index_with_tf_versioned(path: &Path) -> Result<(IndexWithTf, u32), Box<dyn std::error::Error + Send + Sync>> {
+    read_index_with_tf(open_index_reader(path)?)
+}
+
+/// Load IndexWithTf (for ranked search). Format and gzip compression are auto-detected.
+/// Older format versions are converted with a notice on stderr.
+pub fn load_index_with_tf(path: &Path) -> Result<IndexWithTf, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(load_index_with_tf_versioned(path)?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenize::{AnalyzerKind, StandardAnalyzer, StopwordLanguage, Stopwords, Tokenizer};
+
+    /// A crawled page with only a URL, title and body.
+    fn page(url: &str, title: &str, body: &str) -> CrawlResult {
+        CrawlResult {
+            url: url.to_string(),
+            final_url: url.to_string(),
+            canonical_url: None,
+            title: title.to_string(),
+            description: String::new(),
+            headings: String::new(),
+            body_text: body.to_string(),
+            links: Vec::new(),
+            last_modified: None,
+            title_source: None,
+            refresh_target: None,
+        }
+    }
+
+    /// A path in the system temp directory, unique to this process and `name`.
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rustysearch-test-{}-{}", std::process::id(), name))
+    }
+
+    /// Load a sample index from `testdata/index`, with its format version.
+    fn load_fixture(name: &str) -> (IndexWithTf, u32) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/index").join(name);
+        load_index_with_tf_versioned(&path).unwrap_or_else(|e| panic!("loading {}: {}", name, e))
+    }
+
+    /// URLs of the hits for `query`, best first.
+    fn hit_urls(index: &IndexWithTf, query: &str) -> Vec<String> {
+        index.search_ranked(query).into_iter().map(|(url, _)| url).collect()
+    }
+
+    /// The samples are crawls of the same three pages: an English page on Rust,
+    /// one on Tokio (`JoinSet`, `join_next`) and a Japanese one.
+    const SAMPLE_HOME: &str = "http://127.0.0.1:8765/";
+    const SAMPLE_TOKIO: &str = "http://127.0.0.1:8765/tokio.html";
+    const SAMPLE_JAPANESE: &str = "http://127.0.0.1:8765/japanese.html";
+
+    fn assert_sample(index: &IndexWithTf) {
+        assert_eq!(index.doc_count, 3);
+        let hits = hit_urls(index, "rust");
+        assert!(hits.contains(&SAMPLE_HOME.to_string()), "hits for rust: {:?}", hits);
+        assert_eq!(hit_urls(index, "async").first().map(String::as_str), Some(SAMPLE_TOKIO));
+    }
+
+    /// One sample per format version and file type, as written by the builds of the time.
+    const VERSION_SAMPLES: [(&str, u32); 4] = [
+        ("v0.json", 0),
+        ("v1-url-keyed.json", 1),
+        ("v2.json", 2),
+        ("v2.bin", 2),
+    ];
+
+    #[test]
+    fn reads_a_sample_of_every_format_version() {
+        // A new format version needs a sample here.
+        assert_eq!(VERSION_SAMPLES.last().map(|&(_, version)| version), Some(FORMAT_VERSION));
+        for (name, expected) in VERSION_SAMPLES {
+            let (index, version) = load_fixture(name);
+            assert_eq!(version, expected, "{}", name);
+            assert_sample(&index);
+        }
+    }
+
+    #[test]
+    fn migrated_samples_save_in_the_current_format() {
+        for (name, _) in VERSION_SAMPLES {
+            let (index, _) = load_fixture(name);
+            let path = temp_file(&format!("migrated-{}", name));
+            save_index_with_tf(&index, &path).unwrap();
+            let reloaded = load_index_with_tf_versioned(&path);
+            fs::remove_file(&path).unwrap();
+            let (reloaded, version) = reloaded.unwrap();
+            assert_eq!(version, FORMAT_VERSION, "{}", name);
+            assert_eq!(reloaded.urls, index.urls, "{}", name);
+            assert_eq!(reloaded.term_tf, index.term_tf, "{}", name);
+            assert_eq!(reloaded.analyzer, index.analyzer, "{}", name);
+        }
+    }
+
+    #[test]
+    fn files_from_a_newer_version_are_refused_by_version() {
+        let newer = FORMAT_VERSION + 1;
+        let mut binary = BINARY_MAGIC.to_vec();
+        binary.extend(newer.to_le_bytes());
+        binary.extend(FLAG_PACKED_POSTINGS.to_le_bytes());
+        let json = format!(r#"{{"version": {}, "data": {{"urls": []}}}}"#, newer);
+        for contents in [binary, json.into_bytes()] {
+            let error = read_index_with_tf(Box::new(io::Cursor::new(contents))).err().unwrap();
+            assert!(error.to_string().contains("written by a newer version"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn v2_samples_keep_their_analyzer() {
+        for name in ["v2.json", "v2.bin"] {
+            let (index, _) = load_fixture(name);
+            let standard = index.analyzer.standard();
+            assert_eq!(standard.stopwords, Stopwords::builtin(StopwordLanguage::English), "{}", name);
+            assert_eq!(standard.tokenizer, Tokenizer::Unicode, "{}", name);
+            assert!(hit_urls(&index, "検索").contains(&SAMPLE_JAPANESE.to_string()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn baseline_samples_migrate_with_the_default_analyzer() {
+        for name in ["v0.json", "v1-url-keyed.json"] {
+            let (index, _) = load_fixture(name);
+            assert_eq!(index.analyzer, AnalyzerConfig::default(), "{}", name);
+            assert!(index.field(Field::Url).is_some_and(|postings| !postings.is_empty()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn binary_files_of_older_versions_are_refused() {
+        let mut binary = BINARY_MAGIC.to_vec();
+        binary.extend((FORMAT_VERSION - 1).to_le_bytes());
+        binary.extend(FLAG_PACKED_POSTINGS.to_le_bytes());
+        let error = read_index_with_tf(Box::new(io::Cursor::new(binary))).err().unwrap();
+        assert!(error.to_string().contains("unexpected binary index file of format v1"), "{}", error);
+    }
+
+    #[test]
+    fn binary_and_json_round_trip_the_analyzer() {
+        let standard = StandardAnalyzer::default()
+            .with_tokenizer(Tokenizer::Unicode)
+            .with_split_identifiers(true);
+        let analyzer = AnalyzerKind::CjkBigram.build(standard);
+        let results = [
+            page("https://example.com/a", "JoinSet", "await join_next on the set"),
+            page("https://example.com/b", "検索", "全文検索の仕組み"),
+        ];
+        let index = IndexWithTf::build_with(&results, &analyzer);
+        for name in ["round-trip.bin", "round-trip.json"] {
+            let path = temp_file(name);
+            save_index_with_tf(&index, &path).unwrap();
+            let loaded = load_index_with_tf_versioned(&path);
+            fs::remove_file(&path).unwrap();
+            let (loaded, version) = loaded.unwrap();
+            assert_eq!(version, FORMAT_VERSION, "{}", name);
+            assert_eq!(loaded.analyzer, analyzer, "{}", name);
+            assert_eq!(loaded.urls, index.urls, "{}", name);
+            assert_eq!(loaded.term_tf, index.term_tf, "{}", name);
+            assert_eq!(hit_urls(&loaded, "join"), ["https://example.com/a"], "{}", name);
+            assert_eq!(hit_urls(&loaded, "検索"), ["https://example.com/b"], "{}", name);
+        }
+    }
+
+    fn build(pages: &[CrawlResult]) -> IndexWithTf {
+        IndexWithTf::build_with(pages, &StandardAnalyzer::default())
+    }
+
+    /// Hits for `query` by URL, so indexes with different doc IDs compare equal.
+    fn scores(index: &IndexWithTf, query: &str) -> Vec<(String, f64)> {
+        let mut hits = index.search_ranked(query);
+        hits.sort_by(|a, b| a.0.cmp(&b.0));
+        hits
+    }
+
+    fn assert_same_scores(left: &IndexWithTf, right: &IndexWithTf, query: &str) {
+        let (left, right) = (scores(left, query), scores(right, query));
+        assert_eq!(left.len(), right.len(), "{:?} vs {:?}", left, right);
+        for ((left_url, left_score), (right_url, right_score)) in left.iter().zip(&right) {
+            assert_eq!(left_url, right_url);
+            assert!((left_score - right_score).abs() < 1e-9, "{}: {} vs {}", left_url, left_score, right_score);
+        }
+    }
+
+    #[test]
+    fn add_document_indexes_into_an_existing_index() {
+        let mut index = build(&[page("https://a.example/", "Crawler", "a rust crawler")]);
+        index.add_document("https://b.example/", "Runtime", "", "a tokio runtime");
+        assert_eq!(index.doc_count, 2);
+        assert_eq!(hit_urls(&index, "tokio"), ["https://b.example/"]);
+        let built = build(&[
+            page("https://a.example/", "Crawler", "a rust crawler"),
+            page("https://b.example/", "Runtime", "a tokio runtime"),
+        ]);
+        assert_same_scores(&index, &built, "a crawler runtime");
+    }
+
+    #[test]
+    fn re_adding_a_url_replaces_its_previous_text() {
+        let mut crawled = page("https://a.example/", "Crawler", "a rust crawler");
+        crawled.description = "Crawls the web".to_string();
+        crawled.last_modified = Some(Utc::now());
+        let mut index = build(&[crawled.clone(), page("https://b.example/", "Other", "rust elsewhere")]);
+        index.add_document("https://a.example/", "Runtime", "", "a tokio runtime");
+        assert_eq!(index.doc_count, 2);
+        assert!(hit_urls(&index, "crawler").is_empty());
+        assert_eq!(hit_urls(&index, "rust"), ["https://b.example/"]);
+        assert_eq!(hit_urls(&index, "tokio"), ["https://a.example/"]);
+        let meta = &index.docs[&index.doc_id("https://a.example/").unwrap()];
+        assert_eq!(meta.title, "Runtime");
+        assert_eq!(meta.text, "a tokio runtime");
+        assert_eq!(meta.description, crawled.description);
+        assert_eq!(meta.last_modified, crawled.last_modified);
+    }
+
+    #[test]
+    fn add_document_accepts_an_empty_body() {
+        let mut index = build(&[page("https://a.example/", "Crawler", "a rust crawler")]);
+        index.add_document("https://b.example/", "", "", "");
+        assert_eq!(index.doc_count, 2);
+        assert_eq!(index.doc_lengths[&index.doc_id("https://b.example/").unwrap()], 0);
+        assert_eq!(hit_urls(&index, "rust"), ["https://a.example/"]);
+    }
+
+    #[test]
+    fn remove_document_cleans_every_term_map() {
+        let pages = [
+            page("https://a.example/", "Crawler", "a rust crawler"),
+            page("https://b.example/", "Runtime", "a tokio runtime in rust"),
+        ];
+        let mut index = build(&pages);
+        let id = index.doc_id("https://a.example/").unwrap();
+        assert!(index.remove_document("https://a.example/"));
+        assert_eq!(index.doc_count, 1);
+        assert_eq!(index.doc_id("https://a.example/"), None);
+        assert!(!index.term_tf.contains_key("crawler"));
+        assert!(!index.docs.contains_key(&id) && !index.doc_lengths.contains_key(&id));
+        let postings = index.term_tf.values().chain(index.fields.values().flat_map(HashMap::values));
+        for docs in postings {
+            assert!(!docs.is_empty() && !docs.contains_key(&id));
+        }
+        assert!(index.positions.values().all(|docs| !docs.is_empty() && !docs.contains_key(&id)));
+        // IDF now counts one document, as if it had been built without the page.
+        assert_same_scores(&index, &build(&pages[1..]), "a rust tokio runtime");
+    }
+
+    #[test]
+    fn removing_an_unknown_url_leaves_the_index_untouched() {
+        let mut index = build(&[page("https://a.example/", "Crawler", "a rust crawler")]);
+        let (urls, term_tf, doc_count) = (index.urls.clone(), index.term_tf.clone(), index.doc_count);
+        assert!(!index.remove_document("https://b.example/"));
+        assert_eq!((index.urls, index.term_tf, index.doc_count), (urls, term_tf, doc_count));
+    }
+
+    #[test]
+    fn merge_unions_overlapping_vocabularies() {
+        let mut index = build(&[page("https://a.example/", "Crawler", "a rust crawler")]);
+        index.merge(build(&[page("https://b.example/", "Runtime", "a tokio runtime in rust")]));
+        assert_eq!(index.doc_count, 2);
+        let built = build(&[
+            page("https://a.example/", "Crawler", "a rust crawler"),
+            page("https://b.example/", "Runtime", "a tokio runtime in rust"),
+        ]);
+        assert_same_scores(&index, &built, "a rust crawler tokio runtime");
+    }
+
+    #[test]
+    fn merge_counts_a_shared_url_once_with_the_other_version() {
+        let mut index = build(&[
+            page("https://a.example/", "Crawler", "a rust crawler"),
+            page("https://b.example/", "Other", "rust elsewhere"),
+        ]);
+        index.merge(build(&[page("https://a.example/", "Runtime", "a tokio runtime")]));
+        assert_eq!(index.doc_count, 2);
+        let id = index.doc_id("https://a.example/").unwrap();
+        assert_eq!(index.term_tf["a"][&id], 1);
+        assert!(hit_urls(&index, "crawler").is_empty());
+        assert_eq!(hit_urls(&index, "tokio"), ["https://a.example/"]);
+    }
+
+    #[test]
+    fn merge_with_an_empty_index() {
+        let built = build(&[page("https://a.example/", "Crawler", "a rust crawler")]);
+        let mut index = built.clone();
+        index.merge(IndexWithTf::default());
+        assert_eq!(index.doc_count, 1);
+        assert_same_scores(&index, &built, "rust crawler");
+        let mut empty = IndexWithTf::default();
+        empty.merge(built.clone());
+        assert_eq!(empty.doc_count, 1);
+        assert_same_scores(&empty, &built, "rust crawler");
+    }
+
+    #[test]
+    fn bm25_prefers_the_short_document_that_tfidf_ranks_second() {
+        let long_body = format!("rust rust rust {}", ["word"; 27].join(" "));
+        let index = build(&[
+            page("https://long.example/", "", &long_body),
+            page("https://short.example/", "", "rust guide"),
+            page("https://other.example/", "", "tokio async runtime tasks"),
+        ]);
+        assert_eq!(index.avg_doc_len, 12.0);
+        let ranked = |ranker| {
+            let options = SearchOptions { ranker, ..Default::default() };
+            index.search_ranked_with("rust", &options)
+        };
+        let close = |hits: &[(String, f64)], expected: [(&str, f64); 2]| {
+            assert_eq!(hits.len(), 2);
+            for ((url, score), (expected_url, expected_score)) in hits.iter().zip(expected) {
+                assert_eq!(url, expected_url);
+                assert!((score - expected_score).abs() < 1e-9, "{}: {} vs {}", url, score, expected_score);
+            }
+        };
+        // TF-IDF: tf × (ln((N + 1) / (df + 1)) + 1) with N = 3, df = 2.
+        let idf = (4.0f64 / 3.0).ln() + 1.0;
+        close(&ranked(Ranker::Tfidf), [("https://long.example/", 3.0 * idf), ("https://short.example/", idf)]);
+        // BM25: idf × tf (k1 + 1) / (tf + k1 (1 - b + b len / avg)), k1 = 1.2, b = 0.75,
+        // with lengths 30 and 2 against an average of 12.
+        let idf = ((3.0 - 2.0 + 0.5) / (2.0 + 0.5) + 1.0f64).ln();
+        let long = idf * 3.0 * 2.2 / (3.0 + 1.2 * (0.25 + 0.75 * 30.0 / 12.0));
+        let short = idf * 2.2 / (1.0 + 1.2 * (0.25 + 0.75 * 2.0 / 12.0));
+        close(&ranked(Ranker::Bm25), [("https://short.example/", short), ("https://long.example/", long)]);
+    }
+
+    #[test]
+    fn title_match_outranks_a_single_body_occurrence() {
+        let index = build(&[
+            page("https://a.example/footer", "Runtime notes", "tasks and timers, built with tokio"),
+            page("https://a.example/guide", "Tokio", "tasks and timers"),
+            page("https://a.example/other", "Other", "unrelated page"),
+        ]);
+        assert_eq!(hit_urls(&index, "tokio"), ["https://a.example/guide", "https://a.example/footer"]);
+        let mut options = SearchOptions::default();
+        options.boosts.title = 0.0;
+        let hits = index.search_ranked_with("tokio", &options);
+        assert_eq!(hits.first().map(|(url, _)| url.as_str()), Some("https://a.example/footer"));
+    }
+
+    #[test]
+    fn gzipped_indexes_round_trip() {
+        let (index, _) = load_fixture("v2.json");
+        for name in ["gzip.json.gz", "gzip.bin.gz"] {
+            let path = temp_file(name);
+            save_index_with_tf(&index, &path).unwrap();
+            let written = fs::read(&path).unwrap();
+            let reloaded = load_index_with_tf(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(written.starts_with(&GZIP_MAGIC), "{}", name);
+            let reloaded = reloaded.unwrap();
+            assert_eq!(reloaded.urls, index.urls, "{}", name);
+            assert_eq!(reloaded.term_tf, index.term_tf, "{}", name);
+            assert_sample(&reloaded);
+        }
+    }
+
+    #[test]
+    fn corrupted_gzip_is_an_error() {
+        let (index, _) = load_fixture("v2.json");
+        let path = temp_file("corrupt.json.gz");
+        save_index_with_tf(&index, &path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        let truncated = bytes[..bytes.len() / 2].to_vec();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        for contents in [truncated, bytes] {
+            fs::write(&path, contents).unwrap();
+            assert!(load_index_with_tf(&path).is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stemmed_index_matches_other_word_forms() {
+        let pages = [
+            page("https://a.example/crawler", "", "how the crawler crawled pages"),
+            page("https://a.example/index", "", "building an index"),
+        ];
+        let index = IndexWithTf::build_with(&pages, &StandardAnalyzer::default().with_stemming(Some("en".to_string())));
+        assert_eq!(hit_urls(&index, "crawling page"), ["https://a.example/crawler"]);
+        assert_eq!(hit_urls(&index, "indexes"), ["https://a.example/index"]);
+        assert!(hit_urls(&build(&pages), "indexes").is_empty());
+    }
+
+    #[test]
+    fn index_stemmed_in_an_unknown_language_fails_to_load() {
+        let pages = [page("https://a.example/", "", "crawled pages")];
+        let index = IndexWithTf::build_with(&pages, &StandardAnalyzer::default().with_stemming(Some("xx".to_string())));
+        let path = temp_file("unknown-stemmer.json");
+        save_index_with_tf(&index, &path).unwrap();
+        let loaded = load_index_with_tf(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.err().unwrap().to_string().contains("stemming"));
+    }
+
+    #[test]
+    fn prune_keeps_terms_exactly_at_the_bounds() {
+        // Root URLs, so the URL field adds no terms. Document frequencies:
+        // everywhere 4, most 3, pair 2, typo 1.
+        let mut index = build(&[
+            page("https://one.example/", "", "everywhere most pair typo"),
+            page("https://two.example/", "", "everywhere most pair"),
+            page("https://three.example/", "", "everywhere most"),
+            page("https://four.example/", "", "everywhere"),
+        ]);
+        let lengths = index.doc_lengths.clone();
+        let stats = index.prune(&PruneOptions {
+            min_df: 2,
+            max_df_ratio: Some(0.75),
+        });
+        // min_df is inclusive (df >= 2 stays), max_df_ratio too (df <= 0.75 × 4 stays).
+        assert_eq!((stats.rare, stats.common), (1, 1));
+        let mut terms: Vec<&str> = index.term_tf.keys().map(String::as_str).collect();
+        terms.sort_unstable();
+        assert_eq!(terms, ["most", "pair"]);
+        assert!(index.positions.keys().all(|term| term == "most" || term == "pair"));
+        assert_eq!(index.doc_count, 4);
+        assert_eq!(index.doc_lengths, lengths);
+    }
+
+    #[test]
+    fn default_prune_options_keep_everything() {
+        let mut index = build(&[page("https://one.example/", "Title", "a single typo")]);
+        let terms = index.term_tf.clone();
+        let stats = index.prune(&PruneOptions::default());
+        assert_eq!((stats.rare, stats.common), (0, 0));
+        assert_eq!(index.term_tf, terms);
+    }
+
+    /// Passes `left` bytes through to `inner`, then fails every write.
+    struct FailAfter<W> {
+        inner: W,
+        left: usize,
+    }
+
+    impl<W: Write> Write for FailAfter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            let n = buf.len().min(self.left);
+            self.left -= n;
+            self.inner.write(&buf[..n])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn failed_save_leaves_the_previous_file_intact() {
+        let (index, _) = load_fixture("v2.json");
+        let path = temp_file("atomic.json");
+        fs::write(&path, "previous contents").unwrap();
+        for format in [IndexFormat::Json, IndexFormat::Bin] {
+            let result = write_atomic(&path, |writer| {
+                write_index_with_tf(&index, FailAfter { inner: writer, left: 100 }, format)
+            });
+            assert!(result.unwrap_err().to_string().contains("disk full"));
+            assert_eq!(fs::read_to_string(&path).unwrap(), "previous contents");
+            assert!(!temp_path(&path).exists());
+        }
+        save_index_with_tf(&index, &path).unwrap();
+        let saved = load_index_with_tf(&path);
+        fs::remove_file(&path).unwrap();
+        assert_sample(&saved.unwrap());
+        assert!(!temp_path(&path).exists());
+    }
+
+    /// `n` distinct documents all matching "common", with tf cycling through 1..=7
+    /// so most scores tie with many others.
+    fn synthetic_index(n: usize) -> IndexWithTf {
+        let pages: Vec<CrawlResult> = (0..n)
+            .map(|i| {
+                let body = format!("{} filler{} doc{}", ["common"; 7][..i % 7 + 1].join(" "), i % 13, i);
+                page(&format!("https://s{}.example/", i), "", &body)
+            })
+            .collect();
+        build(&pages)
+    }
+
+    #[test]
+    fn top_k_matches_the_fully_sorted_results() {
+        let index = synthetic_index(500);
+        let all = index.search_ranked("common filler3");
+        assert_eq!(all.len(), 500);
+        for k in [0, 1, 10, 71, 499, 500, 600, usize::MAX] {
+            assert_eq!(index.search_ranked_top_k("common filler3", k), all[..k.min(all.len())], "k = {}", k);
+        }
+        for offset in [0, 5, 495, 1_000_000_000_000, usize::MAX] {
+            let options = SearchOptions {
+                limit: Some(10),
+                offset,
+                ..Default::default()
+            };
+            let page = index.search_ranked_with("common filler3", &options);
+            let start = offset.min(all.len());
+            assert_eq!(page, all[start..(start + 10).min(all.len())], "offset = {}", offset);
+        }
+    }
+
+    /// Timing comparison on 100k matching documents: `cargo test -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn top_k_is_faster_than_a_full_sort() {
+        let index = synthetic_index(100_000);
+        let time = |search: &dyn Fn() -> usize| {
+            let started = std::time::Instant::now();
+            for _ in 0..5 {
+                std::hint::black_box(search());
+            }
+            started.elapsed() / 5
+        };
+        let full = time(&|| index.search_ranked("common").len());
+        let top = time(&|| index.search_ranked_top_k("common", 10).len());
+        println!("100k matches: full sort {:?}, top 10 {:?}", full, top);
+        assert!(top < full);
+    }
+
+    /// The IDF caches hold exactly the values computed from the current postings.
+    fn assert_idf_cache_fresh(index: &IndexWithTf) {
+        let n = index.doc_count as f64;
+        let check = |postings: &Postings, cache: &HashMap<String, TermIdf>| {
+            assert_eq!(cache.len(), postings.len());
+            for (term, docs) in postings {
+                let df = docs.len() as f64;
+                let cached = cache[term];
+                assert_eq!(cached.tfidf.to_bits(), (((n + 1.0) / (df + 1.0)).ln() + 1.0).to_bits(), "{}", term);
+                assert_eq!(cached.bm25.to_bits(), ((n - df + 0.5) / (df + 0.5) + 1.0).ln().to_bits(), "{}", term);
+            }
+        };
+        check(&index.term_tf, &index.idf);
+        for (field, postings) in &index.fields {
+            check(postings, &index.field_idf[field]);
+        }
+    }
+
+    #[test]
+    fn cached_idf_gives_bit_identical_scores() {
+        let mut index = build(&[
+            page("https://one.example/", "", "rust rust crawler"),
+            page("https://two.example/", "", "rust tokio"),
+            page("https://three.example/", "", "tokio tokio tokio"),
+        ]);
+        assert_idf_cache_fresh(&index);
+        let body_only = SearchOptions {
+            boosts: FieldBoosts {
+                title: 0.0,
+                headings: 0.0,
+                url: 0.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let tfidf = |tf: f64, df: f64, n: f64| tf * (((n + 1.0) / (df + 1.0)).ln() + 1.0);
+        let hits = index.search_ranked_with("tokio", &body_only);
+        assert_eq!(hits[0], ("https://three.example/".to_string(), tfidf(3.0, 2.0, 3.0)));
+        assert_eq!(hits[1], ("https://two.example/".to_string(), tfidf(1.0, 2.0, 3.0)));
+
+        index.add_document("https://four.example/", "", "", "tokio runtime");
+        assert_idf_cache_fresh(&index);
+        let hits = index.search_ranked_with("tokio", &body_only);
+        assert_eq!(hits[0], ("https://three.example/".to_string(), tfidf(3.0, 3.0, 4.0)));
+
+        index.remove_document("https://three.example/");
+        index.remove_document("https://two.example/");
+        assert_idf_cache_fresh(&index);
+        let hits = index.search_ranked_with("rust", &body_only);
+        assert_eq!(hits, [("https://one.example/".to_string(), tfidf(2.0, 1.0, 2.0))]);
+    }
+
+    #[test]
+    fn match_modes_select_different_documents() {
+        let index = build(&[
+            page("https://all.example/", "", "rust tokio async"),
+            page("https://two.example/", "", "rust tokio"),
+            page("https://one.example/", "", "rust rust rust rust rust"),
+            page("https://none.example/", "", "python"),
+        ]);
+        let matching = |match_mode| {
+            let options = SearchOptions { match_mode, ..Default::default() };
+            let mut urls: Vec<String> = index.search_ranked_with("rust tokio async", &options).into_iter().map(|(url, _)| url).collect();
+            urls.sort();
+            urls
+        };
+        assert_eq!(matching(MatchMode::Any), ["https://all.example/", "https://one.example/", "https://two.example/"]);
+        assert_eq!(matching(MatchMode::MinShouldMatch(2)), ["https://all.example/", "https://two.example/"]);
+        assert_eq!(matching(MatchMode::All), ["https://all.example/"]);
+        // More than the query has means all of them.
+        assert_eq!(matching(MatchMode::MinShouldMatch(5)), ["https://all.example/"]);
+    }
+
+    #[test]
+    fn leading_minus_excludes_documents() {
+        let index = build(&[
+            page("https://engine.example/", "", "rust game engine"),
+            page("https://lang.example/", "", "rust language"),
+            page("https://art.example/", "", "state-of-the-art rust"),
+        ]);
+        let mut hits = hit_urls(&index, "rust -game");
+        hits.sort();
+        assert_eq!(hits, ["https://art.example/", "https://lang.example/"]);
+        assert!(hit_urls(&index, "-game").is_empty());
+        assert!(hit_urls(&index, "-game -language").is_empty());
+    }
+
+    #[test]
+    fn intra_word_hyphen_is_not_an_exclusion() {
+        let analyzer = StandardAnalyzer::default();
+        let parsed = ParsedQuery::parse_with("state-of-the-art -game", &analyzer);
+        assert_eq!(parsed.terms, ["state-of-the-art"]);
+        assert_eq!(parsed.excluded, ["game"]);
+        let parsed = ParsedQuery::parse_with("tokio-rs", &analyzer);
+        assert_eq!(parsed.terms, ["tokio-rs"]);
+        assert!(parsed.excluded.is_empty());
+        let index = build(&[page("https://art.example/", "", "state-of-the-art rust")]);
+        assert_eq!(hit_urls(&index, "state-of-the-art"), ["https://art.example/"]);
+    }
+
+    #[test]
+    fn explanation_sums_to_the_hit_score() {
+        let index = build(&[
+            page("https://a.example/errors", "Error handling", "error handling in rust: the error type"),
+            page("https://b.example/", "Rust", "rust handling of errors, and an error far away from the other word"),
+            page("https://c.example/", "Other", "nothing relevant"),
+        ]);
+        for query in ["error handling", "rust", "\"error handling\" rust", "title:rust error"] {
+            for ranker in [Ranker::Tfidf, Ranker::Bm25, Ranker::Cosine] {
+                let options = SearchOptions { ranker, ..Default::default() };
+                let hits = index.search_ranked_with(query, &options);
+                assert!(!hits.is_empty(), "{}", query);
+                for (url, score) in hits {
+                    let explanation = index.explain_with(query, &url, &options).unwrap();
+                    let sum: f64 = explanation.contributions.iter().map(|part| part.score).sum();
+                    let total = sum * explanation.recency_factor * explanation.proximity_factor;
+                    assert_eq!(explanation.score, score, "{} {:?} {}", query, ranker, url);
+                    assert!((total - score).abs() < 1e-12, "{} {:?} {}", query, ranker, url);
+                }
+            }
+        }
+        assert!(index.explain("error", "https://c.example/").is_none());
+        assert!(index.explain("error", "https://unknown.example/").is_none());
+    }
+
+    #[test]
+    fn tied_scores_come_back_in_url_order() {
+        // Each build gets fresh hash seeds, so iteration order differs between runs.
+        for _ in 0..50 {
+            let index = build(&[
+                page("https://c.example/", "", "rust one"),
+                page("https://a.example/", "", "rust two"),
+                page("https://b.example/", "", "six rust"),
+                page("https://d.example/", "", "rust rust"),
+            ]);
+            let expected = ["https://d.example/", "https://a.example/", "https://b.example/", "https://c.example/"];
+            assert_eq!(hit_urls(&index, "rust"), expected);
+            let top: Vec<String> = index.search_ranked_top_k("rust", 3).into_iter().map(|(url, _)| url).collect();
+            assert_eq!(top, expected[..3]);
+        }
+    }
+
+    #[test]
+    fn scores_within_epsilon_count_as_tied() {
+        let hit = |score, url| (score, url);
+        assert_eq!(cmp_hits(hit(1.0, "https://b/"), hit(1.0 + 1e-12, "https://a/")), Ordering::Less);
+        assert_eq!(cmp_hits(hit(1.0 + 1e-12, "https://a/"), hit(1.0, "https://b/")), Ordering::Greater);
+        assert_eq!(cmp_hits(hit(1.0, "https://a/"), hit(1.1, "https://b/")), Ordering::Less);
+    }
+
+    #[test]
+    fn repeated_urls_count_as_one_document() {
+        let index = build(&[
+            page("https://a.example/", "", "rust rust crawler"),
+            page("https://b.example/", "", "tokio"),
+            page("https://a.example/", "", "rust rust crawler"),
+            page("https://a.example/", "", "a later copy"),
+        ]);
+        assert_eq!(index.doc_count, 2);
+        let id = index.doc_id("https://a.example/").unwrap();
+        // Indexed once, from the first entry.
+        assert_eq!(index.term_tf["rust"][&id], 2);
+        assert_eq!(index.term_tf["crawler"][&id], 1);
+        assert!(!index.term_tf.contains_key("later"));
+        assert_eq!(index.doc_lengths[&id], 3);
+    }
+
+    #[test]
+    fn legacy_inverted_index_counts_unique_urls() {
+        let urls = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect();
+        let mut legacy = InvertedIndex::new();
+        legacy.insert("rust".to_string(), urls(&["https://a.example/", "https://b.example/"]));
+        legacy.insert("tokio".to_string(), urls(&["https://b.example/"]));
+        let index = IndexWithTf::from_inverted(legacy);
+        assert_eq!(index.doc_count, 2);
+        let id = index.doc_id("https://b.example/").unwrap();
+        assert_eq!(index.term_tf["rust"][&id], 1);
+        assert_eq!(index.term_tf["tokio"][&id], 1);
+    }
+
+    #[test]
+    fn cosine_scores_are_in_unit_range_and_favor_the_query_text() {
+        let mut index = build(&[
+            page("https://exact.example/", "", "async rust runtime"),
+            page("https://long.example/", "", "async rust runtime with many other words about crawling and indexing"),
+            page("https://repeat.example/", "", "rust rust rust rust async"),
+            page("https://other.example/", "", "python scripts"),
+        ]);
+        let cosine = SearchOptions {
+            ranker: Ranker::Cosine,
+            proximity: 0.0,
+            ..Default::default()
+        };
+        let check = |index: &IndexWithTf| {
+            let hits = index.search_ranked_with("async rust runtime", &cosine);
+            assert_eq!(hits[0].0, "https://exact.example/");
+            assert!((hits[0].1 - 1.0).abs() < 1e-9, "{}", hits[0].1);
+            assert!(hits.iter().all(|(_, score)| (0.0..=1.0 + 1e-9).contains(score)), "{:?}", hits);
+        };
+        check(&index);
+        // Norms follow incremental changes: these shift every IDF.
+        index.add_document("https://new.example/", "", "", "rust everywhere");
+        check(&index);
+        index.remove_document("https://repeat.example/");
+        check(&index);
+    }
+
+    #[test]
+    fn adjacent_query_terms_rank_higher() {
+        let filler = ["word"; 20].join(" ");
+        // Same terms, counts and length; only the distance differs.
+        let index = build(&[
+            page("https://apart.example/", "", &format!("error {} handling", filler)),
+            page("https://adjacent.example/", "", &format!("error handling {}", filler)),
+            page("https://both.example/", "", &format!("error {} handling error handling", filler)),
+        ]);
+        let hits = hit_urls(&index, "error handling");
+        let rank = |url: &str| hits.iter().position(|hit| hit == url).unwrap();
+        assert!(rank("https://adjacent.example/") < rank("https://apart.example/"), "{:?}", hits);
+        // The shortest window counts: 2 words, the full boost. Apart, 22 words.
+        let factor = |url| index.explain("error handling", url).unwrap().proximity_factor;
+        assert_eq!(factor("https://adjacent.example/"), 1.0 + DEFAULT_PROXIMITY_BOOST);
+        assert_eq!(factor("https://both.example/"), 1.0 + DEFAULT_PROXIMITY_BOOST);
+        assert_eq!(factor("https://apart.example/"), 1.0 + DEFAULT_PROXIMITY_BOOST * 2.0 / 22.0);
+        assert_eq!(index.explain("error", "https://adjacent.example/").unwrap().proximity_factor, 1.0);
+    }
+
+    /// `n` pages of varied text, enough for several build batches.
+    fn varied_pages(n: usize) -> Vec<CrawlResult> {
+        let words = ["rust", "tokio", "async", "crawler", "index", "query", "search", "engine", "page", "link"];
+        (0..n)
+            .map(|i| {
+                let body: Vec<&str> = (0..40).map(|j| words[(i * 7 + j * j) % words.len()]).collect();
+                let title = format!("Page {} {}", i, words[i % words.len()]);
+                page(&format!("https://a.example/{}", i), &title, &format!("{} doc{}", body.join(" "), i))
+            })
+            .collect()
+    }
+
+    fn build_on_threads(pages: &[CrawlResult], threads: usize) -> IndexWithTf {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        pool.install(|| build(pages))
+    }
+
+    #[test]
+    fn parallel_build_equals_a_serial_build() {
+        let pages = varied_pages(3 * BUILD_BATCH + 17);
+        let serial = build_on_threads(&pages, 1);
+        let parallel = build_on_threads(&pages, 4);
+        // JSON values compare maps by content, whatever their iteration order.
+        assert_eq!(serde_json::to_value(&parallel).unwrap(), serde_json::to_value(&serial).unwrap());
+        assert_eq!(parallel.urls, serial.urls);
+    }
+
+    /// Build speedup on a synthetic corpus: `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn parallel_build_is_faster() {
+        let pages = varied_pages(20_000);
+        let time = |threads| {
+            let started = std::time::Instant::now();
+            std::hint::black_box(build_on_threads(&pages, threads));
+            started.elapsed()
+        };
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let (serial, parallel) = (time(1), time(threads));
+        println!("20k documents: 1 thread {:?}, {} threads {:?}", serial, threads, parallel);
+        if threads > 1 {
+            assert!(parallel < serial);
+        }
+    }
+
+    #[test]
+    fn boolean_queries_filter_before_ranking() {
+        let index = build(&[
+            page("https://beginner.example/", "", "rust tokio beginner guide to error handling"),
+            page("https://async.example/", "", "rust async error handling"),
+            page("https://go.example/", "", "go async error handling"),
+            page("https://tokio.example/", "", "rust tokio handling of every error"),
+        ]);
+        let sorted = |query: &str| {
+            let mut urls = hit_urls(&index, query);
+            urls.sort();
+            urls
+        };
+        let query = r#"rust AND (tokio OR async) NOT beginner "error handling""#;
+        assert_eq!(sorted(query), ["https://async.example/"]);
+        let both = ["https://async.example/", "https://tokio.example/"];
+        assert_eq!(sorted("rust AND (tokio OR async) NOT beginner"), both);
+        assert_eq!(sorted("(async OR tokio) -rust"), ["https://go.example/"]);
+        assert_eq!(sorted("async OR tokio -rust"), ["https://async.example/", "https://go.example/"]);
+        // A query of only exclusions has nothing to rank on.
+        assert!(sorted("NOT rust").is_empty());
+        // Without operators every term is optional and ranked, as before.
+        let plain = ParsedQuery::parse_with("rust beginner", index.analyzer());
+        assert_eq!(plain, ParsedQuery::parse_plain("rust beginner", index.analyzer()));
+        assert_eq!(hit_urls(&index, "rust beginner")[0], "https://beginner.example/");
+        assert_eq!(hit_urls(&index, "rust beginner").len(), 3);
+        // Malformed syntax is an error for the API, but plain words for the library.
+        let error = ParsedQuery::try_parse_with("rust AND (tokio", index.analyzer()).unwrap_err();
+        assert_eq!(error.position, 9);
+        assert_eq!(sorted("rust AND (tokio").len(), 3);
+    }
+
+    #[test]
+    fn field_prefixes_scope_terms_and_unknown_ones_are_literal() {
+        let index = build(&[
+            page("https://a.example/blog/intro", "Tokio guide", "an async runtime"),
+            page("https://b.example/blog/rust", "Rust", "tokio makes async easy"),
+            page("https://c.example/news/tokio-release.html", "Tokio", "async news"),
+            page("https://d.example/", "Conferences", "notes from re:invent"),
+        ]);
+        assert_eq!(hit_urls(&index, "title:tokio url:blog async"), ["https://a.example/blog/intro"]);
+        assert_eq!(hit_urls(&index, r#"title:"tokio guide""#), ["https://a.example/blog/intro"]);
+        assert_eq!(hit_urls(&index, "body:tokio"), ["https://b.example/blog/rust"]);
+        assert_eq!(hit_urls(&index, "url:release"), ["https://c.example/news/tokio-release.html"]);
+        let mut titled = hit_urls(&index, "title:tokio");
+        titled.sort();
+        assert_eq!(titled, ["https://a.example/blog/intro", "https://c.example/news/tokio-release.html"]);
+        assert_eq!(hit_urls(&index, "title:tokio -url:news"), ["https://a.example/blog/intro"]);
+        assert_eq!(hit_urls(&index, "title:tokio AND NOT url:news"), ["https://a.example/blog/intro"]);
+        // `re` isn't a field, so this is searched as text rather than rejected.
+        assert!(ParsedQuery::try_parse_with("re:invent", index.analyzer()).is_ok());
+        assert_eq!(hit_urls(&index, "re:invent"), ["https://d.example/"]);
+        assert!(hit_urls(&index, "author:tokio").is_empty());
+    }
+
+    #[test]
+    fn prefix_option_completes_the_last_word() {
+        let index = build(&[
+            page("https://token.example/", "", "rust token parsing"),
+            page("https://tokio.example/", "", "rust tokio runtime"),
+            page("https://tomato.example/", "", "go tomato soup"),
+        ]);
+        let prefix = SearchOptions {
+            prefix: true,
+            ..Default::default()
+        };
+        let mut urls: Vec<String> =
+            index.search_ranked_with("rust tok", &prefix).into_iter().map(|(url, _)| url).collect();
+        urls.sort();
+        assert_eq!(urls, ["https://token.example/", "https://tokio.example/"]);
+        assert!(index.search_ranked_with("tok rust", &prefix).iter().all(|(url, _)| url != "https://tomato.example/"));
+        // Only the last word is a prefix; without the option it must match exactly.
+        assert_eq!(hit_urls(&index, "tok"), Vec::<String>::new());
+        assert_eq!(index.complete_prefix("to"), ["token", "tokio", "tomato"]);
+        assert_eq!(index.complete_prefix("tok"), ["token", "tokio"]);
+        assert!(index.complete_prefix("zzz").is_empty());
+        let pages: Vec<CrawlResult> =
+            (0..60).map(|n| page(&format!("https://{}.example/", n), "", &format!("word{}", n))).collect();
+        let many = build(&pages);
+        assert_eq!(many.complete_prefix("word").len(), MAX_PREFIX_EXPANSIONS);
+    }
+
+    #[test]
+    fn differently_configured_analyzers_build_different_indexes() {
+        let pages = [
+            page("https://a.example/", "", "The crawler is crawling JoinSet pages"),
+            page("https://b.example/", "", "Crawled pages of the Tokio runtime"),
+        ];
+        let plain = StandardAnalyzer::default();
+        let english = StandardAnalyzer::with_stopwords(Stopwords::builtin(StopwordLanguage::English))
+            .with_stemming(Some("en".to_string()))
+            .with_split_identifiers(true);
+        let plain_index = IndexWithTf::build_with(&pages, &plain);
+        let english_index = IndexWithTf::build_with(&pages, &english);
+        let vocabulary = |index: &IndexWithTf| {
+            let mut terms: Vec<String> = index.term_tf.keys().cloned().collect();
+            terms.sort_unstable();
+            terms
+        };
+        assert_eq!(
+            vocabulary(&plain_index),
+            ["crawled", "crawler", "crawling", "is", "joinset", "of", "pages", "runtime", "the", "tokio"]
+        );
+        let stemmed = ["crawl", "crawler", "join", "joinset", "page", "runtim", "set", "tokio"];
+        assert_eq!(vocabulary(&english_index), stemmed);
+        // Each index records its analyzer, so queries are analyzed the way documents were.
+        assert_eq!(plain_index.analyzer, AnalyzerConfig::from(plain));
+        assert_eq!(english_index.analyzer, AnalyzerConfig::from(english));
+        assert_eq!(hit_urls(&plain_index, "crawl"), Vec::<String>::new());
+        assert_eq!(hit_urls(&english_index, "crawls").len(), 2);
+        assert_eq!(hit_urls(&english_index, "join"), ["https://a.example/"]);
+
+        let path = temp_file("analyzer-roundtrip.bin");
+        save_index_with_tf(&english_index, &path).unwrap();
+        let loaded = load_index_with_tf(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.analyzer, english_index.analyzer);
+        assert_eq!(hit_urls(&loaded, "crawls").len(), 2);
+    }
 }
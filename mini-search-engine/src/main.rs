@@ -1,15 +1,40 @@
 //! RustySearch: crawl, index, serve.
 
+mod auth;
+mod codec;
 mod crawler;
+mod graph;
 mod index;
+mod openapi;
+mod query;
+mod querylog;
+mod ratelimit;
 mod search;
+mod snippet;
+mod spell;
+mod sqlite;
+mod stopwords;
+mod synonyms;
 mod tokenize;
 
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::path::Path;
+use std::future::{Future, IntoFuture};
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use axum_server::tls_rustls::RustlsConfig;
 use clap::{Parser, Subcommand};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::services::ServeDir;
+use tower_http::set_header::SetResponseHeader;
+use tower_http::trace::TraceLayer;
+use tracing_subscriber::EnvFilter;
 
 const DEFAULT_INDEX_PATH: &str = "index.json";
 
@@ -19,6 +44,29 @@ const DEFAULT_INDEX_PATH: &str = "index.json";
 struct Cli {
     #[command(subcommand)]
     command: Command,
+
+    /// Log level or filter such as `debug` or `mini_search_engine=debug` (default: RUST_LOG, else `info`).
+    #[arg(long, global = true, value_name = "FILTER")]
+    log_level: Option<String>,
+
+    /// Write logs as JSON lines instead of plain text.
+    #[arg(long, global = true)]
+    log_json: bool,
+}
+
+/// Send `tracing` events to stderr, so they never mix with command output on stdout.
+fn init_logging(level: Option<&str>, json: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level).map_err(|e| format!("invalid --log-level {:?}: {}", level, e))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    if json {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+    Ok(())
 }
 
 #[derive(Subcommand)]
@@ -40,72 +88,2197 @@ enum Command {
         /// Output index file path.
         #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
         output: String,
+
+        /// Index storage: a single file, or a SQLite database.
+        #[arg(long, value_enum, default_value_t = Backend::File)]
+        backend: Backend,
+
+        /// Index file format (default: from the output extension, `.bin` = binary).
+        /// A trailing `.gz` gzips the output in either format.
+        #[arg(long, value_enum)]
+        format: Option<index::IndexFormat>,
+
+        /// Only crawl URLs whose path starts with this prefix (repeatable).
+        #[arg(long = "path-prefix")]
+        path_prefixes: Vec<String>,
+
+        /// Match --path-prefix case-insensitively.
+        #[arg(long)]
+        path_prefix_ignore_case: bool,
+
+        /// Save a partial index to the output path every N pages (0 = only at the end).
+        #[arg(long, default_value_t = 0)]
+        checkpoint_every: usize,
+
+        /// Write raw per-URL fetch metrics (JSON) to this path.
+        #[arg(long)]
+        metrics_out: Option<String>,
+
+        /// Also write the crawled documents as JSONL to this path.
+        #[arg(long)]
+        dump: Option<String>,
+
+        #[command(flatten)]
+        build: BuildArgs,
     },
 
-    /// Load index and start search API.
-    Serve {
+    /// Rebuild an index from a JSONL document dump, streaming it one document at a time.
+    Reindex {
+        /// JSONL document dump written by `crawl --dump`.
+        #[arg(long, short)]
+        input: String,
+
+        /// Output index file path.
+        #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
+        output: String,
+
+        /// Index storage: a single file, or a SQLite database.
+        #[arg(long, value_enum, default_value_t = Backend::File)]
+        backend: Backend,
+
+        /// Index file format (default: from the output extension, `.bin` = binary).
+        #[arg(long, value_enum)]
+        format: Option<index::IndexFormat>,
+
+        /// Fail on a malformed dump line instead of skipping it.
+        #[arg(long)]
+        strict: bool,
+
+        #[command(flatten)]
+        build: BuildArgs,
+    },
+
+    /// Export the intra-site link graph from a crawl dump.
+    ExportGraph {
+        /// JSONL document dump written by `crawl --dump`.
+        #[arg(long, short)]
+        input: String,
+
+        /// Output file path.
+        #[arg(long, short)]
+        output: String,
+
+        /// Output format.
+        #[arg(long, short, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+
+    /// Remove a document from an index file.
+    Remove {
         /// Index file path.
         #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
         index: String,
 
+        /// URL of the document to remove.
+        #[arg(long, short, required_unless_present = "url_prefix", conflicts_with = "url_prefix")]
+        url: Option<String>,
+
+        /// Remove every document whose URL starts with this prefix.
+        #[arg(long)]
+        url_prefix: Option<String>,
+    },
+
+    /// Merge several index files into one.
+    Merge {
+        /// Index file to merge (repeatable; later files win for duplicate URLs).
+        #[arg(long = "input", short, required = true)]
+        inputs: Vec<String>,
+
+        /// Output index file path.
+        #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
+        output: String,
+    },
+
+    /// Convert an index file into a SQLite index.
+    Convert {
+        /// Index file to read.
+        #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
+        input: String,
+
+        /// SQLite database to write.
+        #[arg(long, short)]
+        output: String,
+    },
+
+    /// Export the vocabulary: term, document frequency and total term frequency.
+    ExportTerms {
+        /// Index file path.
+        #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
+        index: String,
+
+        /// Output file (default: stdout).
+        #[arg(long, short)]
+        output: Option<String>,
+
+        /// Output format.
+        #[arg(long, short, value_enum, default_value_t = TermsFormat::Csv)]
+        format: TermsFormat,
+    },
+
+    /// Check an index file's invariants.
+    Validate {
+        /// Index file path.
+        #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
+        index: String,
+    },
+
+    /// Print statistics of an index file.
+    Stats {
+        /// Index file path.
+        #[arg(long, short, default_value = DEFAULT_INDEX_PATH)]
+        index: String,
+
+        /// Print JSON instead of a human-readable report.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Compare two index files: documents, vocabulary and document frequencies.
+    Diff {
+        /// Index A (e.g. the current one).
+        a: String,
+
+        /// Index B (e.g. the rebuilt one).
+        b: String,
+
+        /// Print JSON instead of a human-readable report.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Report the most frequent queries, and those that found nothing, in a
+    /// `serve --query-log` file.
+    TopQueries {
+        /// Query log written by `serve --query-log`.
+        log: PathBuf,
+
+        /// Only count searches within this window before now, such as `24h` or
+        /// `7d` (default: the whole log).
+        #[arg(long, value_name = "WINDOW", value_parser = querylog::parse_window)]
+        since: Option<chrono::Duration>,
+
+        /// Queries listed in each report.
+        #[arg(long, short = 'n', default_value_t = 20)]
+        limit: usize,
+
+        /// Print JSON instead of a human-readable report.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Load index and start search API.
+    Serve {
+        /// Index to serve, as NAME=PATH or a bare PATH (served as `default`); repeatable.
+        #[arg(long, short, value_name = "[NAME=]PATH", default_value = DEFAULT_INDEX_PATH)]
+        index: Vec<String>,
+
         /// Port to listen on.
         #[arg(long, short, default_value_t = 3000)]
         port: u16,
+
+        /// Address to listen on (IPv4 or IPv6).
+        #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+        host: IpAddr,
+
+        /// Address and port to listen on, such as `0.0.0.0:8080` or `[::1]:3000`;
+        /// overrides --host and --port.
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<SocketAddr>,
+
+        /// Listen on this Unix domain socket instead of TCP (for a reverse proxy on
+        /// the same host). A stale socket file is replaced.
+        #[cfg(unix)]
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["host", "port", "bind", "tls_cert"])]
+        unix_socket: Option<PathBuf>,
+
+        /// Permissions of the --unix-socket file, in octal such as `660` (default: from the umask).
+        #[cfg(unix)]
+        #[arg(long, value_name = "MODE", requires = "unix_socket", value_parser = parse_socket_mode)]
+        socket_mode: Option<u32>,
+
+        /// Index storage the index path refers to.
+        #[arg(long, value_enum, default_value_t = Backend::File)]
+        backend: Backend,
+
+        /// Serve indexes whose analyzer this build doesn't reproduce exactly (other
+        /// built-in stopwords or Unicode word boundary rules) instead of refusing.
+        #[arg(long)]
+        force: bool,
+
+        /// Synonym dictionary: comma-separated groups, one per line.
+        #[arg(long, value_name = "PATH")]
+        synonyms: Option<String>,
+
+        /// Score weight of expanded synonyms relative to the typed term.
+        #[arg(long, default_value_t = synonyms::DEFAULT_WEIGHT)]
+        synonym_weight: f64,
+
+        /// Field boost as FIELD=WEIGHT (body, title, headings, url); repeatable.
+        #[arg(long = "boost", value_name = "FIELD=WEIGHT", value_parser = parse_boost)]
+        boosts: Vec<(String, f64)>,
+
+        /// Default score normalization in responses (requests override with `normalize=`).
+        #[arg(long, value_enum, default_value_t = search::ScoreNormalization::None)]
+        normalize: search::ScoreNormalization,
+
+        /// Cache the ranked results of this many recent queries per index (0 = no cache).
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        query_cache_size: usize,
+
+        /// Let clients and proxies reuse /search responses for this many seconds;
+        /// after that they revalidate with the response's ETag (304 if unchanged).
+        #[arg(long, value_name = "SECS", default_value_t = 0)]
+        search_max_age: u64,
+
+        /// Accept document changes via POST and DELETE /documents.
+        #[arg(long)]
+        enable_writes: bool,
+
+        /// Allow browsers on this origin to call the API (CORS); repeatable, `*` for any.
+        #[arg(long = "cors-origin", value_name = "ORIGIN")]
+        cors_origins: Vec<String>,
+
+        /// Limit /search, /similar, /document, /related and /suggest per client IP, as
+        /// REQUESTS/UNIT (s, m or h), e.g. `10/s`; bursts of up to REQUESTS are allowed.
+        #[arg(long, value_name = "RATE", value_parser = ratelimit::parse_rate)]
+        rate_limit: Option<ratelimit::Rate>,
+
+        /// Take the client IP from X-Forwarded-For (only behind a reverse proxy you run).
+        #[arg(long)]
+        trust_proxy: bool,
+
+        /// Require this key (as `Authorization: Bearer KEY` or `X-Api-Key`) on
+        /// /admin/* and write routes.
+        #[arg(long, env = "RUSTYSEARCH_API_KEY", value_name = "KEY", hide_env_values = true)]
+        api_key: Option<String>,
+
+        /// Seconds requests in flight get to finish after SIGINT or SIGTERM.
+        #[arg(long, value_name = "SECS", default_value_t = 10)]
+        shutdown_grace: u64,
+
+        /// Serve HTTPS with this PEM certificate chain (needs --tls-key).
+        #[arg(long, value_name = "PEM", requires = "tls_key")]
+        tls_cert: Option<String>,
+
+        /// Private key (PEM) for --tls-cert.
+        #[arg(long, value_name = "PEM", requires = "tls_cert")]
+        tls_key: Option<String>,
+
+        /// Serve the files of this directory at / (`index.html` for directories)
+        /// instead of the built-in search page. API routes take precedence.
+        #[arg(long, value_name = "DIR")]
+        static_dir: Option<PathBuf>,
+
+        /// Append one JSON line per search (time, query, result count, timing,
+        /// truncated client IP) to this file; see `top-queries`.
+        #[arg(long, value_name = "PATH")]
+        query_log: Option<PathBuf>,
     },
 }
 
+/// Parse a `--socket-mode` such as `660` or `0o660`.
+#[cfg(unix)]
+fn parse_socket_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("expected octal permissions such as 660, got {:?}", value)),
+    }
+}
+
+/// Split a `serve --index` value into (name, path); a bare path is named `default`.
+fn parse_named_index(spec: &str) -> (&str, &str) {
+    match spec.split_once('=') {
+        Some((name, path)) if !name.is_empty() => (name, path),
+        _ => (search::DEFAULT_INDEX_NAME, spec),
+    }
+}
+
+/// Parse a `--boost` value such as `title=3`.
+fn parse_boost(value: &str) -> Result<(String, f64), String> {
+    let (field, weight) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected FIELD=WEIGHT, got {:?}", value))?;
+    let weight: f64 = weight.trim().parse().map_err(|e| format!("invalid weight {:?}: {}", weight, e))?;
+    let field = field.trim().to_string();
+    index::FieldBoosts::default().set(&field, weight)?;
+    Ok((field, weight))
+}
+
+/// Text processing and pruning flags shared by `crawl` and `reindex`.
+#[derive(clap::Args)]
+struct BuildArgs {
+    /// Analyzer: `standard` (the text processing the flags below set up), or
+    /// `cjk-bigram` (runs of Han, Hiragana and Katakana as character bigrams, other
+    /// text through the standard processing).
+    #[arg(long, value_enum, default_value_t = tokenize::AnalyzerKind::Standard)]
+    analyzer: tokenize::AnalyzerKind,
+
+    /// Stopword list file (one or more words per line, `#` comments) instead of a built-in list.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["no_stopwords", "stopword_language"])]
+    stopwords: Option<String>,
+
+    /// Built-in stopword list: `english`, or `japanese` (particles).
+    #[arg(long, value_enum, default_value_t = tokenize::StopwordLanguage::English, conflicts_with = "no_stopwords")]
+    stopword_language: tokenize::StopwordLanguage,
+
+    /// Keep stopwords in the index.
+    #[arg(long)]
+    no_stopwords: bool,
+
+    /// Stem words (e.g. "crawling" -> "crawl") in this language.
+    #[arg(long, value_name = "LANG", value_parser = ["en"])]
+    stemming: Option<String>,
+
+    /// Drop tokens longer than this many characters (0 = no limit).
+    #[arg(long, value_name = "N", default_value_t = 40)]
+    max_token_len: usize,
+
+    /// Drop tokens shorter than this many characters (1 = keep all).
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    min_token_len: usize,
+
+    /// Drop tokens whose fraction of digits exceeds this (0-1).
+    #[arg(long, value_name = "RATIO")]
+    max_digit_ratio: Option<f64>,
+
+    /// Drop tokens made only of hex digits that are longer than this (0 = keep all).
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    max_hex_len: usize,
+
+    /// Tokenizer: `unicode` (Unicode word boundaries, Japanese/Chinese text as
+    /// character bigrams), `whitespace`, or `cjk` (whitespace, with CJK bigrams).
+    #[arg(long, value_enum, default_value_t = tokenize::Tokenizer::Unicode)]
+    tokenizer: tokenize::Tokenizer,
+
+    /// Unicode normalization of documents and queries: `nfkc` (folds full-width
+    /// letters and digits, half-width Katakana and decomposed accents) or `none`.
+    #[arg(long, value_enum, default_value_t = tokenize::Normalization::Nfkc)]
+    normalization: tokenize::Normalization,
+
+    /// Apostrophes and hyphens inside words: `split` (index "state-of-the-art" also
+    /// as "state", "of", "the", "art"; "don't" as "dont") or `keep`.
+    #[arg(long, value_enum, default_value_t = tokenize::WordPunctuation::Split)]
+    word_punctuation: tokenize::WordPunctuation,
+
+    /// Keep thousands separators ("1,000" is not indexed as "1000").
+    #[arg(long)]
+    keep_thousands_separators: bool,
+
+    /// Don't keep versions ("v1.2.3", "1.0-2") whole; hyphens in them split as in words.
+    #[arg(long)]
+    split_versions: bool,
+
+    /// Don't add a year token for ISO dates ("2024-05-01" -> "2024").
+    #[arg(long)]
+    no_date_years: bool,
+
+    /// Also index the parts of identifiers: "JoinSet" as "join" and "set",
+    /// "join_next" as "join" and "next" (grows the index of prose-heavy sites).
+    #[arg(long)]
+    split_identifiers: bool,
+
+    /// Drop terms found in fewer than N documents (a term in exactly N is kept).
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    min_df: usize,
+
+    /// Drop terms found in more than this fraction of documents (0-1; exactly at the ratio is kept).
+    #[arg(long, value_name = "RATIO")]
+    max_df_ratio: Option<f64>,
+}
+
+impl BuildArgs {
+    /// Validate the flags and turn them into index build options.
+    fn options(self) -> Result<index::BuildOptions, Box<dyn std::error::Error + Send + Sync>> {
+        if self.max_df_ratio.is_some_and(|ratio| !(ratio > 0.0 && ratio <= 1.0)) {
+            return Err("--max-df-ratio must be in (0, 1]".into());
+        }
+        if self.max_digit_ratio.is_some_and(|ratio| !(0.0..=1.0).contains(&ratio)) {
+            return Err("--max-digit-ratio must be in [0, 1]".into());
+        }
+        if self.max_token_len > 0 && self.min_token_len > self.max_token_len {
+            return Err("--min-token-len must not exceed --max-token-len".into());
+        }
+        let token_filter = tokenize::TokenFilter {
+            max_len: (self.max_token_len > 0).then_some(self.max_token_len),
+            max_digit_ratio: self.max_digit_ratio,
+            max_hex_len: (self.max_hex_len > 0).then_some(self.max_hex_len),
+            min_len: (self.min_token_len > 1).then_some(self.min_token_len),
+        };
+        let stopwords = match (self.stopwords, self.no_stopwords) {
+            (_, true) => tokenize::Stopwords::default(),
+            (Some(path), false) => tokenize::Stopwords::load(Path::new(&path))?,
+            (None, false) => tokenize::Stopwords::builtin(self.stopword_language),
+        };
+        let standard = tokenize::StandardAnalyzer::with_stopwords(stopwords)
+            .with_stemming(self.stemming)
+            .with_tokenizer(self.tokenizer)
+            .with_token_filter(token_filter)
+            .with_normalization(self.normalization)
+            .with_word_punctuation(self.word_punctuation)
+            .with_number_rules(tokenize::NumberRules {
+                thousands: !self.keep_thousands_separators,
+                versions: !self.split_versions,
+                dates: !self.no_date_years,
+            })
+            .with_split_identifiers(self.split_identifiers);
+        Ok(index::BuildOptions {
+            analyzer: self.analyzer.build(standard),
+            prune: index::PruneOptions {
+                min_df: self.min_df,
+                max_df_ratio: self.max_df_ratio,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Backend {
+    /// JSON or binary file (optionally gzipped), loaded into memory.
+    File,
+    /// SQLite database; postings are read per query.
+    Sqlite,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum TermsFormat {
+    Csv,
+    Tsv,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let cli = Cli::parse();
+    init_logging(cli.log_level.as_deref(), cli.log_json)?;
     match cli.command {
-        Command::Crawl { url, max_pages, max_depth, output } => {
-            run_crawl(&url, Some(max_pages), Some(max_depth), &output)?;
+        Command::Crawl {
+            url,
+            max_pages,
+            max_depth,
+            output,
+            backend,
+            format,
+            path_prefixes,
+            path_prefix_ignore_case,
+            checkpoint_every,
+            metrics_out,
+            dump,
+            build,
+        } => {
+            let options = crawler::CrawlOptions {
+                max_pages: Some(max_pages),
+                max_depth: Some(max_depth),
+                path_prefixes,
+                path_prefix_ignore_case,
+                ..Default::default()
+            };
+            let mut save = index::SaveOptions::for_path(Path::new(&output));
+            if let Some(format) = format {
+                save.format = format;
+            }
+            let target = IndexTarget {
+                path: output,
+                backend,
+                save,
+                build: build.options()?,
+            };
+            run_crawl(&url, &options, checkpoint_every, metrics_out.as_deref(), dump.as_deref(), &target)?;
+        }
+        Command::Reindex {
+            input,
+            output,
+            backend,
+            format,
+            strict,
+            build,
+        } => {
+            let mut save = index::SaveOptions::for_path(Path::new(&output));
+            if let Some(format) = format {
+                save.format = format;
+            }
+            let target = IndexTarget {
+                path: output,
+                backend,
+                save,
+                build: build.options()?,
+            };
+            run_reindex(&input, strict, &target)?;
+        }
+        Command::ExportGraph { input, output, format } => {
+            run_export_graph(&input, &output, format)?;
+        }
+        Command::Remove { index, url, url_prefix } => match (url, url_prefix) {
+            (_, Some(prefix)) => run_remove_prefix(&index, &prefix)?,
+            (Some(url), None) => run_remove(&index, &url)?,
+            (None, None) => unreachable!("clap requires --url or --url-prefix"),
+        },
+        Command::Merge { inputs, output } => {
+            run_merge(&inputs, &output)?;
+        }
+        Command::Convert { input, output } => {
+            run_convert(&input, &output)?;
+        }
+        Command::ExportTerms { index, output, format } => {
+            run_export_terms(&index, output.as_deref(), format)?;
+        }
+        Command::Validate { index } => {
+            run_validate(&index)?;
+        }
+        Command::Stats { index, json } => {
+            run_stats(&index, json)?;
         }
-        Command::Serve { index, port } => {
-            run_serve(&index, port)?;
+        Command::Diff { a, b, json } => {
+            run_diff(&a, &b, json)?;
+        }
+        Command::TopQueries { log, since, limit, json } => {
+            run_top_queries(&log, since, limit, json)?;
+        }
+        Command::Serve {
+            index,
+            port,
+            host,
+            bind,
+            #[cfg(unix)]
+            unix_socket,
+            #[cfg(unix)]
+            socket_mode,
+            backend,
+            force,
+            synonyms,
+            synonym_weight,
+            boosts,
+            normalize,
+            query_cache_size,
+            search_max_age,
+            enable_writes,
+            cors_origins,
+            rate_limit,
+            trust_proxy,
+            api_key,
+            shutdown_grace,
+            tls_cert,
+            tls_key,
+            static_dir,
+            query_log,
+        } => {
+            let mut field_boosts = index::FieldBoosts::default();
+            for (field, boost) in &boosts {
+                field_boosts.set(field, *boost)?;
+            }
+            let indexes = index.iter().map(|spec| parse_named_index(spec)).collect::<Vec<_>>();
+            let config = ServeConfig {
+                addr: bind.unwrap_or(SocketAddr::new(host, port)),
+                #[cfg(unix)]
+                unix_socket: unix_socket.as_deref().map(|path| (path, socket_mode)),
+                backend,
+                force,
+                synonyms_path: synonyms.as_deref(),
+                synonym_weight,
+                boosts: field_boosts,
+                normalize,
+                query_cache_size,
+                search_max_age,
+                enable_writes,
+                cors_origins: &cors_origins,
+                rate_limit,
+                trust_proxy,
+                api_key: api_key.as_deref(),
+                shutdown_grace: Duration::from_secs(shutdown_grace),
+                tls: tls_cert.as_deref().zip(tls_key.as_deref()),
+                static_dir: static_dir.as_deref(),
+                query_log: query_log.as_deref(),
+            };
+            run_serve(&indexes, &config)?;
         }
     }
     Ok(())
 }
 
+/// Where and how `crawl` and `reindex` write their index.
+struct IndexTarget {
+    path: String,
+    backend: Backend,
+    /// File format options (`Backend::File` only).
+    save: index::SaveOptions,
+    build: index::BuildOptions,
+}
+
+/// Save an index to `path` in the given backend.
+fn save_to_backend(
+    idx: &index::IndexWithTf,
+    path: &Path,
+    backend: Backend,
+    save: index::SaveOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match backend {
+        Backend::File => index::save_index_with_tf_as(idx, path, save),
+        Backend::Sqlite => sqlite::save(idx, path),
+    }
+}
+
 fn run_crawl(
     url: &str,
-    max_pages: Option<usize>,
-    max_depth: Option<u32>,
+    options: &crawler::CrawlOptions,
+    checkpoint_every: usize,
+    metrics_out: Option<&str>,
+    dump_path: Option<&str>,
+    target: &IndexTarget,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let save = target.save;
+    let backend = target.backend;
+    let checkpoint = (checkpoint_every > 0).then(|| {
+        let path = PathBuf::from(&target.path);
+        let build = target.build.clone();
+        crawler::Checkpoint {
+            every: checkpoint_every,
+            save: Arc::new(move |results: Vec<crawler::CrawlResult>| {
+                let (idx, _) = index::build_index_with_tf(&results, &build);
+                match save_to_backend(&idx, &path, backend, save) {
+                    Ok(()) => println!("Checkpoint: {} pages saved to {:?}", results.len(), path),
+                    Err(e) => eprintln!("Checkpoint failed: {}", e),
+                }
+            }),
+        }
+    });
+    let output = crawler::crawl(url, options, checkpoint)?;
+    let (idx, pruned) = index::build_index_with_tf(&output.results, &target.build);
+    let path = Path::new(&target.path);
+    save_to_backend(&idx, path, backend, save)?;
+    println!(
+        "Crawled {} pages ({} filtered, {} failed), index saved to {:?}",
+        output.results.len(),
+        output.filtered,
+        output.failed,
+        path
+    );
+    print_build_summary(&idx, &pruned);
+    print_metrics_summary(&output.metrics_summary());
+    if let Some(metrics_path) = metrics_out {
+        let json = serde_json::to_string_pretty(&output.metrics)?;
+        std::fs::write(metrics_path, json)?;
+        println!("Fetch metrics written to {:?}", metrics_path);
+    }
+    if let Some(dump_path) = dump_path {
+        crawler::save_dump(&output.results, Path::new(dump_path))?;
+        println!("Documents dumped to {:?}", dump_path);
+    }
+    Ok(())
+}
+
+/// Rebuild an index from a JSONL dump without holding the whole dump in memory.
+fn run_reindex(
+    input_path: &str,
+    strict: bool,
+    target: &IndexTarget,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let file = std::fs::File::open(input_path)?;
+    let mut reader = crawler::DumpReader::new(std::io::BufReader::new(file)).strict(strict);
+    let mut idx = index::IndexWithTf::build_from_iter(&mut reader, &target.build.analyzer);
+    if let Some(e) = reader.error.take() {
+        return Err(e);
+    }
+    let pruned = idx.prune(&target.build.prune);
+    let path = Path::new(&target.path);
+    save_to_backend(&idx, path, target.backend, target.save)?;
+    println!("Indexed {} documents from {:?}, index saved to {:?}", reader.read, input_path, path);
+    if reader.skipped > 0 {
+        println!("Skipped {} malformed lines", reader.skipped);
+    }
+    print_build_summary(&idx, &pruned);
+    Ok(())
+}
+
+/// Report what building an index collapsed, pruned and dropped.
+fn print_build_summary(idx: &index::IndexWithTf, pruned: &index::PruneStats) {
+    if !idx.aliases.is_empty() {
+        println!("Collapsed {} duplicate pages into their first URL", idx.aliases.len());
+    }
+    if pruned.rare + pruned.common > 0 {
+        println!(
+            "Pruned {} rare and {} common terms ({} terms kept)",
+            pruned.rare,
+            pruned.common,
+            idx.term_tf.len()
+        );
+    }
+    let rejected = idx.rejected_tokens();
+    if rejected.total() > 0 {
+        println!(
+            "Dropped {} junk tokens ({} too short, {} too long, {} mostly digits, {} long hex)",
+            rejected.total(),
+            rejected.too_short,
+            rejected.too_long,
+            rejected.digits,
+            rejected.hex
+        );
+    }
+}
+
+fn run_export_graph(
+    input_path: &str,
     output_path: &str,
+    format: GraphFormat,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let results = crawler::crawl(url, max_pages, max_depth)?;
-    let idx = index::build_index_with_tf(&results);
+    let results = crawler::load_dump(Path::new(input_path))?;
+    let graph = graph::LinkGraph::build(&results);
+    let out = match format {
+        GraphFormat::Dot => graph.to_dot(),
+        GraphFormat::Json => graph.to_json()?,
+    };
+    std::fs::write(output_path, out)?;
+    println!("Link graph of {} pages written to {:?}", results.len(), output_path);
+    Ok(())
+}
+
+fn run_remove(index_path: &str, url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = Path::new(index_path);
+    let mut idx = index::load_index_with_tf(path)?;
+    if !idx.remove_document(url) {
+        match idx.canonical_url(url) {
+            canonical if canonical != url => println!("{} is a duplicate of {}; remove that URL instead", url, canonical),
+            _ => println!("{} is not in {:?}; index unchanged", url, path),
+        }
+        return Ok(());
+    }
+    index::save_index_with_tf_as(&idx, path, index::SaveOptions::for_path(path))?;
+    println!("Removed {} from {:?} ({} documents left)", url, path, idx.doc_count);
+    Ok(())
+}
+
+fn run_remove_prefix(index_path: &str, prefix: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if prefix.is_empty() {
+        return Err("--url-prefix must not be empty (it would remove every document)".into());
+    }
+    let path = Path::new(index_path);
+    let mut idx = index::load_index_with_tf(path)?;
+    let removed = idx.remove_by_prefix(prefix);
+    if removed == 0 {
+        println!("No URLs in {:?} start with {}; index unchanged", path, prefix);
+        return Ok(());
+    }
+    index::save_index_with_tf_as(&idx, path, index::SaveOptions::for_path(path))?;
+    println!("Removed {} documents under {} from {:?} ({} documents left)", removed, prefix, path, idx.doc_count);
+    Ok(())
+}
+
+fn run_merge(inputs: &[String], output_path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut merged = index::IndexWithTf::default();
+    for input in inputs {
+        let idx = index::load_index_with_tf(Path::new(input))?;
+        println!("{:?}: {} documents", input, idx.doc_count);
+        if merged.doc_count > 0 && idx.analyzer != merged.analyzer {
+            eprintln!("Warning: {:?} was built with different text processing (stopwords, stemming, tokenizer, token filters, normalization, word punctuation, number rules, identifier splitting) than the first input", input);
+        }
+        merged.merge(idx);
+    }
     let path = Path::new(output_path);
-    index::save_index_with_tf(&idx, path)?;
-    println!("Crawled {} pages, index saved to {:?}", results.len(), path);
+    index::save_index_with_tf_as(&merged, path, index::SaveOptions::for_path(path))?;
+    println!(
+        "Merged {} indexes: {} documents, {} terms, saved to {:?}",
+        inputs.len(),
+        merged.doc_count,
+        merged.term_tf.len(),
+        path
+    );
+    Ok(())
+}
+
+fn run_convert(input_path: &str, output_path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let idx = index::load_index_with_tf(Path::new(input_path))?;
+    sqlite::save(&idx, Path::new(output_path))?;
+    println!(
+        "Converted {:?} ({} documents) to SQLite index {:?}",
+        input_path, idx.doc_count, output_path
+    );
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or line break.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+fn run_export_terms(
+    index_path: &str,
+    output_path: Option<&str>,
+    format: TermsFormat,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let idx = index::load_index_with_tf(Path::new(index_path))?;
+    let out: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    // Rows are written one at a time; tokens never contain tabs or line breaks, so TSV needs no escaping.
+    match format {
+        TermsFormat::Csv => writeln!(out, "term,df,total_tf")?,
+        TermsFormat::Tsv => writeln!(out, "term\tdf\ttotal_tf")?,
+    }
+    let terms = idx.term_frequencies();
+    for &(term, df, total_tf) in &terms {
+        match format {
+            TermsFormat::Csv => writeln!(out, "{},{},{}", csv_field(term), df, total_tf)?,
+            TermsFormat::Tsv => writeln!(out, "{}\t{}\t{}", term, df, total_tf)?,
+        }
+    }
+    out.flush()?;
+    if let Some(path) = output_path {
+        println!("Exported {} terms to {:?}", terms.len(), path);
+    }
     Ok(())
 }
 
-fn run_serve(index_path: &str, port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+fn run_validate(index_path: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let path = Path::new(index_path);
-    let idx = index::load_index_with_tf(path).or_else(|_| {
-        let simple = index::load_index(path)?;
-        let doc_count = simple.values().flat_map(|urls| urls.iter()).collect::<std::collections::HashSet<_>>().len();
-        let term_tf: HashMap<String, HashMap<String, u32>> = simple
-            .into_iter()
-            .map(|(term, urls)| (term, urls.into_iter().map(|u| (u, 1u32)).collect()))
-            .collect();
-        Ok::<index::IndexWithTf, Box<dyn std::error::Error + Send + Sync>>(index::IndexWithTf { term_tf, doc_count })
-    })?;
-    let state: search::AppState = Arc::new(idx);
+    // Loading already rejects unreadable files and unsupported (newer) format versions.
+    let (idx, version) = index::load_index_with_tf_versioned(path)?;
+    let errors = idx.validate();
+    if !errors.is_empty() {
+        for error in &errors {
+            println!("{}", error);
+        }
+        return Err(format!("{:?}: {} invariant violations", path, errors.len()).into());
+    }
+    let stats = idx.stats();
+    println!(
+        "OK: {:?} (format v{}), {} documents, {} terms, {} postings",
+        path, version, stats.documents, stats.vocabulary, stats.total_postings
+    );
+    Ok(())
+}
+
+fn run_stats(index_path: &str, json: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = Path::new(index_path);
+    let mut stats = match index::load_index_with_tf_versioned(path)? {
+        (idx, 0) => index::IndexStats::from_inverted(&idx.as_inverted()),
+        (idx, _) => idx.stats(),
+    };
+    stats.file_size = Some(std::fs::metadata(path)?.len());
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    let unavailable = || "n/a".to_string();
+    println!("Index:            {:?}", path);
+    println!("File size:        {} bytes", stats.file_size.unwrap_or(0));
+    println!("Documents:        {}", stats.documents);
+    println!("Vocabulary:       {} terms", stats.vocabulary);
+    println!("Postings:         {}", stats.total_postings);
+    println!("Tokens:           {}", stats.total_tokens.map_or_else(unavailable, |n| n.to_string()));
+    println!("Avg doc length:   {}", stats.avg_doc_len.map_or_else(unavailable, |l| format!("{:.1} tokens", l)));
+    println!("Median length:    {}", stats.median_doc_len.map_or_else(unavailable, |l| format!("{:.1} tokens", l)));
+    println!("Stopwords:        {}", stats.stopwords.clone().unwrap_or_else(unavailable));
+    println!("Top terms by document frequency:");
+    for (term, df) in &stats.top_terms {
+        println!("  {:>6}  {}", df, term);
+    }
+    Ok(())
+}
+
+/// Number of entries printed per list by `diff` (JSON output has them all).
+const DIFF_SAMPLE: usize = 20;
+
+fn run_diff(a_path: &str, b_path: &str, json: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let a = index::load_index_with_tf(Path::new(a_path))?;
+    let b = index::load_index_with_tf(Path::new(b_path))?;
+    let diff = index::IndexDiff::between(&a, &b);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+    println!("A: {:?} ({} documents, {} terms)", a_path, a.doc_count, a.term_tf.len());
+    println!("B: {:?} ({} documents, {} terms)", b_path, b.doc_count, b.term_tf.len());
+    let lists = [
+        ("Documents only in A", &diff.docs_only_in_a),
+        ("Documents only in B", &diff.docs_only_in_b),
+        ("Terms added", &diff.terms_added),
+        ("Terms removed", &diff.terms_removed),
+    ];
+    for (label, items) in lists {
+        println!("{}: {}", label, items.len());
+        for item in items.iter().take(DIFF_SAMPLE) {
+            println!("  {}", item);
+        }
+        if items.len() > DIFF_SAMPLE {
+            println!("  ... and {} more", items.len() - DIFF_SAMPLE);
+        }
+    }
+    println!("Largest df changes:");
+    for change in &diff.df_changes {
+        let delta = change.df_b as i64 - change.df_a as i64;
+        println!("  {:>+7}  {} ({} -> {})", delta, change.term, change.df_a, change.df_b);
+    }
+    Ok(())
+}
+
+fn run_top_queries(
+    log: &Path,
+    since: Option<chrono::Duration>,
+    limit: usize,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let report = querylog::top_queries(log, since.map(|window| chrono::Utc::now() - window), limit)
+        .map_err(|e| format!("cannot read query log {}: {}", log.display(), e))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!("Searches: {}", report.searches);
+    for (label, queries) in [("Top queries", &report.top), ("Zero-result queries", &report.zero_results)] {
+        println!("{}:", label);
+        for (query, count) in queries {
+            println!("  {:>7}  {}", count, query);
+        }
+    }
+    Ok(())
+}
+
+fn print_metrics_summary(summary: &crawler::MetricsSummary) {
+    println!(
+        "Downloaded {} bytes, latency avg {:.1} ms / p95 {:.1} ms, {:.2} pages/s",
+        summary.total_bytes, summary.avg_latency_ms, summary.p95_latency_ms, summary.pages_per_sec
+    );
+    let classes: Vec<String> = summary
+        .status_classes
+        .iter()
+        .map(|(class, count)| format!("{}: {}", class, count))
+        .collect();
+    println!("Status: {}", classes.join(", "));
+}
+
+/// Open an index for serving from the given backend.
+fn load_served(path: &Path, backend: Backend) -> Result<search::IndexBackend, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(match backend {
+        Backend::File => search::IndexBackend::Memory(Arc::new(index::load_index_with_tf(path)?)),
+        Backend::Sqlite => search::IndexBackend::Sqlite(Arc::new(sqlite::SqliteIndex::open(path)?)),
+    })
+}
+
+/// `serve` settings other than the indexes.
+struct ServeConfig<'a> {
+    addr: SocketAddr,
+    /// Unix socket to listen on instead of `addr`, and its permissions.
+    #[cfg(unix)]
+    unix_socket: Option<(&'a Path, Option<u32>)>,
+    backend: Backend,
+    /// Serve indexes with a mismatched analyzer anyway.
+    force: bool,
+    synonyms_path: Option<&'a str>,
+    synonym_weight: f64,
+    boosts: index::FieldBoosts,
+    normalize: search::ScoreNormalization,
+    query_cache_size: usize,
+    search_max_age: u64,
+    enable_writes: bool,
+    cors_origins: &'a [String],
+    rate_limit: Option<ratelimit::Rate>,
+    trust_proxy: bool,
+    api_key: Option<&'a str>,
+    /// How long requests in flight may take to finish after SIGINT/SIGTERM.
+    shutdown_grace: Duration,
+    /// PEM certificate chain and private key to serve HTTPS with.
+    tls: Option<(&'a str, &'a str)>,
+    /// Directory served at / in place of the built-in page.
+    static_dir: Option<&'a Path>,
+    /// File every search is logged to.
+    query_log: Option<&'a Path>,
+}
+
+/// `--static-dir` files, with content types from their extensions. Browsers are
+/// told to revalidate (against `Last-Modified`) on every use, so edits to the
+/// directory show up without stale cached copies.
+fn static_files(dir: &Path) -> SetResponseHeader<ServeDir, HeaderValue> {
+    SetResponseHeader::if_not_present(ServeDir::new(dir), header::CACHE_CONTROL, HeaderValue::from_static("no-cache"))
+}
+
+/// CORS layer for the configured origins, or `None` when no `--cors-origin` is
+/// given. Only GET is allowed, plus POST and DELETE when writes are enabled.
+fn cors_layer(origins: &[String], writes: bool) -> Result<Option<CorsLayer>, Box<dyn std::error::Error + Send + Sync>> {
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                // Browsers send the origin without a trailing slash.
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .map_err(|_| format!("invalid --cors-origin {:?}", origin))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    let mut methods = vec![Method::GET];
+    if writes {
+        methods.extend([Method::POST, Method::DELETE]);
+    }
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(methods)
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION, HeaderName::from_static("x-api-key")]),
+    ))
+}
+
+/// Wait for a request to stop: Ctrl-C (SIGINT), or SIGTERM on Unix. Returns the signal name.
+async fn shutdown_signal() -> &'static str {
+    let interrupt = async {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => "SIGINT",
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+                "SIGTERM"
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<&'static str>();
+    tokio::select! {
+        reason = interrupt => reason,
+        reason = terminate => reason,
+    }
+}
+
+/// Reload every served index, and the TLS certificate, whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_on_hangup(state: search::AppState, tls: Option<TlsFiles>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            eprintln!("Warning: can't listen for SIGHUP, reload with POST /admin/reload only: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        for name in state.names() {
+            match state.indexes[&name].reload().await {
+                Ok(info) => println!(
+                    "Reloaded index {:?} ({} documents, {:.0} ms)",
+                    name, info.doc_count, info.load_time_ms
+                ),
+                Err(e) => eprintln!("Reloading index {:?} failed, still serving the previous one: {}", name, e),
+            }
+        }
+        if let Some(tls) = &tls {
+            match tls.reload().await {
+                Ok(()) => println!("Reloaded TLS certificate {:?}", tls.cert),
+                Err(e) => eprintln!("Reloading TLS certificate failed, still using the previous one: {}", e),
+            }
+        }
+    }
+}
+
+/// Load an index for serving, with the synonym dictionary (path, weight) analyzed
+/// by its analyzer. Warnings about the synonym file are printed if `report`. An
+/// index whose recorded analyzer this build can't rebuild exactly (other built-in
+/// stopwords or word boundary rules) is refused unless `force`, since queries would
+/// stop matching some of its documents.
+fn load_snapshot(
+    path: &Path,
+    backend: Backend,
+    force: bool,
+    synonyms: Option<(&str, f64)>,
+    report: bool,
+) -> Result<search::LoadedIndex, Box<dyn std::error::Error + Send + Sync>> {
+    let started = Instant::now();
+    let served = load_served(path, backend)?;
+    if let Some(mismatch) = served.analyzer().mismatch().filter(|_| !force) {
+        return Err(format!("refusing to serve {:?}: {}; rebuild it, or pass --force", path, mismatch).into());
+    }
+    let synonyms = match synonyms {
+        Some((synonyms_path, weight)) => {
+            let (synonyms, warnings) = synonyms::Synonyms::load(Path::new(synonyms_path), served.analyzer(), weight)?;
+            if report {
+                for warning in warnings {
+                    eprintln!("Warning: {}: {}", synonyms_path, warning);
+                }
+                println!("Loaded synonyms for {} words from {:?}", synonyms.word_count(), synonyms_path);
+            }
+            Some(Arc::new(synonyms))
+        }
+        None => None,
+    };
+    let load_info = search::LoadInfo::new(&served, started.elapsed());
+    Ok(search::LoadedIndex::new(served, synonyms, load_info))
+}
+
+/// The served routes with their middleware: rate limiting on the search routes,
+/// the API key on the admin and write routes, then tracing and CORS around all of
+/// them. `/` is the built-in page, or `static_dir` when given.
+fn router(
+    state: search::AppState,
+    rate_limit: Option<ratelimit::RateLimit>,
+    api_key: Option<Arc<str>>,
+    static_dir: Option<&Path>,
+    cors: Option<CorsLayer>,
+) -> axum::Router {
+    let mut search_routes = axum::Router::new()
+        .route("/search", axum::routing::get(search::search_handler))
+        .route("/similar", axum::routing::get(search::similar_handler))
+        .route("/document", axum::routing::get(search::document_handler))
+        .route("/related", axum::routing::get(search::related_handler))
+        .route("/suggest", axum::routing::get(search::suggest_handler));
+    if let Some(limit) = rate_limit {
+        search_routes =
+            search_routes.route_layer(axum::middleware::from_fn_with_state(limit, ratelimit::rate_limit));
+    }
+    let mut admin_routes = axum::Router::new()
+        .route("/admin/reload", axum::routing::post(search::reload_handler))
+        .route("/admin/crawl", axum::routing::post(search::crawl_handler))
+        .route("/admin/crawl/:id", axum::routing::get(search::crawl_status_handler))
+        .route(
+            "/documents",
+            axum::routing::post(search::documents_handler).delete(search::delete_document_handler),
+        );
+    if let Some(key) = api_key {
+        admin_routes = admin_routes.route_layer(axum::middleware::from_fn_with_state(key, auth::require_api_key));
+    }
+    let app = axum::Router::new()
+        .merge(search_routes)
+        .route("/indexes", axum::routing::get(search::indexes_handler))
+        .route("/stats", axum::routing::get(search::stats_handler))
+        .route("/healthz", axum::routing::get(search::healthz_handler))
+        .route("/readyz", axum::routing::get(search::readyz_handler))
+        .route("/openapi.json", axum::routing::get(openapi::openapi_handler))
+        .route("/docs", axum::routing::get(openapi::docs_page))
+        .merge(admin_routes);
+    // Routes match before the fallback, so a file can't shadow the API.
+    let app = match static_dir {
+        Some(dir) => app.fallback_service(static_files(dir)),
+        None => app.route("/", axum::routing::get(search::index_page)),
+    };
+    let app = app.with_state(state).layer(TraceLayer::new_for_http());
+    match cors {
+        Some(cors) => app.layer(cors),
+        None => app,
+    }
+}
+
+/// Serve `indexes` as (name, path) pairs. Synonyms are analyzed once per index,
+/// with that index's analyzer.
+fn run_serve(indexes: &[(&str, &str)], config: &ServeConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut named = HashMap::new();
+    for (i, &(name, index_path)) in indexes.iter().enumerate() {
+        if named.contains_key(name) {
+            return Err(format!("index name {:?} is given more than once", name).into());
+        }
+        let path = PathBuf::from(index_path);
+        let backend = config.backend;
+        let force = config.force;
+        let synonyms = config.synonyms_path.map(|path| (path.to_string(), config.synonym_weight));
+        // The synonym warnings are about the file, so report them for one index only.
+        let report = i == 0;
+        let loader: search::Loader = Arc::new(move || {
+            let synonyms = synonyms.as_ref().map(|(path, weight)| (path.as_str(), *weight));
+            load_snapshot(&path, backend, force, synonyms, report)
+        });
+        let saver: Option<search::Saver> = (backend == Backend::File).then(|| {
+            let path = PathBuf::from(index_path);
+            Arc::new(move |idx: &index::IndexWithTf| index::save_index_with_tf(idx, &path)) as search::Saver
+        });
+        let cache = search::QueryCache::new(config.query_cache_size).map(Arc::new);
+        let served = search::NamedIndex::load(loader, saver, cache)?;
+        let doc_count = served.snapshot().index.doc_count();
+        println!("Serving {:?} as index {:?} ({} documents)", index_path, name, doc_count);
+        named.insert(name.to_string(), served);
+    }
+    let default_index = if named.contains_key(search::DEFAULT_INDEX_NAME) {
+        search::DEFAULT_INDEX_NAME
+    } else {
+        indexes.first().map(|&(name, _)| name).ok_or("no index to serve")?
+    };
+    let state = search::AppState {
+        indexes: Arc::new(named),
+        default_index: default_index.to_string(),
+        boosts: config.boosts,
+        normalize: config.normalize,
+        // Indexes are loaded above, before the server starts.
+        ready: Arc::new(AtomicBool::new(true)),
+        writes: config.enable_writes,
+        metrics: Arc::new(search::ServerMetrics::start()),
+        search_max_age: config.search_max_age,
+        query_log: match config.query_log {
+            Some(path) => Some(
+                querylog::QueryLog::start(path.to_path_buf())
+                    .map_err(|e| format!("cannot open query log {}: {}", path.display(), e))?,
+            ),
+            None => None,
+        },
+        crawls: Arc::default(),
+    };
+    if let Some(dir) = config.static_dir.filter(|dir| !dir.is_dir()) {
+        return Err(format!("--static-dir {} is not a directory", dir.display()).into());
+    }
+    let cors = cors_layer(config.cors_origins, config.enable_writes)?;
+    let rate_limit = config.rate_limit.map(|rate| ratelimit::RateLimit {
+        limiter: Arc::new(ratelimit::RateLimiter::new(rate, Instant::now())),
+        trust_proxy: config.trust_proxy,
+    });
+    let api_key: Option<Arc<str>> = match config.api_key {
+        Some("") => return Err("--api-key must not be empty".into()),
+        Some(key) => Some(key.into()),
+        None => {
+            if config.enable_writes {
+                eprintln!("Warning: --enable-writes without --api-key; anyone who can reach the server can write");
+            }
+            None
+        }
+    };
 
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async {
-        let app = axum::Router::new()
-            .route("/", axum::routing::get(search::index_page))
-            .route("/search", axum::routing::get(search::search_handler))
-            .with_state(state);
-
-        let addr = format!("127.0.0.1:{}", port);
-        println!("Listening on http://{}", addr);
-        let listener = tokio::net::TcpListener::bind(&addr).await?;
-        axum::serve(listener, app).await?;
-        Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+        let app = router(state.clone(), rate_limit, api_key, config.static_dir, cors);
+
+        // Load the certificate before listening, so a bad file fails at startup.
+        let tls = match config.tls {
+            Some((cert, key)) => Some(TlsFiles::load(cert, key).await?),
+            None => None,
+        };
+
+        #[cfg(unix)]
+        tokio::spawn(reload_on_hangup(state, tls.clone()));
+
+        #[cfg(unix)]
+        if let Some((path, mode)) = config.unix_socket {
+            return serve_unix(app, path, mode, config.shutdown_grace).await;
+        }
+        let listener = std::net::TcpListener::bind(config.addr)
+            .map_err(|e| format!("cannot listen on {}: {}", config.addr, e))?;
+        match tls {
+            Some(tls) => serve_https(app, listener, tls.config, config.shutdown_grace, shutdown_signal()).await,
+            None => serve_http(app, listener, config.shutdown_grace, shutdown_signal()).await,
+        }
     })?;
     Ok(())
 }
+
+/// The served TLS certificate, and the PEM files it is loaded (and reloaded) from.
+#[derive(Clone)]
+struct TlsFiles {
+    config: RustlsConfig,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl TlsFiles {
+    async fn load(cert: &str, key: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (cert, key) = (PathBuf::from(cert), PathBuf::from(key));
+        let config = RustlsConfig::from_pem_file(&cert, &key)
+            .await
+            .map_err(|e| format!("can't load TLS certificate {:?} with key {:?}: {}", cert, key, e))?;
+        Ok(Self { config, cert, key })
+    }
+
+    /// Read the files again; on error the current certificate stays in use.
+    #[cfg(unix)]
+    async fn reload(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.config.reload_from_pem_file(&self.cert, &self.key).await?;
+        Ok(())
+    }
+}
+
+/// Serve plain HTTP on `listener` until `shutdown` resolves (to the reason, e.g.
+/// the signal name), then give requests in flight `grace` to finish.
+async fn serve_http(
+    app: axum::Router,
+    listener: std::net::TcpListener,
+    grace: Duration,
+    shutdown: impl Future<Output = &'static str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    // The bound address, so an ephemeral port (0) shows the real one.
+    println!("Listening on http://{}", listener.local_addr()?);
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            let _ = stopped.await;
+        });
+    let mut server = std::pin::pin!(server.into_future());
+    let reason = tokio::select! {
+        result = &mut server => return result.map_err(Into::into),
+        reason = shutdown => reason,
+    };
+    // Stop accepting connections, then give requests in flight the grace period.
+    let _ = stop.send(());
+    println!("Shutting down on {}; waiting up to {:?} for requests in flight", reason, grace);
+    let started = Instant::now();
+    match tokio::time::timeout(grace, &mut server).await {
+        Ok(result) => result?,
+        Err(_) => eprintln!("Warning: grace period over, dropping the remaining connections"),
+    }
+    println!("Stopped in {:.1}s", started.elapsed().as_secs_f64());
+    Ok(())
+}
+
+/// Serve HTTP on the Unix socket `path` until SIGINT/SIGTERM, then give requests
+/// in flight `grace` to finish. Connections have no peer IP, so handlers see
+/// 127.0.0.1 (the proxy); use --trust-proxy to take the client's from its headers.
+#[cfg(unix)]
+async fn serve_unix(
+    app: axum::Router,
+    path: &Path,
+    mode: Option<u32>,
+    grace: Duration,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::server::graceful::GracefulShutdown;
+    use hyper_util::service::TowerToHyperService;
+    use std::os::unix::fs::PermissionsExt;
+
+    remove_stale_socket(path)?;
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| format!("cannot listen on {}: {}", path.display(), e))?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    println!("Listening on unix:{}", path.display());
+    let peer = axum::extract::ConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+    let app = app.layer(axum::Extension(peer));
+    let builder = Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    let mut signal = std::pin::pin!(shutdown_signal());
+    let reason = loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "accept failed");
+                    continue;
+                }
+            },
+            reason = &mut signal => break reason,
+        };
+        let service = TowerToHyperService::new(app.clone());
+        let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service).into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!(error = %e, "connection closed with an error");
+            }
+        });
+    };
+    // Stop accepting connections, then give requests in flight the grace period.
+    drop(listener);
+    let _ = std::fs::remove_file(path);
+    println!("Shutting down on {}; waiting up to {:?} for requests in flight", reason, grace);
+    let started = Instant::now();
+    if tokio::time::timeout(grace, graceful.shutdown()).await.is_err() {
+        eprintln!("Warning: grace period over, dropping the remaining connections");
+    }
+    println!("Stopped in {:.1}s", started.elapsed().as_secs_f64());
+    Ok(())
+}
+
+/// Remove a socket file left behind by a server that's gone. A socket something
+/// still listens on, or a path that isn't a socket, is an error instead.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(format!("{} exists and is not a socket", path.display()).into());
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(format!("{} is in use by another server", path.display()).into());
+    }
+    std::fs::remove_file(path).map_err(|e| format!("cannot remove stale socket {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Serve HTTPS with `tls` on `listener` until `shutdown` resolves (to the reason),
+/// then give requests in flight `grace` to finish.
+async fn serve_https(
+    app: axum::Router,
+    listener: std::net::TcpListener,
+    tls: RustlsConfig,
+    grace: Duration,
+    shutdown: impl Future<Output = &'static str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let handle = axum_server::Handle::new();
+    let server = axum_server::from_tcp_rustls(listener, tls)
+        .handle(handle.clone())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+    let mut server = tokio::spawn(server);
+    match handle.listening().await {
+        Some(bound) => println!("Listening on https://{}", bound),
+        None => {
+            // Setting up the listener failed; the server task ended with the error.
+            server.await.map_err(|e| e.to_string())??;
+            return Ok(());
+        }
+    }
+    let reason = tokio::select! {
+        result = &mut server => {
+            result.map_err(|e| e.to_string())??;
+            return Ok(());
+        }
+        reason = shutdown => reason,
+    };
+    // Stops accepting connections; those still open after `grace` are closed.
+    handle.graceful_shutdown(Some(grace));
+    println!("Shutting down on {}; waiting up to {:?} for requests in flight", reason, grace);
+    let started = Instant::now();
+    server.await.map_err(|e| e.to_string())??;
+    println!("Stopped in {:.1}s", started.elapsed().as_secs_f64());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{HeaderMap, Request, StatusCode};
+    use http_body_util::BodyExt;
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    type ServeResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Serve `app` with `serve` on an ephemeral port. Sending on the returned
+    /// channel stands in for SIGTERM.
+    fn spawn_server<F, S>(
+        serve: S,
+    ) -> (SocketAddr, tokio::sync::oneshot::Sender<()>, tokio::task::JoinHandle<ServeResult>)
+    where
+        S: FnOnce(std::net::TcpListener, tokio::sync::oneshot::Receiver<()>) -> F,
+        F: Future<Output = ServeResult> + Send + 'static,
+    {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel();
+        (addr, stop, tokio::spawn(serve(listener, stopped)))
+    }
+
+    async fn stopped(stop: tokio::sync::oneshot::Receiver<()>) -> &'static str {
+        let _ = stop.await;
+        "test"
+    }
+
+    /// A self-signed certificate for `localhost` and its private key, written as PEM
+    /// files named after `name`; returns their paths.
+    fn self_signed_cert(name: &str) -> (String, String) {
+        use openssl::{asn1::Asn1Time, bn::BigNum, ec, hash::MessageDigest, nid::Nid, pkey::PKey, x509};
+        let group = ec::EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(ec::EcKey::generate(&group).unwrap()).unwrap();
+        let mut subject = x509::X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", "localhost").unwrap();
+        let subject = subject.build();
+        let mut cert = x509::X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let names = x509::extension::SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&cert.x509v3_context(None, None))
+            .unwrap();
+        cert.append_extension(names).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let (cert_path, key_path) = (temp_file(&format!("{}-cert.pem", name)), temp_file(&format!("{}-key.pem", name)));
+        std::fs::write(&cert_path, cert.build().to_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path.display().to_string(), key_path.display().to_string())
+    }
+
+    #[tokio::test]
+    async fn serves_https_with_the_configured_certificate() {
+        let (cert, key) = self_signed_cert("serves-https");
+        let tls = TlsFiles::load(&cert, &key).await.unwrap();
+        let app = axum::Router::new().route("/healthz", axum::routing::get(|| async { "ok" }));
+        let (addr, stop, server) = spawn_server(|listener, stop| {
+            serve_https(app, listener, tls.config, Duration::from_secs(1), stopped(stop))
+        });
+
+        let client = reqwest::Client::builder().danger_accept_invalid_certs(true).build().unwrap();
+        let response = client.get(format!("https://localhost:{}/healthz", addr.port())).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        // The certificate is self-signed, so a verifying client refuses it.
+        let verified = reqwest::get(format!("https://localhost:{}/healthz", addr.port())).await;
+        assert!(verified.is_err());
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        std::fs::remove_file(cert).unwrap();
+        std::fs::remove_file(key).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreadable_tls_files_fail_at_load() {
+        let (cert, key) = self_signed_cert("unreadable-tls");
+        // Swapped: the key file has no certificate in it.
+        assert!(TlsFiles::load(&key, &cert).await.is_err());
+        assert!(TlsFiles::load(&cert, "does/not/exist.pem").await.is_err());
+        std::fs::remove_file(cert).unwrap();
+        std::fs::remove_file(key).unwrap();
+    }
+
+    /// An in-memory index of (url, body) documents.
+    fn docs_index(docs: &[(&str, &str)]) -> index::IndexWithTf {
+        let text: Vec<index::DocumentText> = docs
+            .iter()
+            .map(|&(url, body)| index::DocumentText {
+                url,
+                title: "",
+                headings: "",
+                body,
+            })
+            .collect();
+        let mut idx = index::IndexWithTf::default();
+        idx.add_documents(&text);
+        idx
+    }
+
+    /// A loader that serves a copy of `idx` every time.
+    fn memory_loader(idx: index::IndexWithTf) -> search::Loader {
+        Arc::new(move || {
+            let served = search::IndexBackend::Memory(Arc::new(idx.clone()));
+            let load_info = search::LoadInfo::new(&served, Duration::ZERO);
+            Ok(search::LoadedIndex::new(served, None, load_info))
+        })
+    }
+
+    /// State serving one index as the default, with a query cache of `cache_size`.
+    fn app_state(loader: search::Loader, cache_size: usize, writes: bool) -> search::AppState {
+        let cache = search::QueryCache::new(cache_size).map(Arc::new);
+        let served = search::NamedIndex::load(loader, None, cache).unwrap();
+        search::AppState {
+            indexes: Arc::new(HashMap::from([(search::DEFAULT_INDEX_NAME.to_string(), served)])),
+            default_index: search::DEFAULT_INDEX_NAME.to_string(),
+            boosts: index::FieldBoosts::default(),
+            normalize: search::ScoreNormalization::default(),
+            ready: Arc::new(AtomicBool::new(true)),
+            writes,
+            metrics: Arc::new(search::ServerMetrics::start()),
+            search_max_age: 0,
+            query_log: None,
+            crawls: Arc::default(),
+        }
+    }
+
+    /// `app` as served to a client at 127.0.0.1, for requests sent with `oneshot`.
+    fn with_peer(app: axum::Router) -> axum::Router {
+        app.layer(axum::Extension(axum::extract::ConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 40000)))))
+    }
+
+    /// The default router (no rate limit, API key, static files or CORS).
+    fn plain_app(state: search::AppState) -> axum::Router {
+        with_peer(router(state, None, None, None, None))
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn post_json(uri: &str, body: serde_json::Value) -> Request<Body> {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// Send `request` to `app`, returning the status, headers and body.
+    async fn send(app: &axum::Router, request: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        (parts.status, parts.headers, body.collect().await.unwrap().to_bytes().to_vec())
+    }
+
+    /// Send `request` to `app`, returning the status and the JSON body.
+    async fn send_json(app: &axum::Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let (status, _, body) = send(app, request).await;
+        let json = serde_json::from_slice(&body).unwrap_or_else(|_| panic!("not JSON: {}", String::from_utf8_lossy(&body)));
+        (status, json)
+    }
+
+    /// URLs of the hits in a `/search` response.
+    fn hit_urls(response: &serde_json::Value) -> Vec<&str> {
+        response["hits"].as_array().unwrap().iter().map(|hit| hit["url"].as_str().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn writes_clear_the_query_cache() {
+        let idx = docs_index(&[("https://a.example/", "rust crawler"), ("https://b.example/", "tokio")]);
+        let app = plain_app(app_state(memory_loader(idx), 10, true));
+        let cache_stats = || async {
+            let (_, stats) = send_json(&app, get("/stats")).await;
+            stats["indexes"][0]["query_cache"].clone()
+        };
+
+        for _ in 0..2 {
+            let (status, response) = send_json(&app, get("/search?q=rust")).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(hit_urls(&response), ["https://a.example/"]);
+        }
+        let stats = cache_stats().await;
+        assert_eq!((stats["hits"].as_u64(), stats["misses"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(stats["entries"], 1);
+
+        let doc = serde_json::json!({"url": "https://c.example/", "body": "rust rust rust"});
+        let (status, _) = send_json(&app, post_json("/documents", doc)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cache_stats().await["entries"], 0);
+        let (_, response) = send_json(&app, get("/search?q=rust")).await;
+        assert_eq!(hit_urls(&response), ["https://c.example/", "https://a.example/"]);
+
+        let delete = Request::delete("/documents?url=https://c.example/").body(Body::empty()).unwrap();
+        let (status, _) = send_json(&app, delete).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cache_stats().await["entries"], 0);
+        let (_, response) = send_json(&app, get("/search?q=rust")).await;
+        assert_eq!(hit_urls(&response), ["https://a.example/"]);
+    }
+
+    #[tokio::test]
+    async fn health_and_readiness() {
+        let idx = docs_index(&[("https://a.example/", "rust"), ("https://b.example/", "tokio")]);
+        let state = app_state(memory_loader(idx), 0, false);
+        state.ready.store(false, std::sync::atomic::Ordering::Release);
+        let app = plain_app(state.clone());
+
+        let (status, health) = send_json(&app, get("/healthz")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health, serde_json::json!({"status": "ok"}));
+        let (status, ready) = send_json(&app, get("/readyz")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready, serde_json::json!({"status": "loading"}));
+
+        state.ready.store(true, std::sync::atomic::Ordering::Release);
+        let (status, ready) = send_json(&app, get("/readyz")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ready, serde_json::json!({"status": "ready", "doc_count": 2}));
+    }
+
+    /// A path in the system temp directory, unique to this process and `name`.
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rustysearch-test-{}-{}", std::process::id(), name))
+    }
+
+    /// A loader that reads `path` like `serve` does.
+    fn file_loader(path: PathBuf) -> search::Loader {
+        Arc::new(move || load_snapshot(&path, Backend::File, false, None, false))
+    }
+
+    #[test]
+    fn stale_analyzers_need_force() {
+        let path = temp_file("stale-analyzer.json");
+        let mut idx = docs_index(&[("https://a.example/", "the rust crawler")]);
+        idx.analyzer = tokenize::StandardAnalyzer::with_stopwords(tokenize::Stopwords::builtin(
+            tokenize::StopwordLanguage::English,
+        ))
+        .into();
+        index::save_index_with_tf(&idx, &path).unwrap();
+        assert!(load_snapshot(&path, Backend::File, false, None, false).is_ok());
+
+        // An index from a build whose English stopword list lacked "the".
+        let mut json: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        let words = json["data"]["analyzer"]["standard"]["stopwords"]["words"].as_array_mut().unwrap();
+        words.retain(|word| word != "the");
+        std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+        let error = load_snapshot(&path, Backend::File, false, None, false).err().unwrap();
+        assert!(error.to_string().contains("differs from the index's in stopwords"), "{}", error);
+        assert!(load_snapshot(&path, Backend::File, true, None, false).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_swaps_in_the_new_file() {
+        let path = temp_file("reload.json");
+        let first = docs_index(&[("https://a.example/", "rust crawler")]);
+        index::save_index_with_tf(&first, &path).unwrap();
+        let app = plain_app(app_state(file_loader(path.clone()), 10, false));
+        let (_, response) = send_json(&app, get("/search?q=rust")).await;
+        assert_eq!(hit_urls(&response), ["https://a.example/"]);
+
+        let second = docs_index(&[("https://b.example/", "rust"), ("https://c.example/", "tokio")]);
+        index::save_index_with_tf(&second, &path).unwrap();
+        let (status, reloaded) = send_json(&app, Request::post("/admin/reload").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reloaded["index"], search::DEFAULT_INDEX_NAME);
+        assert_eq!(reloaded["doc_count"], 2);
+        assert!(reloaded["load_time_ms"].is_number());
+        let (_, response) = send_json(&app, get("/search?q=rust")).await;
+        assert_eq!(hit_urls(&response), ["https://b.example/"]);
+
+        // A broken file is refused, and the second index keeps serving.
+        std::fs::write(&path, "{ not an index").unwrap();
+        let (status, error) = send_json(&app, Request::post("/admin/reload").body(Body::empty()).unwrap()).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(error["error"]["message"].as_str().unwrap().contains("still serving the previous index"));
+        let (_, response) = send_json(&app, get("/search?q=tokio")).await;
+        assert_eq!(hit_urls(&response), ["https://c.example/"]);
+    }
+
+    #[tokio::test]
+    async fn cors_headers_only_for_configured_origins() {
+        let state = || app_state(memory_loader(docs_index(&[("https://a.example/", "rust")])), 0, false);
+        let origins = ["https://site.example/".to_string()];
+        let app = with_peer(router(state(), None, None, None, cors_layer(&origins, false).unwrap()));
+        let from = |origin: &str| {
+            Request::get("/search?q=rust").header(header::ORIGIN, origin).body(Body::empty()).unwrap()
+        };
+        let allowed = |headers: &HeaderMap| headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).cloned();
+
+        let (status, headers, _) = send(&app, from("https://site.example")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allowed(&headers).unwrap(), "https://site.example");
+        let (status, headers, _) = send(&app, from("https://other.example")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allowed(&headers), None);
+
+        let preflight = |method: &str| {
+            Request::options("/search")
+                .header(header::ORIGIN, "https://site.example")
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, headers, _) = send(&app, preflight("GET")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allowed(&headers).unwrap(), "https://site.example");
+        let methods = headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap().to_str().unwrap();
+        assert_eq!(methods, "GET");
+
+        // Writes add their methods; without --cors-origin there are no CORS headers.
+        let app = with_peer(router(state(), None, None, None, cors_layer(&origins, true).unwrap()));
+        let (_, headers, _) = send(&app, preflight("POST")).await;
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "GET,POST,DELETE");
+        assert!(cors_layer(&[], true).unwrap().is_none());
+        let (_, headers, _) = send(&plain_app(state()), from("https://site.example")).await;
+        assert_eq!(allowed(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_searches_get_429_with_retry_after() {
+        let state = app_state(memory_loader(docs_index(&[("https://a.example/", "rust")])), 0, false);
+        let rate = ratelimit::parse_rate("2/m").unwrap();
+        let limit = ratelimit::RateLimit {
+            limiter: Arc::new(ratelimit::RateLimiter::new(rate, Instant::now())),
+            trust_proxy: false,
+        };
+        let app = with_peer(router(state, Some(limit), None, None, None));
+        for _ in 0..2 {
+            assert_eq!(send(&app, get("/search?q=rust")).await.0, StatusCode::OK);
+        }
+        let (status, headers, body) = send(&app, get("/search?q=rust")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers[header::RETRY_AFTER], "30");
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["code"], "rate_limited");
+        // Only the search routes are limited.
+        assert_eq!(send(&app, get("/healthz")).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn admin_routes_require_the_api_key() {
+        let state = app_state(memory_loader(docs_index(&[("https://a.example/", "rust")])), 0, true);
+        let app = with_peer(router(state, None, Some(Arc::from("s3cret")), None, None));
+        let reload = |header: Option<(&str, &str)>| {
+            let mut request = Request::post("/admin/reload");
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let rejected = [
+            None,
+            Some(("authorization", "Bearer wrong")),
+            Some(("authorization", "Bearer s3cre")),
+            Some(("authorization", "Basic s3cret")),
+            Some(("x-api-key", "S3CRET")),
+        ];
+        for header in rejected {
+            let (status, error) = send_json(&app, reload(header)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", header);
+            assert_eq!(error["error"]["code"], "unauthorized");
+        }
+        for header in [("authorization", "Bearer s3cret"), ("x-api-key", "s3cret")] {
+            assert_eq!(send(&app, reload(Some(header))).await.0, StatusCode::OK, "{:?}", header);
+        }
+        // Other Authorization schemes fall through to X-Api-Key; a wrong bearer key doesn't.
+        let with_key = |authorization: &str| {
+            let request = Request::post("/admin/reload").header("authorization", authorization);
+            request.header("x-api-key", "s3cret").body(Body::empty()).unwrap()
+        };
+        assert_eq!(send(&app, with_key("Basic dXNlcjpwYXNz")).await.0, StatusCode::OK);
+        assert_eq!(send(&app, with_key("Bearer wrong")).await.0, StatusCode::UNAUTHORIZED);
+        let document = serde_json::json!({"url": "https://b.example/", "body": "tokio"});
+        assert_eq!(send(&app, post_json("/documents", document)).await.0, StatusCode::UNAUTHORIZED);
+        // Searching needs no key.
+        let (status, response) = send_json(&app, get("/search?q=rust")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hit_urls(&response), ["https://a.example/"]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_requests_in_flight_finish() {
+        let started = Arc::new(tokio::sync::Notify::new());
+        let handler_started = started.clone();
+        let app = axum::Router::new().route(
+            "/slow",
+            axum::routing::get(move || async move {
+                handler_started.notify_one();
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "done"
+            }),
+        );
+        let (addr, stop, server) =
+            spawn_server(|listener, stop| serve_http(app, listener, Duration::from_secs(10), stopped(stop)));
+
+        let url = format!("http://{}/slow", addr);
+        let request = tokio::spawn(async move { reqwest::get(url).await?.text().await });
+        started.notified().await;
+        stop.send(()).unwrap();
+        assert_eq!(request.await.unwrap().unwrap(), "done");
+        server.await.unwrap().unwrap();
+        // The listener is closed once the server has returned.
+        assert!(reqwest::get(format!("http://{}/slow", addr)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_boolean_queries_are_400_with_the_position() {
+        let app = plain_app(app_state(memory_loader(docs_index(&[("https://a.example/", "rust tokio")])), 0, false));
+        let (status, error) = send_json(&app, get("/search?q=rust%20AND%20(tokio")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"]["code"], "invalid_query");
+        assert_eq!(error["error"]["message"], "unmatched '('");
+        assert_eq!(error["error"]["position"], 9);
+        let (status, response) = send_json(&app, get("/search?q=rust%20AND%20(tokio)")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hit_urls(&response), ["https://a.example/"]);
+    }
+
+    #[tokio::test]
+    async fn collapse_keeps_the_best_hits_per_host() {
+        // Fewer mentions score lower, so hits rank in this order of hosts.
+        let hosts = ["a", "b", "a", "c", "a", "b", "a", "b"];
+        let pages: Vec<(String, String)> = hosts
+            .iter()
+            .enumerate()
+            .map(|(rank, host)| (format!("https://{}.example/{}", host, rank), "rust ".repeat(hosts.len() - rank)))
+            .collect();
+        let docs: Vec<(&str, &str)> = pages.iter().map(|(url, body)| (url.as_str(), body.as_str())).collect();
+        let app = plain_app(app_state(memory_loader(docs_index(&docs)), 0, false));
+        let collapsed_counts = |response: &serde_json::Value| -> Vec<Option<u64>> {
+            response["hits"].as_array().unwrap().iter().map(|hit| hit["collapsed_count"].as_u64()).collect()
+        };
+
+        let (_, all) = send_json(&app, get("/search?q=rust")).await;
+        assert_eq!(all["total"], 8);
+        assert_eq!(hit_urls(&all)[..3], ["https://a.example/0", "https://b.example/1", "https://a.example/2"]);
+
+        let (_, collapsed) = send_json(&app, get("/search?q=rust&collapse=host")).await;
+        let expected = [
+            "https://a.example/0",
+            "https://b.example/1",
+            "https://a.example/2",
+            "https://c.example/3",
+            "https://b.example/5",
+        ];
+        assert_eq!(hit_urls(&collapsed), expected);
+        assert_eq!(collapsed["total"], 5);
+        assert_eq!(collapsed_counts(&collapsed), [Some(2), Some(1), None, None, None]);
+
+        let (_, single) = send_json(&app, get("/search?q=rust&collapse=host&collapse_size=1")).await;
+        assert_eq!(hit_urls(&single), ["https://a.example/0", "https://b.example/1", "https://c.example/3"]);
+        assert_eq!(collapsed_counts(&single), [Some(3), Some(2), None]);
+
+        // Pagination applies to the collapsed list.
+        let (_, page) = send_json(&app, get("/search?q=rust&collapse=host&limit=2&offset=2")).await;
+        assert_eq!(hit_urls(&page), expected[2..4]);
+        assert_eq!(page["total"], 5);
+
+        // A host is expanded by searching it with `site` instead of collapsing.
+        let (_, expanded) = send_json(&app, get("/search?q=rust&site=a.example")).await;
+        assert_eq!(expanded["total"], 4);
+        assert_eq!(collapsed_counts(&expanded), [None; 4]);
+        let (status, _) = send_json(&app, get("/search?q=rust&collapse=host&collapse_size=0")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn static_dir_serves_files_without_shadowing_the_api_or_escaping() {
+        let root = temp_file("static-site");
+        let public = root.join("public");
+        std::fs::create_dir_all(public.join("sub")).unwrap();
+        std::fs::write(root.join("secret.txt"), "secret").unwrap();
+        std::fs::write(public.join("index.html"), "<h1>custom</h1>").unwrap();
+        std::fs::write(public.join("app.js"), "search();").unwrap();
+        std::fs::write(public.join("style.css"), "h1 {}").unwrap();
+        std::fs::write(public.join("search"), "shadow").unwrap();
+        let state = app_state(memory_loader(docs_index(&[("https://a.example/", "rust")])), 0, false);
+        let app = with_peer(router(state, None, None, Some(&public), None));
+
+        for (path, content_type, body) in [
+            ("/", "text/html", "<h1>custom</h1>"),
+            ("/index.html", "text/html", "<h1>custom</h1>"),
+            ("/app.js", "text/javascript", "search();"),
+            ("/style.css", "text/css", "h1 {}"),
+        ] {
+            let (status, headers, bytes) = send(&app, get(path)).await;
+            assert_eq!(status, StatusCode::OK, "{}", path);
+            assert!(headers[header::CONTENT_TYPE].to_str().unwrap().starts_with(content_type), "{}", path);
+            assert_eq!(headers[header::CACHE_CONTROL], "no-cache", "{}", path);
+            assert_eq!(bytes, body.as_bytes(), "{}", path);
+        }
+        // Routes win over files of the same name.
+        let (status, response) = send_json(&app, get("/search?q=rust")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hit_urls(&response), ["https://a.example/"]);
+        assert_eq!(send(&app, get("/missing.html")).await.0, StatusCode::NOT_FOUND);
+        for path in ["/../secret.txt", "/sub/../../secret.txt", "/%2e%2e/secret.txt", "/sub/..%2f..%2fsecret.txt"] {
+            let (status, _, bytes) = send(&app, get(path)).await;
+            assert_ne!(status, StatusCode::OK, "{}", path);
+            assert_ne!(bytes, b"secret", "{}", path);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // Without a static dir `/` is the built-in page.
+        let state = app_state(memory_loader(docs_index(&[("https://a.example/", "rust")])), 0, false);
+        let (status, headers, _) = send(&plain_app(state), get("/")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_reloads_writes_and_searches() {
+        // Every write adds two documents in one update and a reload goes back to
+        // the two base ones, so a snapshot with an odd count would be a torn write.
+        let base = docs_index(&[("https://base.example/1", "shared one"), ("https://base.example/2", "shared two")]);
+        let named = Arc::new(search::NamedIndex::load(memory_loader(base), None, None).unwrap());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let (named, stop) = (named.clone(), stop.clone());
+                tokio::task::spawn_blocking(move || {
+                    let mut reads = 0;
+                    while !stop.load(Ordering::Relaxed) || reads == 0 {
+                        let snapshot = named.snapshot();
+                        let search::IndexBackend::Memory(idx) = &snapshot.index else { unreachable!() };
+                        assert_eq!(idx.doc_count % 2, 0);
+                        assert_eq!(idx.search_ranked("shared").len(), idx.doc_count);
+                        reads += 1;
+                    }
+                })
+            })
+            .collect();
+        let writers: Vec<_> = (0..2)
+            .map(|writer| {
+                let named = named.clone();
+                tokio::task::spawn_blocking(move || {
+                    for n in 0..100 {
+                        let urls = [0, 1].map(|half| format!("https://w{}.example/{}/{}", writer, n, half));
+                        let bodies = [0, 1].map(|half| format!("shared w{}n{}h{}", writer, n, half));
+                        let docs: Vec<index::DocumentText> = urls
+                            .iter()
+                            .zip(&bodies)
+                            .map(|(url, body)| index::DocumentText {
+                                url,
+                                title: "",
+                                headings: "",
+                                body,
+                            })
+                            .collect();
+                        named.update(|idx| idx.add_documents(&docs)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let reloads = {
+            let named = named.clone();
+            tokio::spawn(async move {
+                for _ in 0..50 {
+                    named.reload().await.unwrap();
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let all_done = async {
+            for writer in writers {
+                writer.await.unwrap();
+            }
+            reloads.await.unwrap();
+            stop.store(true, Ordering::Relaxed);
+            for reader in readers {
+                reader.await.unwrap();
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(60), all_done).await.expect("deadlocked");
+        named.reload().await.unwrap();
+        let search::IndexBackend::Memory(idx) = &named.snapshot().index else { unreachable!() };
+        assert_eq!(idx.doc_count, 2);
+    }
+
+    #[tokio::test]
+    async fn search_etags_revalidate_until_the_index_changes() {
+        let mut state = app_state(memory_loader(docs_index(&[("https://a.example/", "rust")])), 10, true);
+        state.search_max_age = 60;
+        let app = plain_app(state);
+        let conditional = |uri: &str, etag: &str| {
+            Request::get(uri).header(header::IF_NONE_MATCH, etag).body(Body::empty()).unwrap()
+        };
+
+        let (status, headers, _) = send(&app, get("/search?q=rust")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CACHE_CONTROL], "public, max-age=60");
+        let etag = headers[header::ETAG].to_str().unwrap().to_string();
+        assert!(etag.starts_with("W/\""), "{}", etag);
+
+        let (status, headers, body) = send(&app, conditional("/search?q=rust", &etag)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(headers[header::ETAG], etag.as_str());
+        assert!(body.is_empty());
+        // Whitespace in the query doesn't matter, other parameters do.
+        assert_eq!(send(&app, conditional("/search?q=%20rust%20", &etag)).await.0, StatusCode::NOT_MODIFIED);
+        let listed = format!("W/\"other\", {}", etag);
+        assert_eq!(send(&app, conditional("/search?q=rust", &listed)).await.0, StatusCode::NOT_MODIFIED);
+        assert_eq!(send(&app, conditional("/search?q=rust&limit=5", &etag)).await.0, StatusCode::OK);
+        assert_eq!(send(&app, conditional("/search?q=go", &etag)).await.0, StatusCode::OK);
+
+        // A reload is a new snapshot, even of the same file.
+        let reload = Request::post("/admin/reload").body(Body::empty()).unwrap();
+        assert_eq!(send(&app, reload).await.0, StatusCode::OK);
+        let (status, headers, _) = send(&app, conditional("/search?q=rust", &etag)).await;
+        assert_eq!(status, StatusCode::OK);
+        let reloaded = headers[header::ETAG].to_str().unwrap().to_string();
+        assert_ne!(reloaded, etag);
+
+        // So is a write.
+        let document = serde_json::json!({"url": "https://b.example/", "body": "rust tokio"});
+        assert!(send(&app, post_json("/documents", document)).await.0.is_success());
+        let (status, response) = send_json(&app, conditional("/search?q=rust", &reloaded)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["total"], 2);
+    }
+
+    #[tokio::test]
+    async fn min_score_applies_in_the_requested_scale() {
+        let idx = docs_index(&[
+            ("https://a.example/", "rust rust rust rust rust rust rust rust"),
+            ("https://b.example/", "rust rust rust"),
+            ("https://c.example/", "rust"),
+            ("https://d.example/", "go"),
+        ]);
+        let app = plain_app(app_state(memory_loader(idx), 0, false));
+        let scores = |response: &serde_json::Value| -> Vec<f64> {
+            response["hits"].as_array().unwrap().iter().map(|hit| hit["score"].as_f64().unwrap()).collect()
+        };
+
+        for normalize in ["none", "max", "sigmoid"] {
+            let (_, all) = send_json(&app, get(&format!("/search?q=rust&normalize={}", normalize))).await;
+            let all_scores = scores(&all);
+            assert_eq!(all_scores.len(), 3, "{}", normalize);
+            // Halfway between the second and third hit, in this scale.
+            let min = (all_scores[1] + all_scores[2]) / 2.0;
+            let uri = format!("/search?q=rust&normalize={}&min_score={}", normalize, min);
+            let (status, filtered) = send_json(&app, get(&uri)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(hit_urls(&filtered), ["https://a.example/", "https://b.example/"], "{}", normalize);
+            assert_eq!(scores(&filtered), all_scores[..2], "{}", normalize);
+            // Hits are dropped before `total` and pagination.
+            let (_, page) = send_json(&app, get(&format!("{}&limit=1&offset=1", uri))).await;
+            assert_eq!(page["total"], 2);
+            assert_eq!(hit_urls(&page), ["https://b.example/"]);
+        }
+        let (_, top) = send_json(&app, get("/search?q=rust&normalize=max&min_score=1")).await;
+        assert_eq!(hit_urls(&top), ["https://a.example/"]);
+        let (_, none) = send_json(&app, get("/search?q=rust&normalize=sigmoid&min_score=1")).await;
+        assert_eq!(none["total"], 0);
+        let (_, everything) = send_json(&app, get("/search?q=rust&min_score=0")).await;
+        assert_eq!(everything["total"], 3);
+
+        for invalid in ["-1", "NaN", "high"] {
+            let (status, error) = send_json(&app, get(&format!("/search?q=rust&min_score={}", invalid))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", invalid);
+            assert_eq!(error["error"]["code"], "invalid_parameter", "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn deep_offsets_are_refused() {
+        let app = plain_app(app_state(memory_loader(docs_index(&[("https://a.example/", "rust")])), 0, false));
+        for offset in ["10001", "1000000000000", &usize::MAX.to_string()] {
+            let (status, error) = send_json(&app, get(&format!("/search?q=rust&offset={}", offset))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", offset);
+            assert_eq!(error["error"]["code"], "invalid_parameter");
+        }
+        let (status, response) = send_json(&app, get("/search?q=rust&offset=10000")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["total"], 1);
+        assert!(hit_urls(&response).is_empty());
+    }
+
+    #[tokio::test]
+    async fn similar_limit_is_clamped() {
+        let docs: Vec<(String, String)> =
+            (0..120).map(|n| (format!("https://{}.example/", n), format!("rust tokio crate{}", n))).collect();
+        let docs: Vec<(&str, &str)> = docs.iter().map(|(url, body)| (url.as_str(), body.as_str())).collect();
+        let app = plain_app(app_state(memory_loader(docs_index(&docs)), 0, false));
+        for limit in ["1000", "1000000000000", &usize::MAX.to_string()] {
+            let uri = format!("/similar?url=https://0.example/&limit={}", limit);
+            let (status, hits) = send_json(&app, get(&uri)).await;
+            assert_eq!(status, StatusCode::OK, "{}", limit);
+            assert_eq!(hits.as_array().unwrap().len(), 100, "{}", limit);
+        }
+    }
+
+    #[tokio::test]
+    async fn related_limit_is_clamped() {
+        // 150 terms that each share one document with "rust", among unrelated documents.
+        let docs: Vec<(String, String)> = (0..300)
+            .map(|n| {
+                let body = if n < 150 { format!("rust word{}", n) } else { format!("other{}", n) };
+                (format!("https://{}.example/", n), body)
+            })
+            .collect();
+        let docs: Vec<(&str, &str)> = docs.iter().map(|(url, body)| (url.as_str(), body.as_str())).collect();
+        let app = plain_app(app_state(memory_loader(docs_index(&docs)), 0, false));
+        for limit in ["5", "1000000000000", &usize::MAX.to_string()] {
+            let (status, terms) = send_json(&app, get(&format!("/related?term=rust&limit={}", limit))).await;
+            assert_eq!(status, StatusCode::OK, "{}", limit);
+            assert_eq!(terms.as_array().unwrap().len(), limit.parse::<usize>().unwrap().min(100), "{}", limit);
+        }
+    }
+
+    #[tokio::test]
+    async fn prefix_search_over_http() {
+        let idx = docs_index(&[("https://token.example/", "rust token"), ("https://tokio.example/", "rust tokio")]);
+        let app = plain_app(app_state(memory_loader(idx), 0, false));
+        let (_, exact) = send_json(&app, get("/search?q=tok")).await;
+        assert_eq!(exact["total"], 0);
+        let (_, prefix) = send_json(&app, get("/search?q=tok&prefix=true")).await;
+        assert_eq!(prefix["total"], 2);
+    }
+}
@@ -0,0 +1,107 @@
+//! OpenAPI 3 description of the HTTP API (`GET /openapi.json`), generated from
+//! the handler annotations and request/response types in `search`.
+
+use axum::extract::State;
+use axum::Json;
+use utoipa::OpenApi;
+
+use crate::index::{Facet, FacetBucket, Ranker, RelaxStep, Relaxation, SortBy, SortOrder, TfNorm};
+use crate::search::{self, AppState};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "RustySearch", description = "Search API of a RustySearch server."),
+    paths(
+        search::index_page,
+        search::search_handler,
+        search::similar_handler,
+        search::document_handler,
+        search::related_handler,
+        search::suggest_handler,
+        search::indexes_handler,
+        search::stats_handler,
+        search::healthz_handler,
+        search::readyz_handler,
+        search::reload_handler,
+        search::crawl_handler,
+        search::crawl_status_handler,
+        search::documents_handler,
+        search::delete_document_handler,
+    ),
+    components(schemas(
+        search::SearchResponse,
+        search::SearchHit,
+        search::DocumentInfo,
+        search::DocumentTerm,
+        search::RelatedTerm,
+        search::IndexInfo,
+        search::ServerStats,
+        search::IndexStats,
+        search::LoadInfo,
+        search::CacheStats,
+        search::Health,
+        search::ReloadResponse,
+        search::CrawlRequest,
+        search::CrawlStatus,
+        search::CrawlJobInfo,
+        search::NewDocument,
+        search::NewDocuments,
+        search::DocumentsResponse,
+        search::DeleteDocumentResponse,
+        search::ErrorResponse,
+        search::ApiErrorBody,
+        search::QueryMode,
+        search::Fallback,
+        search::ResponseFormat,
+        search::ScoreNormalization,
+        Ranker,
+        TfNorm,
+        Facet,
+        FacetBucket,
+        Relaxation,
+        RelaxStep,
+        SortBy,
+        SortOrder,
+    ))
+)]
+struct ApiDoc;
+
+/// Routes that only do anything with `--enable-writes`.
+const WRITE_PATHS: &[&str] = &["/documents", "/admin/crawl", "/admin/crawl/{id}"];
+
+/// The API as this server serves it: write routes are left out unless enabled.
+pub fn document(writes: bool) -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    if !writes {
+        for path in WRITE_PATHS {
+            doc.paths.paths.remove(*path);
+        }
+    }
+    doc
+}
+
+/// GET /openapi.json -> the OpenAPI document for this server.
+pub async fn openapi_handler(State(state): State<AppState>) -> Json<utoipa::openapi::OpenApi> {
+    Json(document(state.writes))
+}
+
+/// GET /docs -> Swagger UI for `/openapi.json` (loaded from a CDN).
+pub async fn docs_page() -> axum::response::Html<&'static str> {
+    const HTML: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="UTF-8">
+  <title>RustySearch API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: '/openapi.json', dom_id: '#swagger-ui' });
+  </script>
+</body>
+</html>
+"#;
+    axum::response::Html(HTML)
+}
@@ -0,0 +1,531 @@
+//! Boolean query syntax: `rust AND (tokio OR async) NOT beginner "error handling"`.
+//! Operators are the upper-case words `AND`, `OR` and `NOT`, with parentheses for
+//! grouping. `NOT` (or a leading `-`) binds tightest, then `AND`, then `OR`, and
+//! clauses written side by side are ANDed. Queries without operators or
+//! parentheses never come here, so they keep the ranked any-term behavior of
+//! `ParsedQuery`.
+//!
+//! Field prefixes (`title:rust`, `url:blog`, `headings:"error handling"`) work in
+//! both kinds of query; see `split_scope`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::index::{DocId, Field};
+
+/// The field a `field:` prefix restricts a word or phrase to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Body,
+    Field(Field),
+}
+
+impl Scope {
+    /// The scope called `name`: `body`, `title`, `headings` or `url`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "body" => Some(Scope::Body),
+            _ => Field::ALL.into_iter().find(|field| field.name() == name).map(Scope::Field),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Body => "body",
+            Scope::Field(field) => field.name(),
+        }
+    }
+}
+
+/// A `field:word` or `field:"phrase"` clause, analyzed: every token must occur in
+/// that field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scoped {
+    pub scope: Scope,
+    pub tokens: Vec<String>,
+    /// Quoted. In the body the tokens must then be adjacent and in order; other
+    /// fields have no positions, so there it only needs the tokens.
+    pub phrase: bool,
+}
+
+/// Split `title:rust` into its scope and the rest (empty for `title:` itself).
+/// None unless the prefix is a known field name, so `re:invent` or
+/// `http://example.com` are searched as literal text rather than rejected.
+pub fn split_scope(word: &str) -> Option<(Scope, &str)> {
+    let (name, rest) = word.split_once(':')?;
+    Some((Scope::from_name(name)?, rest))
+}
+
+/// `-word` as (true, `word`). A minus only negates when it starts a word
+/// ("-game"), not inside one ("e-mail").
+pub fn strip_negation(word: &str) -> (bool, &str) {
+    match word.strip_prefix('-') {
+        Some(rest) if rest.starts_with(|c: char| c.is_alphanumeric()) => (true, rest),
+        _ => (false, word),
+    }
+}
+
+/// A parsed boolean query. Words and phrases are stored analyzed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// A word, as the tokens it analyzes to (a document needs all of them); empty
+    /// for a stopword, which matches everything.
+    Term(Vec<String>),
+    /// A quoted phrase: its tokens adjacent and in order.
+    Phrase(Vec<String>),
+    /// A word or phrase with a field prefix.
+    Scoped(Scoped),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+impl Query {
+    /// Tokens of the unprefixed words and phrases that aren't negated, in query
+    /// order; these are what matching documents are scored on in every field.
+    pub fn positive_words(&self) -> Vec<String> {
+        let mut words = Vec::new();
+        self.visit(false, &mut |clause| {
+            if let Query::Term(tokens) | Query::Phrase(tokens) = clause {
+                words.extend(tokens.iter().cloned());
+            }
+        });
+        words
+    }
+
+    /// Field clauses that aren't negated; these score in their field only.
+    pub fn positive_scoped(&self) -> Vec<Scoped> {
+        let mut scoped = Vec::new();
+        self.visit(false, &mut |clause| {
+            if let Query::Scoped(clause) = clause {
+                scoped.push(clause.clone());
+            }
+        });
+        scoped
+    }
+
+    /// Tokens of every word and phrase, negated or not.
+    pub fn words(&self) -> Vec<String> {
+        let mut words = Vec::new();
+        self.visit(true, &mut |clause| match clause {
+            Query::Term(tokens) | Query::Phrase(tokens) => words.extend(tokens.iter().cloned()),
+            Query::Scoped(scoped) => words.extend(scoped.tokens.iter().cloned()),
+            _ => {}
+        });
+        words
+    }
+
+    /// Call `f` on every word, phrase and field clause, in query order.
+    fn visit(&self, negated_too: bool, f: &mut impl FnMut(&Query)) {
+        match self {
+            Query::Term(_) | Query::Phrase(_) | Query::Scoped(_) => f(self),
+            Query::And(clauses) | Query::Or(clauses) => {
+                for clause in clauses {
+                    clause.visit(negated_too, f);
+                }
+            }
+            Query::Not(inner) if negated_too => inner.visit(negated_too, f),
+            Query::Not(_) => {}
+        }
+    }
+}
+
+/// A syntax error in a boolean query, at a character offset into it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl QueryError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+    /// A phrase right after a `field:` or `-field:` word: whether it's negated,
+    /// the field and the phrase.
+    ScopedPhrase(bool, Scope, String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// Split a query into tokens with their character offsets. An unterminated quote
+/// runs to the end, as in `ParsedQuery`.
+fn lex(query: &str) -> Vec<(usize, Token)> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((i, Token::Open));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((i, Token::Close));
+                i += 1;
+            }
+            '"' => {
+                let end = chars[i + 1..].iter().position(|&c| c == '"').map_or(chars.len(), |n| i + 1 + n);
+                let phrase: String = chars[i + 1..end].iter().collect();
+                // `title:"…"`: the prefix was lexed as a word ending at this quote.
+                let prefix = match tokens.last() {
+                    Some((at, Token::Word(word))) if at + word.chars().count() == i => {
+                        let (negated, word) = strip_negation(word);
+                        match split_scope(word) {
+                            Some((scope, "")) => Some((*at, negated, scope)),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                match prefix {
+                    Some((at, negated, scope)) => {
+                        tokens.pop();
+                        tokens.push((at, Token::ScopedPhrase(negated, scope, phrase)));
+                    }
+                    None => tokens.push((i, Token::Phrase(phrase))),
+                }
+                i = end + 1;
+            }
+            _ => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c.is_whitespace() || matches!(c, '(' | ')' | '"'))
+                    .map_or(chars.len(), |n| i + n);
+                let word: String = chars[i..end].iter().collect();
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                };
+                tokens.push((i, token));
+                i = end;
+            }
+        }
+    }
+    tokens
+}
+
+/// True if `query` uses boolean syntax: an operator or a parenthesis outside quotes.
+pub fn is_boolean(query: &str) -> bool {
+    lex(query)
+        .iter()
+        .any(|(_, token)| !matches!(token, Token::Word(_) | Token::Phrase(_) | Token::ScopedPhrase(..)))
+}
+
+/// Parse a boolean query, analyzing words and phrases with `analyze`.
+pub fn parse(query: &str, analyze: impl Fn(&str) -> Vec<String>) -> Result<Query, QueryError> {
+    let mut parser = Parser {
+        tokens: lex(query),
+        next: 0,
+        end: query.chars().count(),
+        analyze: &analyze,
+    };
+    let tree = parser.or()?;
+    match parser.peek() {
+        Some((at, _)) => Err(QueryError::new(at, "unmatched ')'")),
+        None => Ok(tree),
+    }
+}
+
+/// Recursive descent over the tokens: `or := and (OR and)*`,
+/// `and := unary ([AND] unary)*`, `unary := NOT unary | ( or ) | word | phrase`,
+/// where words and phrases may carry a field prefix.
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Query length in characters, the position of errors at the end.
+    end: usize,
+    analyze: &'a dyn Fn(&str) -> Vec<String>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens.get(self.next).map(|(at, token)| (*at, token))
+    }
+
+    /// Consume the next token if it is `token`.
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek().is_some_and(|(_, next)| next == token);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut clauses = vec![self.and()?];
+        while self.eat(&Token::Or) {
+            clauses.push(self.and()?);
+        }
+        Ok(if clauses.len() == 1 {
+            clauses.remove(0)
+        } else {
+            Query::Or(clauses)
+        })
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut clauses = vec![self.unary()?];
+        loop {
+            let starts_clause = matches!(
+                self.peek(),
+                Some((_, Token::Word(_) | Token::Phrase(_) | Token::ScopedPhrase(..) | Token::Not | Token::Open))
+            );
+            if self.eat(&Token::And) || starts_clause {
+                clauses.push(self.unary()?);
+            } else {
+                break;
+            }
+        }
+        Ok(if clauses.len() == 1 {
+            clauses.remove(0)
+        } else {
+            Query::And(clauses)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Query, QueryError> {
+        let Some((at, token)) = self.tokens.get(self.next).cloned() else {
+            return Err(QueryError::new(self.end, "expected a word, phrase or '(' at the end of the query"));
+        };
+        self.next += 1;
+        match token {
+            Token::Not => Ok(Query::Not(Box::new(self.unary()?))),
+            Token::Open => {
+                let inner = self.or()?;
+                if !self.eat(&Token::Close) {
+                    return Err(QueryError::new(at, "unmatched '('"));
+                }
+                Ok(inner)
+            }
+            Token::Word(word) => {
+                let (negated, word) = strip_negation(&word);
+                let clause = match split_scope(word) {
+                    Some((scope, rest)) if !rest.is_empty() => Query::Scoped(Scoped {
+                        scope,
+                        tokens: (self.analyze)(rest),
+                        phrase: false,
+                    }),
+                    _ => Query::Term((self.analyze)(word)),
+                };
+                Ok(if negated { Query::Not(Box::new(clause)) } else { clause })
+            }
+            Token::Phrase(phrase) => Ok(Query::Phrase((self.analyze)(&phrase))),
+            Token::ScopedPhrase(negated, scope, phrase) => {
+                let clause = Query::Scoped(Scoped {
+                    scope,
+                    tokens: (self.analyze)(&phrase),
+                    phrase: true,
+                });
+                Ok(if negated { Query::Not(Box::new(clause)) } else { clause })
+            }
+            Token::Close => Err(QueryError::new(at, "unmatched ')'")),
+            Token::And => Err(QueryError::new(at, "AND needs a clause before it")),
+            Token::Or => Err(QueryError::new(at, "OR needs a clause before it")),
+        }
+    }
+}
+
+/// Documents matching a boolean clause. Negations are kept as the set of
+/// documents *not* matching, so `NOT x` never lists the whole collection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocSet {
+    Only(HashSet<DocId>),
+    Except(HashSet<DocId>),
+}
+
+impl DocSet {
+    /// Every document.
+    pub fn all() -> Self {
+        DocSet::Except(HashSet::new())
+    }
+
+    pub fn contains(&self, id: DocId) -> bool {
+        match self {
+            DocSet::Only(docs) => docs.contains(&id),
+            DocSet::Except(docs) => !docs.contains(&id),
+        }
+    }
+
+    pub fn not(self) -> Self {
+        match self {
+            DocSet::Only(docs) => DocSet::Except(docs),
+            DocSet::Except(docs) => DocSet::Only(docs),
+        }
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        match (self, other) {
+            (DocSet::Only(mut a), DocSet::Only(b)) => {
+                a.retain(|id| b.contains(id));
+                DocSet::Only(a)
+            }
+            (DocSet::Only(mut a), DocSet::Except(b)) | (DocSet::Except(b), DocSet::Only(mut a)) => {
+                a.retain(|id| !b.contains(id));
+                DocSet::Only(a)
+            }
+            (DocSet::Except(mut a), DocSet::Except(b)) => {
+                a.extend(b);
+                DocSet::Except(a)
+            }
+        }
+    }
+
+    pub fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (DocSet::Only(mut a), DocSet::Only(b)) => {
+                a.extend(b);
+                DocSet::Only(a)
+            }
+            (DocSet::Only(a), DocSet::Except(mut b)) | (DocSet::Except(mut b), DocSet::Only(a)) => {
+                b.retain(|id| !a.contains(id));
+                DocSet::Except(b)
+            }
+            (DocSet::Except(mut a), DocSet::Except(b)) => {
+                a.retain(|id| b.contains(id));
+                DocSet::Except(a)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(query: &str) -> Result<Query, QueryError> {
+        parse(query, |part| part.split_whitespace().map(str::to_lowercase).collect())
+    }
+
+    fn term(word: &str) -> Query {
+        Query::Term(vec![word.to_string()])
+    }
+
+    fn not(query: Query) -> Query {
+        Query::Not(Box::new(query))
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_tighter_than_or() {
+        let (a, b, c) = (term("a"), term("b"), term("c"));
+        let cases = [
+            ("a OR b AND c", Query::Or(vec![a.clone(), Query::And(vec![b.clone(), c.clone()])])),
+            ("a AND b OR c", Query::Or(vec![Query::And(vec![a.clone(), b.clone()]), c.clone()])),
+            ("a OR b c", Query::Or(vec![a.clone(), Query::And(vec![b.clone(), c.clone()])])),
+            ("NOT a b", Query::And(vec![not(a.clone()), b.clone()])),
+            ("NOT a OR b", Query::Or(vec![not(a.clone()), b.clone()])),
+            ("-a OR b", Query::Or(vec![not(a.clone()), b.clone()])),
+            ("NOT (a OR b)", not(Query::Or(vec![a.clone(), b.clone()]))),
+            ("(a OR b) c", Query::And(vec![Query::Or(vec![a.clone(), b.clone()]), c.clone()])),
+            ("a (b OR (c))", Query::And(vec![a.clone(), Query::Or(vec![b.clone(), c.clone()])])),
+            ("NOT NOT a", not(not(a.clone()))),
+            ("a OR b OR c", Query::Or(vec![a.clone(), b.clone(), c.clone()])),
+            ("A and b", Query::And(vec![a.clone(), term("and"), b.clone()])),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(tree(query), Ok(expected), "{}", query);
+        }
+    }
+
+    #[test]
+    fn parses_phrases_and_field_prefixes() {
+        let query = tree(r#"rust AND (tokio OR async) NOT beginner "error handling""#).unwrap();
+        let expected = Query::And(vec![
+            term("rust"),
+            Query::Or(vec![term("tokio"), term("async")]),
+            not(term("beginner")),
+            Query::Phrase(vec!["error".to_string(), "handling".to_string()]),
+        ]);
+        assert_eq!(query, expected);
+
+        let scoped = |scope, tokens: &[&str], phrase| {
+            Query::Scoped(Scoped {
+                scope,
+                tokens: tokens.iter().map(|t| t.to_string()).collect(),
+                phrase,
+            })
+        };
+        let query = tree(r#"title:tokio OR -url:"blog post" re:invent"#).unwrap();
+        let expected = Query::Or(vec![
+            scoped(Scope::Field(Field::Title), &["tokio"], false),
+            Query::And(vec![not(scoped(Scope::Field(Field::Url), &["blog", "post"], true)), term("re:invent")]),
+        ]);
+        assert_eq!(query, expected);
+        assert_eq!(split_scope("body:rust"), Some((Scope::Body, "rust")));
+        assert_eq!(split_scope("http://example.com"), None);
+        assert_eq!(strip_negation("-game"), (true, "game"));
+        assert_eq!(strip_negation("e-mail"), (false, "e-mail"));
+        assert_eq!(strip_negation("-"), (false, "-"));
+    }
+
+    #[test]
+    fn only_operators_and_parentheses_make_a_query_boolean() {
+        assert!(!is_boolean("rust async tokio"));
+        assert!(!is_boolean(r#"title:rust "error AND handling" -beginner"#));
+        assert!(!is_boolean("rust and or not"));
+        assert!(is_boolean("rust OR go"));
+        assert!(is_boolean("NOT beginner"));
+        assert!(is_boolean("(rust)"));
+    }
+
+    #[test]
+    fn syntax_errors_have_character_positions() {
+        let cases = [
+            ("(rust", 0, "unmatched '('"),
+            ("rust)", 4, "unmatched ')'"),
+            ("a (b OR (c)", 2, "unmatched '('"),
+            (")(", 0, "unmatched ')'"),
+            ("()", 1, "unmatched ')'"),
+            ("OR rust", 0, "OR needs a clause before it"),
+            ("rust AND AND go", 9, "AND needs a clause before it"),
+            ("rust AND", 8, "expected a word, phrase or '(' at the end of the query"),
+            ("NOT", 3, "expected a word, phrase or '(' at the end of the query"),
+            // Positions count characters, not bytes.
+            ("日本語 (rust", 4, "unmatched '('"),
+        ];
+        for (query, position, message) in cases {
+            let error = tree(query).unwrap_err();
+            assert_eq!((error.position, error.message.as_str()), (position, message), "{}", query);
+        }
+        assert_eq!(tree("(rust").unwrap_err().to_string(), "unmatched '(' at position 0");
+    }
+
+    #[test]
+    fn any_token_sequence_parses_or_fails_without_panicking() {
+        let parts = ["(", ")", "a", "AND", "OR", "NOT", "\"", "-", "title:", "é"];
+        let mut queries = vec![String::new()];
+        for _ in 0..4 {
+            queries = queries
+                .iter()
+                .flat_map(|query| parts.iter().map(move |part| format!("{} {}", query, part)))
+                .collect();
+            for query in &queries {
+                let _ = tree(query);
+                let _ = tree(&query.replace(' ', ""));
+            }
+        }
+    }
+}
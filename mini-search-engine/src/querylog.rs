@@ -0,0 +1,214 @@
+//! Opt-in search analytics (`serve --query-log`): one JSON line per search,
+//! written off the request path, and the `top-queries` report over such logs.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::{Duration, Instant};
+
+/// Entries waiting to be written; further searches go unlogged until there's room.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Longest an entry waits in the write buffer.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One logged search.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct QueryLogEntry {
+    pub timestamp: DateTime<Utc>,
+    /// The query lower-cased, with runs of whitespace collapsed to one space.
+    pub query: String,
+    pub total: usize,
+    pub took_ms: f64,
+    /// Client address with the host part zeroed (IPv4 /24, IPv6 /48).
+    pub client: String,
+}
+
+impl QueryLogEntry {
+    pub fn new(query: &str, total: usize, took_ms: f64, client: IpAddr) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            query: normalize_query(query),
+            total,
+            took_ms,
+            client: truncate_ip(client).to_string(),
+        }
+    }
+}
+
+/// Lower-case `query` and collapse its whitespace, so trivially different
+/// spellings of a query count together.
+pub fn normalize_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// The network of `ip` rather than the host: enough for rough geography, not
+/// enough to identify a user.
+fn truncate_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(ip) => {
+            let s = ip.segments();
+            IpAddr::from([s[0], s[1], s[2], 0, 0, 0, 0, 0])
+        }
+    }
+}
+
+/// Handle to the log writer thread. Recording never blocks: when the queue is
+/// full (the disk can't keep up), entries are dropped.
+#[derive(Clone)]
+pub struct QueryLog {
+    sender: SyncSender<QueryLogEntry>,
+}
+
+impl QueryLog {
+    /// Open `path` for appending and start the writer thread.
+    pub fn start(path: PathBuf) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("query-log".to_string())
+            .spawn(move || write_entries(&path, file, receiver))?;
+        Ok(Self { sender })
+    }
+
+    pub fn record(&self, entry: QueryLogEntry) {
+        let _ = self.sender.try_send(entry);
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?))
+}
+
+/// Writer thread: buffer entries, and every `FLUSH_INTERVAL` flush them and
+/// reopen the path if writing failed or the file was rotated away (moved or
+/// deleted), so a new file is started in its place. Ends when every `QueryLog`
+/// is dropped.
+fn write_entries(path: &Path, file: BufWriter<File>, receiver: Receiver<QueryLogEntry>) {
+    let mut file = Some(file);
+    let mut flushed = Instant::now();
+    loop {
+        let entry = match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(entry) => Some(entry),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if let (Some(entry), Some(writer)) = (entry, file.as_mut()) {
+            let written = serde_json::to_writer(&mut *writer, &entry)
+                .map_err(io::Error::from)
+                .and_then(|()| writer.write_all(b"\n"));
+            if let Err(e) = written {
+                tracing::warn!(path = %path.display(), error = %e, "query log write failed; reopening");
+                file = None;
+            }
+        }
+        if flushed.elapsed() < FLUSH_INTERVAL {
+            continue;
+        }
+        flushed = Instant::now();
+        if let Some(Err(e)) = file.as_mut().map(|writer| writer.flush()) {
+            tracing::warn!(path = %path.display(), error = %e, "query log write failed; reopening");
+            file = None;
+        }
+        if file.as_ref().is_none_or(|writer| rotated(path, writer)) {
+            file = match open_append(path) {
+                Ok(reopened) => Some(reopened),
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), error = %e, "cannot open query log");
+                    None
+                }
+            };
+        }
+    }
+    if let Some(mut writer) = file {
+        let _ = writer.flush();
+    }
+}
+
+/// True if `path` no longer names the open file. Checked on Unix by inode;
+/// elsewhere a rotation shows up as a write error instead.
+fn rotated(path: &Path, file: &BufWriter<File>) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (std::fs::metadata(path), file.get_ref().metadata()) {
+            (Ok(named), Ok(open)) => named.ino() != open.ino() || named.dev() != open.dev(),
+            _ => true,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, file);
+        false
+    }
+}
+
+/// Most frequent queries in a log.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct TopQueries {
+    /// Searches counted (inside the time window).
+    pub searches: usize,
+    /// (query, count), most frequent first.
+    pub top: Vec<(String, usize)>,
+    /// Queries that found nothing, most frequent first.
+    pub zero_results: Vec<(String, usize)>,
+}
+
+/// Count the searches of the log at `path` made at or after `since` (all when
+/// None), keeping the `limit` most frequent queries of each list. Lines that
+/// aren't entries (such as one cut short by a crash) are skipped.
+pub fn top_queries(path: &Path, since: Option<DateTime<Utc>>, limit: usize) -> io::Result<TopQueries> {
+    let mut searches = 0;
+    let mut all: HashMap<String, usize> = HashMap::new();
+    let mut zero: HashMap<String, usize> = HashMap::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let Ok(entry) = serde_json::from_str::<QueryLogEntry>(&line?) else {
+            continue;
+        };
+        if since.is_some_and(|since| entry.timestamp < since) {
+            continue;
+        }
+        searches += 1;
+        if entry.total == 0 {
+            *zero.entry(entry.query.clone()).or_insert(0) += 1;
+        }
+        *all.entry(entry.query).or_insert(0) += 1;
+    }
+    Ok(TopQueries {
+        searches,
+        top: most_frequent(all, limit),
+        zero_results: most_frequent(zero, limit),
+    })
+}
+
+/// The `limit` largest counts, ties alphabetically.
+fn most_frequent(counts: HashMap<String, usize>, limit: usize) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(limit);
+    counts
+}
+
+/// Parse a `--since` window such as `30m`, `24h` or `7d`.
+pub fn parse_window(value: &str) -> Result<chrono::Duration, String> {
+    let value = value.trim();
+    let split = value.len() - value.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("expected a number and a unit such as 24h, got {:?}", value))?;
+    match unit {
+        "m" => Ok(chrono::Duration::minutes(amount)),
+        "h" => Ok(chrono::Duration::hours(amount)),
+        "d" => Ok(chrono::Duration::days(amount)),
+        other => Err(format!("unknown unit {:?} (use m, h or d)", other)),
+    }
+}
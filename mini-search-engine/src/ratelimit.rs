@@ -0,0 +1,224 @@
+//! Per-client rate limiting for the search routes (`serve --rate-limit`): a token
+//! bucket per IP address, refilled continuously at the configured rate.
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::search::ApiError;
+
+/// How often buckets of idle clients are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A `--rate-limit` value: `requests` per `period`, with bursts of up to `requests`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+/// Parse a `--rate-limit` value such as `10/s` or `300/m`.
+pub fn parse_rate(value: &str) -> Result<Rate, String> {
+    let (requests, unit) = value
+        .split_once('/')
+        .ok_or_else(|| format!("expected REQUESTS/UNIT such as 10/s, got {:?}", value))?;
+    let requests: u32 = requests
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid request count {:?}", requests))?;
+    if requests == 0 {
+        return Err("the request count must be at least 1".to_string());
+    }
+    let period = match unit.trim() {
+        "s" => Duration::from_secs(1),
+        "m" => Duration::from_secs(60),
+        "h" => Duration::from_secs(3600),
+        other => return Err(format!("unknown unit {:?} (use s, m or h)", other)),
+    };
+    Ok(Rate { requests, period })
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    last_sweep: Instant,
+}
+
+/// Token buckets keyed by client IP. Times are passed in, so the limiter can be
+/// driven without a real clock.
+pub struct RateLimiter {
+    capacity: f64,
+    /// Tokens added per second.
+    refill: f64,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            capacity: f64::from(rate.requests),
+            refill: f64::from(rate.requests) / rate.period.as_secs_f64(),
+            buckets: Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                last_sweep: now,
+            }),
+        }
+    }
+
+    /// Take a token for `ip` at time `now`, or return how long until one is available.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if now.saturating_duration_since(buckets.last_sweep) >= SWEEP_INTERVAL {
+            self.sweep(&mut buckets.by_ip, now);
+            buckets.last_sweep = now;
+        }
+        let bucket = buckets.by_ip.entry(ip).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill).min(self.capacity)
+    }
+
+    /// Drop buckets that have refilled completely; a new bucket starts full anyway.
+    fn sweep(&self, by_ip: &mut HashMap<IpAddr, Bucket>, now: Instant) {
+        by_ip.retain(|_, bucket| self.refilled(bucket, now) < self.capacity);
+    }
+}
+
+/// Middleware state: the limiter and whether to trust `X-Forwarded-For`.
+#[derive(Clone)]
+pub struct RateLimit {
+    pub limiter: Arc<RateLimiter>,
+    /// Take the client IP from `X-Forwarded-For` (`--trust-proxy`).
+    pub trust_proxy: bool,
+}
+
+/// Client address: the last `X-Forwarded-For` entry when the proxy is trusted
+/// and the entry parses, otherwise the peer address. The proxy appends the
+/// address it saw to whatever the client sent, so only the last entry can't be forged.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, trust_proxy: bool) -> IpAddr {
+    let forwarded = || {
+        let value = headers.get_all("x-forwarded-for").iter().next_back()?.to_str().ok()?;
+        value.rsplit(',').next()?.trim().parse::<IpAddr>().ok()
+    };
+    trust_proxy.then(forwarded).flatten().unwrap_or_else(|| peer.ip())
+}
+
+/// 429 with a JSON error and `Retry-After` for clients over the limit.
+pub async fn rate_limit(
+    State(limit): State<RateLimit>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = client_ip(request.headers(), peer, limit.trust_proxy);
+    match limit.limiter.check(ip, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            let error = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded; retry later");
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            ([(header::RETRY_AFTER, retry_after.to_string())], error).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn per_second(requests: u32) -> Rate {
+        Rate {
+            requests,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("10/s"), Ok(per_second(10)));
+        assert_eq!(parse_rate(" 300 / m ").map(|rate| rate.period), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_rate("5/h").map(|rate| rate.period), Ok(Duration::from_secs(3600)));
+        for invalid in ["10", "0/s", "ten/s", "10/d"] {
+            assert!(parse_rate(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn bursts_up_to_the_rate_then_refills() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(per_second(2), start);
+        assert_eq!(limiter.check(CLIENT, start), Ok(()));
+        assert_eq!(limiter.check(CLIENT, start), Ok(()));
+        assert_eq!(limiter.check(CLIENT, start), Err(Duration::from_millis(500)));
+        // Other clients have buckets of their own.
+        assert_eq!(limiter.check(OTHER, start), Ok(()));
+        // Half a second later one token is back, and only one.
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check(CLIENT, later), Ok(()));
+        assert!(limiter.check(CLIENT, later).is_err());
+        // Idle time never saves up more than the burst.
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(limiter.check(CLIENT, much_later), Ok(()));
+        assert_eq!(limiter.check(CLIENT, much_later), Ok(()));
+        assert!(limiter.check(CLIENT, much_later).is_err());
+    }
+
+    #[test]
+    fn sweeps_buckets_of_idle_clients() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(per_second(1), start);
+        limiter.check(CLIENT, start).unwrap();
+        limiter.check(OTHER, start + SWEEP_INTERVAL - Duration::from_millis(500)).unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().by_ip.len(), 2);
+        // At the sweep CLIENT's bucket is full again and dropped; OTHER's is still refilling.
+        limiter.check(OTHER, start + SWEEP_INTERVAL).unwrap_err();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_ip.keys().collect::<Vec<_>>(), [&OTHER]);
+    }
+
+    #[test]
+    fn forwarded_for_only_from_a_trusted_proxy() {
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 4000));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "192.0.2.1".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, true), CLIENT);
+        assert_eq!(client_ip(&headers, peer, false), peer.ip());
+        // Entries before the proxy's own are whatever the client sent: a spoofed
+        // leading address is ignored, however it changes.
+        for spoofed in ["192.0.2.2, 192.0.2.1", "10.9.8.7,192.0.2.1", "garbage , 192.0.2.1"] {
+            headers.insert("x-forwarded-for", spoofed.parse().unwrap());
+            assert_eq!(client_ip(&headers, peer, true), CLIENT, "{}", spoofed);
+        }
+        // The proxy may add its own header after the client's.
+        headers.insert("x-forwarded-for", "192.0.2.2".parse().unwrap());
+        headers.append("x-forwarded-for", "192.0.2.1".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, true), CLIENT);
+        headers.insert("x-forwarded-for", "not an address".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, true), peer.ip());
+    }
+}
@@ -1,43 +1,1882 @@
 //! Phase 4: Search API handler (axum). Phase 6: TF-IDF ranked results.
 
-use axum::extract::{Query, State};
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{ConnectInfo, Path, Query, RawQuery, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use std::sync::Arc;
+use lru::LruCache;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::index::IndexWithTf;
+use crate::crawler::{self, CrawlOptions};
+use crate::index::{
+    complete_query, Collapse, DocMeta, DocumentText, Explanation, Facet, FacetBucket, FieldBoosts, IndexReader,
+    IndexWithTf, MatchMode, MinScore, ParsedQuery, RankedHit, Ranker, RecencyBoost, Relaxation, SearchOptions, Sort,
+    SortBy, SortOrder, TfNorm, UrlFilter, DEFAULT_PROXIMITY_BOOST, MAX_PREFIX_EXPANSIONS,
+};
+use crate::querylog::{QueryLog, QueryLogEntry};
+use crate::snippet;
+use crate::sqlite::SqliteIndex;
+use crate::synonyms::Synonyms;
+use crate::tokenize::{Analyzer, AnalyzerConfig};
 
-/// Shared app state: index with TF for ranking.
-pub type AppState = Arc<IndexWithTf>;
+/// Maximum snippet length in characters.
+const SNIPPET_CHARS: usize = 200;
+
+/// Default score multiplier when only `recent_days` is given.
+const DEFAULT_RECENCY_FACTOR: f64 = 1.5;
+
+/// Results per `/search` page when `limit` is not given.
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+
+/// Largest `/search` page; bigger `limit`s are clamped to it.
+const MAX_SEARCH_LIMIT: usize = 100;
+
+/// Largest `/search` `offset`. Ranking keeps `offset + limit` candidates, so deeper
+/// pages are refused rather than sized by the client.
+const MAX_SEARCH_OFFSET: usize = 10_000;
+
+/// Searches with fewer hits than this get a "did you mean" suggestion.
+const SUGGEST_BELOW_HITS: usize = 3;
+
+/// Hits kept per host with `collapse` when `collapse_size` is not given.
+const DEFAULT_COLLAPSE_SIZE: usize = 2;
+
+/// Where the served index lives.
+#[derive(Clone)]
+pub enum IndexBackend {
+    /// The whole index in memory.
+    Memory(Arc<IndexWithTf>),
+    /// Postings in SQLite; each query loads only what it needs.
+    Sqlite(Arc<SqliteIndex>),
+}
+
+impl IndexBackend {
+    pub fn doc_count(&self) -> usize {
+        match self {
+            IndexBackend::Memory(index) => index.doc_count,
+            IndexBackend::Sqlite(store) => store.doc_count(),
+        }
+    }
+
+    pub fn analyzer(&self) -> &AnalyzerConfig {
+        match self {
+            IndexBackend::Memory(index) => &index.analyzer,
+            IndexBackend::Sqlite(store) => store.analyzer(),
+        }
+    }
+
+    /// Distinct body terms.
+    pub fn vocabulary(&self) -> usize {
+        match self {
+            IndexBackend::Memory(index) => index.term_tf.len(),
+            IndexBackend::Sqlite(store) => store.vocabulary(),
+        }
+    }
+}
+
+/// Figures about a served index taken once when it is loaded, so `/stats`
+/// doesn't walk the postings per request.
+#[derive(Debug, Clone, Copy, serde::Serialize, utoipa::ToSchema)]
+pub struct LoadInfo {
+    pub doc_count: usize,
+    pub vocabulary: usize,
+    /// Time spent reading the index, in milliseconds.
+    pub load_time_ms: f64,
+}
+
+impl LoadInfo {
+    pub fn new(index: &IndexBackend, load_time: Duration) -> Self {
+        Self {
+            doc_count: index.doc_count(),
+            vocabulary: index.vocabulary(),
+            load_time_ms: load_time.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// Server-wide counters for `/stats`.
+pub struct ServerMetrics {
+    started: Instant,
+    /// `/search` requests answered successfully.
+    queries: AtomicU64,
+}
+
+impl ServerMetrics {
+    /// Counters starting now.
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            queries: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Name of the index a bare `--index PATH` is served as. Requests without `index=`
+/// search it if present, otherwise the first index given.
+pub const DEFAULT_INDEX_NAME: &str = "default";
+
+/// One loaded version of a served index, with the synonym dictionary analyzed by
+/// its analyzer. Requests keep the snapshot they started with across a reload.
+#[derive(Clone)]
+pub struct LoadedIndex {
+    pub index: IndexBackend,
+    /// Query-time synonyms (`--synonyms`); `None` disables expansion.
+    pub synonyms: Option<Arc<Synonyms>>,
+    pub load_info: LoadInfo,
+    /// Identifies this snapshot: starts from the load time in microseconds and
+    /// goes up by one on every reload and write. Part of query cache keys and
+    /// ETags, so results computed on an old snapshot (or by an earlier process)
+    /// are never served for the new one.
+    generation: u64,
+}
+
+impl LoadedIndex {
+    pub fn new(index: IndexBackend, synonyms: Option<Arc<Synonyms>>, load_info: LoadInfo) -> Self {
+        Self {
+            index,
+            synonyms,
+            load_info,
+            generation: 0,
+        }
+    }
+}
+
+/// Reads a served index from disk, at startup and on every reload.
+pub type Loader = Arc<dyn Fn() -> Result<LoadedIndex, Box<dyn std::error::Error + Send + Sync>> + Send + Sync>;
+
+/// Writes a served in-memory index back to its file (`persist=true`).
+pub type Saver = Arc<dyn Fn(&IndexWithTf) -> Result<(), Box<dyn std::error::Error + Send + Sync>> + Send + Sync>;
+
+/// An index served under a name. Readers take the current snapshot; `reload`
+/// and `update` build the next one without holding the lock, then swap it in,
+/// so searches never wait on a write and never see one half-applied.
+pub struct NamedIndex {
+    current: RwLock<Arc<LoadedIndex>>,
+    /// Held by `update` from copying the snapshot to swapping in the result, and
+    /// by `reload` while swapping, so concurrent writes can't drop each other.
+    writing: Mutex<()>,
+    loader: Loader,
+    /// `None` when writes can't be saved (SQLite).
+    saver: Option<Saver>,
+    saving: tokio::sync::Mutex<()>,
+    versions: AtomicU64,
+    /// Ranked results of recent queries (`--query-cache-size`); `None` disables caching.
+    pub cache: Option<Arc<QueryCache>>,
+}
+
+impl NamedIndex {
+    /// Load the index for the first time.
+    pub fn load(
+        loader: Loader,
+        saver: Option<Saver>,
+        cache: Option<Arc<QueryCache>>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut loaded = loader()?;
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        loaded.generation = since_epoch.as_micros() as u64;
+        Ok(Self {
+            versions: AtomicU64::new(loaded.generation),
+            current: RwLock::new(Arc::new(loaded)),
+            writing: Mutex::new(()),
+            loader,
+            saver,
+            saving: tokio::sync::Mutex::new(()),
+            cache,
+        })
+    }
+
+    /// The index as currently loaded.
+    pub fn snapshot(&self) -> Arc<LoadedIndex> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Read the index again on a blocking task and swap it in. On error the
+    /// previous snapshot keeps serving. Clears the query cache.
+    pub async fn reload(&self) -> Result<LoadInfo, Box<dyn std::error::Error + Send + Sync>> {
+        let loader = self.loader.clone();
+        let loaded = tokio::task::spawn_blocking(move || loader())
+            .await
+            .map_err(|e| format!("reload task failed: {}", e))??;
+        let load_info = loaded.load_info;
+        let _writing = self.writing.lock().unwrap_or_else(|e| e.into_inner());
+        self.swap(loaded);
+        Ok(load_info)
+    }
+
+    /// Modify a copy of the in-memory index and swap it in, returning `f`'s result
+    /// and the new document count. Searches keep using the current snapshot until
+    /// the swap; writes run one at a time. Clears the query cache. `None` for a
+    /// SQLite index, which is read-only while served.
+    pub fn update<T>(&self, f: impl FnOnce(&mut IndexWithTf) -> T) -> Option<(T, usize)> {
+        let _writing = self.writing.lock().unwrap_or_else(|e| e.into_inner());
+        let mut next = LoadedIndex::clone(&self.snapshot());
+        let IndexBackend::Memory(index) = &mut next.index else {
+            return None;
+        };
+        let index = Arc::make_mut(index);
+        let result = f(index);
+        let doc_count = index.doc_count;
+        self.swap(next);
+        Some((result, doc_count))
+    }
+
+    /// Make `next` the current snapshot and clear the query cache. Callers hold
+    /// `writing`.
+    fn swap(&self, mut next: LoadedIndex) {
+        next.generation = self.versions.fetch_add(1, Ordering::Relaxed) + 1;
+        let next = Arc::new(next);
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = next;
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    /// Write the current snapshot back to disk with the saver, on a blocking task.
+    /// Saves run one at a time, since they share a temporary file.
+    pub async fn persist(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let saver = self.saver.clone().ok_or("this index can't be saved while served")?;
+        let _saving = self.saving.lock().await;
+        let snapshot = self.snapshot();
+        tokio::task::spawn_blocking(move || match &snapshot.index {
+            IndexBackend::Memory(index) => saver(index),
+            IndexBackend::Sqlite(_) => Err("the sqlite backend is read-only while served".into()),
+        })
+        .await
+        .map_err(|e| format!("save task failed: {}", e))?
+    }
+}
+
+/// LRU cache of `/search` result pages for one index, keyed by the normalized
+/// query and every option that affects ranking. Entries are stored before score
+/// normalization and without explanations. Call `clear` whenever the index changes.
+pub struct QueryCache {
+    entries: Mutex<LruCache<String, Arc<SearchResponse>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Query cache counters, as reported by `GET /stats`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// hits / (hits + misses); 0 before the first lookup.
+    pub hit_rate: f64,
+}
+
+impl QueryCache {
+    /// A cache holding up to `capacity` result lists; `None` for 0 (disabled).
+    pub fn new(capacity: usize) -> Option<Self> {
+        let capacity = NonZeroUsize::new(capacity)?;
+        Some(Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<SearchResponse>> {
+        let found = self.entries.lock().unwrap().get(key).cloned();
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    fn put(&self, key: String, page: Arc<SearchResponse>) {
+        self.entries.lock().unwrap().put(key, page);
+    }
+
+    /// Drop every entry, e.g. after the index is modified or reloaded.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheStats {
+            capacity: entries.cap().get(),
+            entries: entries.len(),
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 },
+        }
+    }
+}
+
+/// Shared app state: the served indexes by name and the default field boosts.
+#[derive(Clone)]
+pub struct AppState {
+    /// name -> index
+    pub indexes: Arc<HashMap<String, NamedIndex>>,
+    /// Index searched when a request names none.
+    pub default_index: String,
+    /// Default field boosts (`--boost`); requests may override each one.
+    pub boosts: FieldBoosts,
+    /// Default score normalization (`--normalize`); requests may override it.
+    pub normalize: ScoreNormalization,
+    /// Set once the indexes are loaded; `/readyz` reports 503 until then.
+    pub ready: Arc<AtomicBool>,
+    /// Accept `POST /documents` (`--enable-writes`).
+    pub writes: bool,
+    pub metrics: Arc<ServerMetrics>,
+    /// `max-age` of `/search` responses in seconds (`--search-max-age`).
+    pub search_max_age: u64,
+    /// Where searches are logged (`--query-log`).
+    pub query_log: Option<QueryLog>,
+    /// Crawls started with `POST /admin/crawl`.
+    pub crawls: Arc<CrawlJobs>,
+}
+
+impl AppState {
+    /// Served index names, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.indexes.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The index called `name` (the default one for `None`), or a 404 listing the names.
+    fn get(&self, name: Option<&str>) -> Result<&NamedIndex, ApiError> {
+        let name = name.unwrap_or(&self.default_index);
+        self.indexes.get(name).ok_or_else(|| {
+            let error = ApiError::new(StatusCode::NOT_FOUND, format!("unknown index {:?}", name));
+            let mut error = error.with_code("unknown_index");
+            error.body.available = Some(self.names());
+            error
+        })
+    }
+}
+
+/// Error response of every endpoint: the status, with a JSON body
+/// `{"error": {"code": ..., "message": ...}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ApiErrorBody,
+}
+
+/// The `error` object of an error response.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct ApiErrorBody {
+    /// Machine-readable reason, such as `missing_query` or `not_found`.
+    #[schema(value_type = String)]
+    pub code: &'static str,
+    pub message: String,
+    /// Valid values, for an unknown `index=` or `fields=` name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<Vec<String>>,
+    /// Character offset of a query syntax error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+impl ApiError {
+    /// Error with a generic code for the status; `with_code` gives a specific one.
+    pub(crate) fn new(status: StatusCode, message: impl Into<String>) -> ApiError {
+        let code = match status {
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::FORBIDDEN => "forbidden",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+            StatusCode::NOT_IMPLEMENTED => "not_implemented",
+            _ => "internal_error",
+        };
+        ApiError {
+            status,
+            body: ApiErrorBody {
+                code,
+                message: message.into(),
+                available: None,
+                position: None,
+            },
+        }
+    }
+
+    pub(crate) fn with_code(mut self, code: &'static str) -> ApiError {
+        self.body.code = code;
+        self
+    }
+}
+
+/// JSON body of an error response.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ErrorResponse {
+    pub error: ApiErrorBody,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorResponse { error: self.body })).into_response()
+    }
+}
+
+/// Query parameters, or a 400 `invalid_parameter` error for a malformed query
+/// string or a bad parameter value (instead of axum's plain-text rejection).
+fn query_params<T>(params: Result<Query<T>, QueryRejection>) -> Result<T, ApiError> {
+    params
+        .map(|Query(params)| params)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()).with_code("invalid_parameter"))
+}
+
+/// Longest accepted `q`, in bytes.
+const MAX_QUERY_BYTES: usize = 1024;
+
+/// The `q` parameter, or a 400 for a missing, blank or overlong query.
+fn check_query(q: Option<String>) -> Result<String, ApiError> {
+    let q = q.ok_or_else(|| {
+        ApiError::new(StatusCode::BAD_REQUEST, "missing query parameter q").with_code("missing_query")
+    })?;
+    if q.trim().is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "query parameter q is empty").with_code("empty_query"));
+    }
+    if q.len() > MAX_QUERY_BYTES {
+        let error = format!("query is {} bytes; the limit is {}", q.len(), MAX_QUERY_BYTES);
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, error).with_code("query_too_long"));
+    }
+    Ok(q)
+}
+
+/// `mode` query parameter.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryMode {
+    #[default]
+    Any,
+    All,
+}
+
+/// `fallback` query parameter of `/search`: what to do when nothing matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Fallback {
+    /// Search again with the query relaxed step by step (see `search_relaxed`).
+    Relax,
+}
+
+/// `format` query parameter of `/search`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// The `SearchResponse` envelope.
+    #[default]
+    Json,
+    /// One hit object per line.
+    Ndjson,
+    /// `url,score,title` rows after a header row.
+    Csv,
+}
+
+/// How scores are rescaled in responses. Ranking always uses raw scores; this only
+/// changes the numbers returned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreNormalization {
+    /// Raw ranker scores.
+    #[default]
+    #[serde(alias = "false")]
+    None,
+    /// Divide by the top score: the best hit is 1.0, others proportional.
+    #[serde(alias = "true")]
+    Max,
+    /// `tanh(score / 2)`: 0–1 without depending on the other hits.
+    Sigmoid,
+}
+
+impl ScoreNormalization {
+    /// The ranking threshold for a `min_score` given in this scale.
+    fn min_score(self, min: f64) -> MinScore {
+        match self {
+            ScoreNormalization::None => MinScore::Raw(min),
+            ScoreNormalization::Max => MinScore::Relative(min),
+            // tanh never reaches 1, so nothing passes a threshold of 1 or more.
+            ScoreNormalization::Sigmoid if min < 1.0 => MinScore::Raw(2.0 * min.atanh()),
+            ScoreNormalization::Sigmoid => MinScore::Raw(f64::INFINITY),
+        }
+    }
+
+    /// Rescale `hits` (sorted best first), keeping the raw scores in `raw_score`.
+    fn apply(self, hits: &mut [SearchHit]) {
+        // The first hit unless the hits are sorted by something else.
+        let top = hits.iter().map(|hit| hit.score).fold(0.0, f64::max);
+        for hit in hits {
+            let raw = hit.score;
+            hit.score = match self {
+                ScoreNormalization::None => continue,
+                // A lone hit, or all-equal scores, normalize to 1.0; so does a
+                // non-positive top score, which can't be divided by.
+                ScoreNormalization::Max if top > 0.0 => raw / top,
+                ScoreNormalization::Max => 1.0,
+                ScoreNormalization::Sigmoid => (raw / 2.0).tanh(),
+            };
+            hit.raw_score = Some(raw);
+        }
+    }
+}
 
 /// Query params for GET /search?q=...
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchQuery {
-    pub q: String,
+    /// Required; checked by `check_query`.
+    pub q: Option<String>,
+    /// Name of the index to search (default: `default`, or the first index).
+    pub index: Option<String>,
+    /// `tfidf` (default), `bm25` or `cosine`.
+    #[serde(default)]
+    pub ranker: Ranker,
+    /// TF scaling for `tfidf`: `raw` (default), `length` or `log`.
+    #[serde(default)]
+    pub tf_norm: TfNorm,
+    /// Boost documents modified within this many days.
+    pub recent_days: Option<u32>,
+    /// Score multiplier for recent documents (default 1.5).
+    pub recent_boost: Option<f64>,
+    /// Field boosts overriding the server defaults (`boost.title=5`). `title_boost`
+    /// is accepted for `boost.title`.
+    #[serde(rename = "boost.body")]
+    pub boost_body: Option<f64>,
+    #[serde(rename = "boost.title", alias = "title_boost")]
+    pub boost_title: Option<f64>,
+    #[serde(rename = "boost.headings")]
+    pub boost_headings: Option<f64>,
+    #[serde(rename = "boost.url")]
+    pub boost_url: Option<f64>,
+    /// Results per page (default 10, at most 100).
+    pub limit: Option<usize>,
+    /// Number of best results to skip (default 0, at most 10000).
+    #[serde(default)]
+    pub offset: usize,
+    /// `any` (default): documents matching any term; `all`: every term.
+    #[serde(default)]
+    pub mode: QueryMode,
+    /// Minimum number of terms a document must match (overrides `mode`).
+    pub mm: Option<usize>,
+    /// Match the last word as a prefix (for as-you-type search).
+    #[serde(default)]
+    pub prefix: bool,
+    /// Expand terms with the server's synonym dictionary (default true).
+    pub synonyms: Option<bool>,
+    /// Proximity boost weight for multi-term queries (default 0.5; 0 disables).
+    pub proximity: Option<f64>,
+    /// Rescale scores: `max` (or `true`), `sigmoid` or `none` (default: the server's `--normalize`).
+    pub normalize: Option<ScoreNormalization>,
+    /// Leave out hits scoring below this, in the scale chosen by `normalize`.
+    pub min_score: Option<f64>,
+    /// Attach a per-term score breakdown to each hit.
+    #[serde(default)]
+    pub explain: bool,
+    /// Inserted before each matched word in snippets (default `<em>`).
+    pub highlight_pre: Option<String>,
+    /// Inserted after each matched word in snippets (default `</em>`).
+    pub highlight_post: Option<String>,
+    /// `compat=1` returns only the array of hits, as before the response envelope.
+    #[serde(default)]
+    pub compat: u8,
+    /// `json` (default), `ndjson` or `csv`.
+    #[serde(default)]
+    pub format: ResponseFormat,
+    /// Only return pages on this host or its subdomains.
+    pub site: Option<String>,
+    /// Comma-separated facets to count all matches by: `host`, `path1`.
+    pub facets: Option<String>,
+    /// Keep only the best few hits per `host` (or `path1` segment).
+    pub collapse: Option<Facet>,
+    /// Hits kept per group with `collapse` (default 2).
+    pub collapse_size: Option<usize>,
+    /// Comma-separated hit fields to return, such as `url,score,title` (default:
+    /// all). `url` and `score` are always included; fields left out aren't computed.
+    pub fields: Option<String>,
+    /// `relax`: when nothing matches, loosen the query until something does.
+    pub fallback: Option<Fallback>,
+    /// `score` (default), `url` or `time` (last modified; pages without a date last).
+    #[serde(default)]
+    pub sort: SortBy,
+    /// `asc` or `desc` (default: `desc` for `score` and `time`, `asc` for `url`).
+    pub order: Option<SortOrder>,
+}
+
+/// Names `fields` accepts.
+const HIT_FIELDS: [&str; 8] =
+    ["url", "score", "raw_score", "title", "snippet", "last_modified", "collapsed_count", "explanation"];
+
+/// The optional `SearchHit` fields a request asked for (`fields`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HitFields {
+    raw_score: bool,
+    title: bool,
+    snippet: bool,
+    last_modified: bool,
+    collapsed_count: bool,
+    explanation: bool,
+}
+
+impl Default for HitFields {
+    /// Every field.
+    fn default() -> Self {
+        Self {
+            raw_score: true,
+            title: true,
+            snippet: true,
+            last_modified: true,
+            collapsed_count: true,
+            explanation: true,
+        }
+    }
+}
+
+impl HitFields {
+    /// The `fields` parameter; every field when it's not given.
+    fn parse(value: Option<&str>) -> Result<Self, ApiError> {
+        let Some(value) = value else {
+            return Ok(Self::default());
+        };
+        let mut fields = Self {
+            raw_score: false,
+            title: false,
+            snippet: false,
+            last_modified: false,
+            collapsed_count: false,
+            explanation: false,
+        };
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let field = match name {
+                "url" | "score" => continue,
+                "raw_score" => &mut fields.raw_score,
+                "title" => &mut fields.title,
+                "snippet" => &mut fields.snippet,
+                "last_modified" => &mut fields.last_modified,
+                "collapsed_count" => &mut fields.collapsed_count,
+                "explanation" => &mut fields.explanation,
+                _ => {
+                    let error = ApiError::new(StatusCode::BAD_REQUEST, format!("unknown field {:?}", name));
+                    let mut error = error.with_code("invalid_parameter");
+                    error.body.available = Some(HIT_FIELDS.map(String::from).to_vec());
+                    return Err(error);
+                }
+            };
+            *field = true;
+        }
+        Ok(fields)
+    }
+
+    /// Clear `raw_score` unless asked for; normalization sets it after the hits are built.
+    fn clear_raw_scores(self, hits: &mut [SearchHit]) {
+        if !self.raw_score {
+            hits.iter_mut().for_each(|hit| hit.raw_score = None);
+        }
+    }
+}
+
+/// The `facets` parameter as facets, without repeats.
+fn parse_facets(value: Option<&str>) -> Result<Vec<Facet>, ApiError> {
+    let mut facets = Vec::new();
+    for name in value.unwrap_or("").split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let facet = Facet::ALL.into_iter().find(|facet| facet.name() == name).ok_or_else(|| {
+            let error = format!("unknown facet {:?} (expected host or path1)", name);
+            ApiError::new(StatusCode::BAD_REQUEST, error).with_code("invalid_parameter")
+        })?;
+        if !facets.contains(&facet) {
+            facets.push(facet);
+        }
+    }
+    Ok(facets)
+}
+
+/// Every `url_prefix` value in a query string. The parameter may repeat, which
+/// `Query` can't deserialize, so it isn't part of `SearchQuery`.
+fn url_prefixes(query: Option<&str>) -> Vec<String> {
+    url::form_urlencoded::parse(query.unwrap_or("").as_bytes())
+        .filter(|(key, value)| key == "url_prefix" && !value.is_empty())
+        .map(|(_, value)| value.into_owned())
+        .collect()
 }
 
 /// Search result: URL and TF-IDF score.
-#[derive(serde::Serialize)]
+#[derive(Clone, serde::Serialize, utoipa::ToSchema)]
 pub struct SearchHit {
     pub url: String,
     pub score: f64,
+    /// Score before normalization, when the response is normalized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_score: Option<f64>,
+    /// Page title, when the index stores metadata.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Stored text around the first query term, with matches marked; otherwise
+    /// the description or leading body text. Only with stored metadata.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// Last modification time (RFC 3339), when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// With `collapse`, on the best hit of a host that had more: how many more
+    /// hits it had (search again with `site=` to see them).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapsed_count: Option<usize>,
+    /// Score breakdown, with `explain=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub explanation: Option<Explanation>,
+}
+
+/// A page of `/search` results.
+#[derive(Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct SearchResponse {
+    /// The query as given.
+    pub query: String,
+    /// Number of matching documents across all pages.
+    pub total: usize,
+    /// Time spent finding and ranking the hits (or reading them from the query
+    /// cache), in milliseconds; snippets and serialization are not included.
+    pub took_ms: f64,
+    pub limit: usize,
+    pub offset: usize,
+    /// What the hits are sorted by, and in which order.
+    pub sort: SortBy,
+    pub order: SortOrder,
+    /// Sorted by `sort` (by score, descending, unless asked otherwise); empty past the last page.
+    pub hits: Vec<SearchHit>,
+    /// With `facets`, each facet's most common values (at most 10, largest count
+    /// first) among all matching documents.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facets: Option<BTreeMap<Facet, Vec<FacetBucket>>>,
+    /// A spelling-corrected query with more matches, when this one has almost none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+    /// With `fallback=relax`, whether the hits are for a relaxed query because
+    /// the query as given matched nothing.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub relaxed: bool,
+    /// What was relaxed, in order, when `relaxed`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub relaxations: Vec<Relaxation>,
 }
 
-/// GET /search?q=word -> JSON array of { url, score } sorted by score descending.
+/// GET /search?q=word -> `SearchResponse` with one page of hits.
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(
+        SearchQuery,
+        ("url_prefix" = Option<Vec<String>>, Query,
+            description = "Only return URLs with this prefix (a path prefix if it starts with `/`); may repeat"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of an earlier response to revalidate"),
+    ),
+    responses(
+        (status = 200, description = "One page of hits; NDJSON and CSV list the hits only", body = SearchResponse),
+        (status = 304, description = "Unchanged since the response with the given ETag"),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Unknown index", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    )
+)]
 pub async fn search_handler(
-    State(index): State<AppState>,
-    Query(params): Query<SearchQuery>,
-) -> Json<Vec<SearchHit>> {
-    let ranked = index.search_ranked(&params.q);
-    let hits = ranked
+    State(state): State<AppState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    RawQuery(raw_query): RawQuery,
+    params: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let started = Instant::now();
+    let mut params = query_params(params)?;
+    let q = check_query(params.q.take())?;
+    if params.limit == Some(0) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "limit must be at least 1").with_code("invalid_parameter"));
+    }
+    if params.offset > MAX_SEARCH_OFFSET {
+        let error = format!("offset must be at most {}", MAX_SEARCH_OFFSET);
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, error).with_code("invalid_parameter"));
+    }
+    if params.min_score.is_some_and(|min| min.is_nan() || min < 0.0) {
+        let error = "min_score must be a number of at least 0";
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, error).with_code("invalid_parameter"));
+    }
+    if params.collapse_size == Some(0) {
+        let error = "collapse_size must be at least 1";
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, error).with_code("invalid_parameter"));
+    }
+    let named = state.get(params.index.as_deref())?;
+    let served = named.snapshot();
+    if let Err(e) = ParsedQuery::try_parse_with(&q, served.index.analyzer()) {
+        let mut error = ApiError::new(StatusCode::BAD_REQUEST, e.message).with_code("invalid_query");
+        error.body.position = Some(e.position);
+        return Err(error);
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT);
+    let facets = parse_facets(params.facets.as_deref())?;
+    let fields = HitFields::parse(params.fields.as_deref())?;
+    let mut boosts = state.boosts;
+    let overrides = [
+        ("body", params.boost_body),
+        ("title", params.boost_title),
+        ("headings", params.boost_headings),
+        ("url", params.boost_url),
+    ];
+    for (field, boost) in overrides {
+        if let Some(boost) = boost {
+            boosts
+                .set(field, boost)
+                .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e).with_code("invalid_parameter"))?;
+        }
+    }
+    let options = SearchOptions {
+        ranker: params.ranker,
+        tf_norm: params.tf_norm,
+        match_mode: match (params.mm, params.mode) {
+            (Some(n), _) => MatchMode::MinShouldMatch(n),
+            (None, QueryMode::Any) => MatchMode::Any,
+            (None, QueryMode::All) => MatchMode::All,
+        },
+        recency: params.recent_days.map(|days| RecencyBoost {
+            days,
+            factor: params.recent_boost.unwrap_or(DEFAULT_RECENCY_FACTOR),
+        }),
+        boosts,
+        limit: Some(limit),
+        offset: params.offset,
+        prefix: params.prefix,
+        synonyms: served.synonyms.clone().filter(|_| params.synonyms.unwrap_or(true)),
+        proximity: params.proximity.unwrap_or(DEFAULT_PROXIMITY_BOOST),
+        filter: UrlFilter {
+            site: params.site.take().filter(|site| !site.is_empty()),
+            url_prefixes: url_prefixes(raw_query.as_deref()),
+        },
+        facets,
+        collapse: params.collapse.map(|by| Collapse {
+            by,
+            size: params.collapse_size.unwrap_or(DEFAULT_COLLAPSE_SIZE),
+        }),
+        min_score: params.min_score.map(|min| params.normalize.unwrap_or(state.normalize).min_score(min)),
+        sort: Sort {
+            by: params.sort,
+            order: params.order.unwrap_or(params.sort.default_order()),
+        },
+        ..Default::default()
+    };
+    // Results boosted by recency depend on the current time: no ETag for them.
+    let etag = options.recency.is_none().then(|| search_etag(served.generation, &q, raw_query.as_deref()));
+    if let Some(etag) = &etag {
+        let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+        if if_none_match.is_some_and(|tags| etag_matches(tags, etag)) {
+            state.metrics.queries.fetch_add(1, Ordering::Relaxed);
+            let response = StatusCode::NOT_MODIFIED.into_response();
+            return Ok(with_cache_headers(response, etag.clone(), state.search_max_age));
+        }
+    }
+    // Results boosted by recency depend on the current time, and explanations
+    // aren't cached, so neither kind of request uses the cache.
+    let cache = named.cache.as_ref().filter(|_| options.recency.is_none() && !params.explain);
+    let key = cache.map(|_| {
+        let query = q.split_whitespace().collect::<Vec<_>>().join(" ");
+        let synonyms = options.synonyms.is_some();
+        format!(
+            "{}\0{}\0{:?} {:?} {:?} {:?} {} {} {} {} {} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            served.generation, query, options.ranker, options.tf_norm, options.match_mode, options.boosts, limit,
+            options.offset, options.prefix, synonyms, options.proximity, params.highlight_pre, params.highlight_post,
+            options.filter, options.facets, options.collapse, options.min_score, fields, params.fallback, options.sort
+        )
+    });
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        let lookup = Instant::now();
+        if let Some(cached) = cache.get(key) {
+            let mut page = cached.as_ref().clone();
+            page.query = q.clone();
+            page.took_ms = lookup.elapsed().as_secs_f64() * 1000.0;
+            params.normalize.unwrap_or(state.normalize).apply(&mut page.hits);
+            fields.clear_raw_scores(&mut page.hits);
+            state.metrics.queries.fetch_add(1, Ordering::Relaxed);
+            log_search(&state, client, &q, page.total, started, true);
+            let response = search_body(page, params.format, params.compat != 0);
+            return Ok(with_optional_etag(response, etag, state.search_max_age));
+        }
+    }
+    let ranking = Instant::now();
+    let loaded;
+    let index: &IndexWithTf = match &served.index {
+        IndexBackend::Memory(index) => index,
+        IndexBackend::Sqlite(store) => {
+            loaded = IndexWithTf::for_query(store.as_ref(), &q, &options)
+                .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            &loaded
+        }
+    };
+    let mut ranked = index.search_page(&q, &options);
+    // The index loaded from sqlite holds just the query's terms: nothing to correct
+    // words to, but relaxing to fewer of them works.
+    let memory = matches!(served.index, IndexBackend::Memory(_));
+    let relaxed = (ranked.total == 0 && params.fallback == Some(Fallback::Relax))
+        .then(|| index.search_relaxed(&q, &options, memory))
+        .flatten();
+    let (searched, options, relaxations) = match relaxed {
+        Some(relaxed) => {
+            ranked = relaxed.page;
+            (relaxed.query, relaxed.options, relaxed.steps)
+        }
+        None => (q.clone(), options, Vec::new()),
+    };
+    let took_ms = ranking.elapsed().as_secs_f64() * 1000.0;
+    // Only near-empty results are worth correcting, so common queries skip this.
+    let suggestion = match &served.index {
+        IndexBackend::Memory(index) if ranked.total < SUGGEST_BELOW_HITS && relaxations.is_empty() => {
+            index.suggest_query(&q, &options, ranked.total)
+        }
+        _ => None,
+    };
+    let parsed = ParsedQuery::parse_with(&searched, &index.analyzer);
+    let highlight = Highlight {
+        analyzer: &index.analyzer,
+        terms: parsed.highlight_terms(),
+        markers: (
+            params.highlight_pre.as_deref().unwrap_or(snippet::DEFAULT_MARKERS.0),
+            params.highlight_post.as_deref().unwrap_or(snippet::DEFAULT_MARKERS.1),
+        ),
+    };
+    let hits: Vec<SearchHit> = ranked
+        .hits
         .into_iter()
-        .map(|(url, score)| SearchHit { url, score })
+        .map(|hit| {
+            let explanation = (params.explain && fields.explanation)
+                .then(|| index.explain_with(&searched, &hit.url, &options))
+                .flatten();
+            SearchHit::new(hit, explanation, Some(&highlight), fields)
+        })
         .collect();
-    Json(hits)
+    let mut page = SearchResponse {
+        query: q.clone(),
+        total: ranked.total,
+        took_ms,
+        limit,
+        offset: params.offset,
+        sort: options.sort.by,
+        order: options.sort.order,
+        hits,
+        facets: (!options.facets.is_empty()).then_some(ranked.facets),
+        suggestion,
+        relaxed: !relaxations.is_empty(),
+        relaxations,
+    };
+    if let (Some(cache), Some(key)) = (cache, key) {
+        cache.put(key, Arc::new(page.clone()));
+    }
+    params.normalize.unwrap_or(state.normalize).apply(&mut page.hits);
+    fields.clear_raw_scores(&mut page.hits);
+    state.metrics.queries.fetch_add(1, Ordering::Relaxed);
+    log_search(&state, client, &q, page.total, started, false);
+    let response = search_body(page, params.format, params.compat != 0);
+    Ok(with_optional_etag(response, etag, state.search_max_age))
+}
+
+/// Weak ETag of a `/search` response: a hash of the index snapshot, the query
+/// with its whitespace normalized, and every other parameter (pagination,
+/// format, boosts, ...). Scores can differ in the last digits, hence weak.
+fn search_etag(generation: u64, query: &str, raw_query: Option<&str>) -> String {
+    let mut hasher = DefaultHasher::new();
+    generation.hash(&mut hasher);
+    query.split_whitespace().for_each(|word| word.hash(&mut hasher));
+    for (name, value) in url::form_urlencoded::parse(raw_query.unwrap_or("").as_bytes()) {
+        if name != "q" {
+            (name, value).hash(&mut hasher);
+        }
+    }
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Whether an `If-None-Match` value (`*` or a list of tags) matches `etag`,
+/// comparing weakly as RFC 9110 requires for this header.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag).to_string()
+    };
+    let etag = opaque(etag);
+    if_none_match.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// `Cache-Control` (`--search-max-age`) and `ETag` on a `/search` response.
+fn with_cache_headers(response: Response, etag: String, max_age: u64) -> Response {
+    let cache_control = format!("public, max-age={}", max_age);
+    ([(header::CACHE_CONTROL, cache_control), (header::ETAG, etag)], response).into_response()
+}
+
+/// `with_cache_headers`, or no caching headers at all without an ETag.
+fn with_optional_etag(response: Response, etag: Option<String>, max_age: u64) -> Response {
+    match etag {
+        Some(etag) => with_cache_headers(response, etag, max_age),
+        None => response,
+    }
+}
+
+/// The `/search` response in `format`. JSON is the envelope, or just the hits
+/// with `compat=1`; NDJSON and CSV always list the hits only.
+fn search_body(page: SearchResponse, format: ResponseFormat, compat: bool) -> Response {
+    match format {
+        ResponseFormat::Json if compat => Json(page.hits).into_response(),
+        ResponseFormat::Json => Json(page).into_response(),
+        ResponseFormat::Ndjson => {
+            let mut body = String::new();
+            for hit in &page.hits {
+                // Serializing plain strings and numbers can't fail.
+                body.push_str(&serde_json::to_string(hit).unwrap_or_default());
+                body.push('\n');
+            }
+            ([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response()
+        }
+        ResponseFormat::Csv => {
+            let mut body = String::from("url,score,title\r\n");
+            for hit in &page.hits {
+                let title = hit.title.as_deref().unwrap_or("");
+                body.push_str(&format!("{},{},{}\r\n", csv_field(&hit.url), hit.score, csv_field(title)));
+            }
+            ([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], body).into_response()
+        }
+    }
+}
+
+/// A CSV field, quoted (with quotes doubled) when it contains a comma, quote or line break.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// One structured log event per answered search, plus a `--query-log` entry.
+fn log_search(state: &AppState, client: SocketAddr, query: &str, total: usize, started: Instant, cached: bool) {
+    let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+    tracing::info!(client = %client.ip(), query, total, elapsed_ms, cached, "search");
+    if let Some(log) = &state.query_log {
+        log.record(QueryLogEntry::new(query, total, elapsed_ms, client.ip()));
+    }
+}
+
+/// What snippets are centered on, and how matched words are marked.
+struct Highlight<'a> {
+    analyzer: &'a dyn Analyzer,
+    terms: HashSet<&'a str>,
+    markers: (&'a str, &'a str),
+}
+
+impl SearchHit {
+    /// Response entry for a ranked hit, with title and snippet from its metadata.
+    /// With `highlight`, the snippet is centered on the first query term found in
+    /// the stored text (or description). Only the metadata `fields` asks for is read.
+    fn new(
+        hit: RankedHit<'_>,
+        explanation: Option<Explanation>,
+        highlight: Option<&Highlight>,
+        fields: HitFields,
+    ) -> Self {
+        let meta = hit.meta;
+        let title = meta.filter(|_| fields.title).map(|m| m.title.clone()).filter(|t| !t.is_empty());
+        let snippet = meta
+            .filter(|_| fields.snippet)
+            .map(|m| {
+                let centered = highlight.and_then(|h| {
+                    [&m.text, &m.description]
+                        .into_iter()
+                        .find_map(|text| snippet::snippet(text, h.analyzer, &h.terms, SNIPPET_CHARS, h.markers))
+                });
+                centered.unwrap_or_else(|| {
+                    let leading = if m.description.is_empty() { &m.text } else { &m.description };
+                    leading.chars().take(SNIPPET_CHARS).collect()
+                })
+            })
+            .filter(|s| !s.is_empty());
+        let last_modified = meta
+            .filter(|_| fields.last_modified)
+            .and_then(|m| m.last_modified)
+            .map(|t| t.to_rfc3339());
+        SearchHit {
+            explanation,
+            collapsed_count: Some(hit.collapsed).filter(|&n| n > 0 && fields.collapsed_count),
+            url: hit.url,
+            score: hit.score,
+            raw_score: None,
+            title,
+            snippet,
+            last_modified,
+        }
+    }
+}
+
+/// Default number of `/similar` results.
+const DEFAULT_SIMILAR_LIMIT: usize = 10;
+
+/// Query params for GET /similar?url=...
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SimilarQuery {
+    /// An indexed URL (or an alias of one).
+    pub url: String,
+    /// Name of the index to search (default: `default`, or the first index).
+    pub index: Option<String>,
+    /// Return at most this many results (default 10, at most 100).
+    pub limit: Option<usize>,
+    /// Rescale scores, as for `/search`.
+    pub normalize: Option<ScoreNormalization>,
+}
+
+/// GET /similar?url=... -> documents most similar to `url`, in the `/search` hit shape.
+#[utoipa::path(
+    get,
+    path = "/similar",
+    params(SimilarQuery),
+    responses(
+        (status = 200, body = Vec<SearchHit>),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Unknown index or URL", body = ErrorResponse),
+        (status = 501, description = "Not supported by the sqlite backend", body = ErrorResponse),
+    )
+)]
+pub async fn similar_handler(
+    State(state): State<AppState>,
+    params: Result<Query<SimilarQuery>, QueryRejection>,
+) -> Result<Json<Vec<SearchHit>>, ApiError> {
+    let params = query_params(params)?;
+    let served = state.get(params.index.as_deref())?.snapshot();
+    let IndexBackend::Memory(index) = &served.index else {
+        let error = "/similar needs the whole document vector; it isn't supported by the sqlite backend";
+        return Err(ApiError::new(StatusCode::NOT_IMPLEMENTED, error));
+    };
+    let options = SearchOptions {
+        boosts: state.boosts,
+        ..Default::default()
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_SIMILAR_LIMIT).min(MAX_SEARCH_LIMIT);
+    let ranked = index
+        .more_like_this(&params.url, limit, &options)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("URL {:?} is not in the index", params.url)))?;
+    let mut hits: Vec<SearchHit> = ranked
+        .into_iter()
+        .map(|hit| SearchHit::new(hit, None, None, HitFields::default()))
+        .collect();
+    params.normalize.unwrap_or(state.normalize).apply(&mut hits);
+    Ok(Json(hits))
+}
+
+/// Number of `top_terms` in a `/document` response.
+const DOCUMENT_TOP_TERMS: usize = 10;
+
+/// Query params for GET /document?url=...
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DocumentQuery {
+    /// An indexed URL (or an alias of one).
+    pub url: String,
+    /// Name of the index to use (default: `default`, or the first index).
+    pub index: Option<String>,
+}
+
+/// What the index holds about one document.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct DocumentInfo {
+    /// The indexed URL; the original when the requested URL is a duplicate of it.
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// The start of the body text, as stored for snippets.
+    pub text: Option<String>,
+    /// RFC 3339 timestamp reported by the server or page, if any.
+    pub last_modified: Option<String>,
+    /// Body length in tokens (missing in old indexes).
+    pub length: Option<u32>,
+    /// Body terms with the highest TF-IDF, best first.
+    pub top_terms: Vec<DocumentTerm>,
+}
+
+/// A term of `DocumentInfo::top_terms`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct DocumentTerm {
+    pub term: String,
+    /// Occurrences in the body.
+    pub tf: u32,
+    /// tf × idf.
+    pub score: f64,
+}
+
+/// GET /document?url=... -> the stored metadata, length and top terms of a document.
+#[utoipa::path(
+    get,
+    path = "/document",
+    params(DocumentQuery),
+    responses(
+        (status = 200, body = DocumentInfo),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Unknown index or URL", body = ErrorResponse),
+        (status = 501, description = "Not supported by the sqlite backend", body = ErrorResponse),
+    )
+)]
+pub async fn document_handler(
+    State(state): State<AppState>,
+    params: Result<Query<DocumentQuery>, QueryRejection>,
+) -> Result<Json<DocumentInfo>, ApiError> {
+    let params = query_params(params)?;
+    let served = state.get(params.index.as_deref())?.snapshot();
+    let IndexBackend::Memory(index) = &served.index else {
+        let error = "/document looks documents up by URL; it isn't supported by the sqlite backend";
+        return Err(ApiError::new(StatusCode::NOT_IMPLEMENTED, error));
+    };
+    let url = index.canonical_url(&params.url);
+    let id = index
+        .doc_id(url)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("URL {:?} is not in the index", params.url)))?;
+    let meta = index.docs.get(&id);
+    let stored = |field: fn(&DocMeta) -> &String| meta.map(field).filter(|value| !value.is_empty()).cloned();
+    let top_terms = index
+        .top_document_terms(id, DOCUMENT_TOP_TERMS)
+        .into_iter()
+        .map(|(term, tf, score)| DocumentTerm {
+            term: term.to_string(),
+            tf,
+            score,
+        })
+        .collect();
+    Ok(Json(DocumentInfo {
+        url: url.to_string(),
+        title: stored(|meta| &meta.title),
+        description: stored(|meta| &meta.description),
+        text: stored(|meta| &meta.text),
+        last_modified: meta.and_then(|meta| meta.last_modified).map(|t| t.to_rfc3339()),
+        length: index.doc_lengths.get(&id).copied(),
+        top_terms,
+    }))
+}
+
+/// Default number of `/related` terms.
+const DEFAULT_RELATED_LIMIT: usize = 10;
+
+/// Query params for GET /related?term=...
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RelatedQuery {
+    pub term: String,
+    /// Name of the index to use (default: `default`, or the first index).
+    pub index: Option<String>,
+    /// Return at most this many terms (default 10, at most 100).
+    pub limit: Option<usize>,
+}
+
+/// A `/related` entry.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct RelatedTerm {
+    pub term: String,
+    pub score: f64,
+}
+
+/// GET /related?term=... -> terms that co-occur with `term`, best first.
+#[utoipa::path(
+    get,
+    path = "/related",
+    params(RelatedQuery),
+    responses(
+        (status = 200, body = Vec<RelatedTerm>),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Unknown index", body = ErrorResponse),
+        (status = 501, description = "Not supported by the sqlite backend", body = ErrorResponse),
+    )
+)]
+pub async fn related_handler(
+    State(state): State<AppState>,
+    params: Result<Query<RelatedQuery>, QueryRejection>,
+) -> Result<Json<Vec<RelatedTerm>>, ApiError> {
+    let params = query_params(params)?;
+    let served = state.get(params.index.as_deref())?.snapshot();
+    let IndexBackend::Memory(index) = &served.index else {
+        let error = "/related scans every posting list; it isn't supported by the sqlite backend";
+        return Err(ApiError::new(StatusCode::NOT_IMPLEMENTED, error));
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_RELATED_LIMIT).min(MAX_SEARCH_LIMIT);
+    let terms = index
+        .related_terms(&params.term, limit)
+        .into_iter()
+        .map(|(term, score)| RelatedTerm { term, score })
+        .collect();
+    Ok(Json(terms))
+}
+
+/// Query parameters of `/suggest`.
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct SuggestQuery {
+    /// The query typed so far; its last word is completed.
+    pub q: String,
+    /// Name of the index to use (default: `default`, or the first index).
+    pub index: Option<String>,
+    /// Return at most this many completions (default 8, at most 50).
+    pub limit: Option<usize>,
+}
+
+/// Completions per `/suggest` request when `limit` is not given.
+const DEFAULT_SUGGEST_LIMIT: usize = 8;
+
+/// GET /suggest?q=rust+tok -> completed queries (`["rust tokio", ...]`), most frequent first.
+#[utoipa::path(
+    get,
+    path = "/suggest",
+    params(SuggestQuery),
+    responses(
+        (status = 200, body = Vec<String>),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Unknown index", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    )
+)]
+pub async fn suggest_handler(
+    State(state): State<AppState>,
+    params: Result<Query<SuggestQuery>, QueryRejection>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let params = query_params(params)?;
+    let served = state.get(params.index.as_deref())?.snapshot();
+    let limit = params.limit.unwrap_or(DEFAULT_SUGGEST_LIMIT).min(MAX_PREFIX_EXPANSIONS);
+    let completions = match &served.index {
+        IndexBackend::Memory(index) => complete_query(index.as_ref(), &params.q, limit),
+        IndexBackend::Sqlite(store) => complete_query(store.as_ref(), &params.q, limit),
+    };
+    completions
+        .map(Json)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// An entry of `GET /indexes`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct IndexInfo {
+    pub name: String,
+    pub doc_count: usize,
+    /// Searched when a request has no `index=` parameter.
+    pub default: bool,
+}
+
+/// GET /indexes -> JSON array of the served indexes, by name.
+#[utoipa::path(get, path = "/indexes", responses((status = 200, body = Vec<IndexInfo>)))]
+pub async fn indexes_handler(State(state): State<AppState>) -> Json<Vec<IndexInfo>> {
+    let infos = state
+        .names()
+        .into_iter()
+        .map(|name| IndexInfo {
+            doc_count: state.indexes[&name].snapshot().index.doc_count(),
+            default: name == state.default_index,
+            name,
+        })
+        .collect();
+    Json(infos)
+}
+
+/// Body of `GET /stats`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ServerStats {
+    pub uptime_secs: u64,
+    /// `/search` requests answered since startup.
+    pub queries: u64,
+    /// Served indexes, by name.
+    pub indexes: Vec<IndexStats>,
+}
+
+/// A served index in `GET /stats`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct IndexStats {
+    pub name: String,
+    #[serde(flatten)]
+    pub load_info: LoadInfo,
+    /// `None` when the query cache is disabled.
+    pub query_cache: Option<CacheStats>,
+}
+
+/// GET /stats -> index sizes, load times and serving counters.
+#[utoipa::path(get, path = "/stats", responses((status = 200, body = ServerStats)))]
+pub async fn stats_handler(State(state): State<AppState>) -> Json<ServerStats> {
+    let indexes = state
+        .names()
+        .into_iter()
+        .map(|name| {
+            let served = &state.indexes[&name];
+            IndexStats {
+                load_info: served.snapshot().load_info,
+                query_cache: served.cache.as_ref().map(|cache| cache.stats()),
+                name,
+            }
+        })
+        .collect();
+    Json(ServerStats {
+        uptime_secs: state.metrics.started.elapsed().as_secs(),
+        queries: state.metrics.queries.load(Ordering::Relaxed),
+        indexes,
+    })
+}
+
+/// Body of `/healthz` and `/readyz`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Health {
+    #[schema(value_type = String)]
+    pub status: &'static str,
+    /// Documents across all served indexes (`/readyz` only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc_count: Option<usize>,
+}
+
+/// GET /healthz -> 200 whenever the process is serving requests.
+#[utoipa::path(get, path = "/healthz", responses((status = 200, body = Health)))]
+pub async fn healthz_handler() -> Json<Health> {
+    Json(Health {
+        status: "ok",
+        doc_count: None,
+    })
+}
+
+/// GET /readyz -> 200 with the document count once the indexes are loaded, 503 before.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses((status = 200, body = Health), (status = 503, description = "Indexes still loading", body = Health))
+)]
+pub async fn readyz_handler(State(state): State<AppState>) -> (StatusCode, Json<Health>) {
+    if !state.ready.load(Ordering::Acquire) {
+        let health = Health {
+            status: "loading",
+            doc_count: None,
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(health));
+    }
+    let doc_count = state.indexes.values().map(|served| served.snapshot().index.doc_count()).sum();
+    let health = Health {
+        status: "ready",
+        doc_count: Some(doc_count),
+    };
+    (StatusCode::OK, Json(health))
+}
+
+/// Query params for POST /admin/reload
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReloadQuery {
+    /// Name of the index to reload (default: `default`, or the first index).
+    pub index: Option<String>,
+}
+
+/// Response of `POST /admin/reload`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ReloadResponse {
+    pub index: String,
+    #[serde(flatten)]
+    pub load_info: LoadInfo,
+}
+
+/// POST /admin/reload -> re-read an index from disk and swap it in. Requests in
+/// flight finish on the old snapshot; if the file can't be loaded, the old index
+/// keeps serving and the error is returned.
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    params(ReloadQuery),
+    responses(
+        (status = 200, body = ReloadResponse),
+        (status = 401, description = "Missing or wrong API key", body = ErrorResponse),
+        (status = 404, description = "Unknown index", body = ErrorResponse),
+        (status = 500, description = "The index could not be loaded", body = ErrorResponse),
+    )
+)]
+pub async fn reload_handler(
+    State(state): State<AppState>,
+    params: Result<Query<ReloadQuery>, QueryRejection>,
+) -> Result<Json<ReloadResponse>, ApiError> {
+    let params = query_params(params)?;
+    let name = params.index.unwrap_or_else(|| state.default_index.clone());
+    let load_info = state.get(Some(name.as_str()))?.reload().await.map_err(|e| {
+        let error = format!("reload failed, still serving the previous index: {}", e);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, error)
+    })?;
+    Ok(Json(ReloadResponse { index: name, load_info }))
+}
+
+/// A document posted to `/documents`.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewDocument {
+    pub url: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub headings: String,
+    pub body: String,
+}
+
+/// Body of `POST /documents`: one document or an array of them.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum NewDocuments {
+    One(NewDocument),
+    Many(Vec<NewDocument>),
+}
+
+/// Query params for POST /documents
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DocumentsQuery {
+    /// Name of the index to add to (default: `default`, or the first index).
+    pub index: Option<String>,
+    /// Also save the modified index to its file.
+    #[serde(default)]
+    pub persist: bool,
+}
+
+/// 403 unless the server was started with `--enable-writes`.
+fn check_writes(state: &AppState) -> Result<(), ApiError> {
+    if state.writes {
+        return Ok(());
+    }
+    let error = "writes are disabled; start the server with --enable-writes";
+    Err(ApiError::new(StatusCode::FORBIDDEN, error))
+}
+
+/// 500 for a failed `persist=true` save; the change itself stays applied in memory.
+fn persist_error(e: Box<dyn std::error::Error + Send + Sync>) -> ApiError {
+    let error = format!("the index was changed in memory but not saved: {}", e);
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, error)
+}
+
+/// 501 for writes to a SQLite index.
+fn read_only_error() -> ApiError {
+    ApiError::new(StatusCode::NOT_IMPLEMENTED, "the sqlite backend is read-only while served")
+}
+
+/// Response of `POST /documents`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct DocumentsResponse {
+    pub added: usize,
+    pub doc_count: usize,
+}
+
+/// POST /documents -> tokenize and add (or replace, by URL) documents in a running
+/// in-memory index. Needs `--enable-writes`. Changes live in memory unless
+/// `persist=true` saves the index to its file.
+#[utoipa::path(
+    post,
+    path = "/documents",
+    params(DocumentsQuery),
+    request_body = NewDocuments,
+    responses(
+        (status = 200, body = DocumentsResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong API key", body = ErrorResponse),
+        (status = 404, description = "Unknown index", body = ErrorResponse),
+        (status = 500, description = "Changed in memory but not saved", body = ErrorResponse),
+        (status = 501, description = "Not supported by the sqlite backend", body = ErrorResponse),
+    )
+)]
+pub async fn documents_handler(
+    State(state): State<AppState>,
+    params: Result<Query<DocumentsQuery>, QueryRejection>,
+    body: Result<Json<NewDocuments>, JsonRejection>,
+) -> Result<Json<DocumentsResponse>, ApiError> {
+    check_writes(&state)?;
+    let params = query_params(params)?;
+    let Json(body) = body.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    let docs = match body {
+        NewDocuments::One(doc) => vec![doc],
+        NewDocuments::Many(docs) => docs,
+    };
+    if let Some(doc) = docs.iter().find(|doc| url::Url::parse(&doc.url).is_err()) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, format!("invalid URL {:?}", doc.url)));
+    }
+    let named = state.get(params.index.as_deref())?;
+    let text: Vec<DocumentText> = docs
+        .iter()
+        .map(|doc| DocumentText {
+            url: &doc.url,
+            title: &doc.title,
+            headings: &doc.headings,
+            body: &doc.body,
+        })
+        .collect();
+    let ((), doc_count) = named.update(|index| index.add_documents(&text)).ok_or_else(read_only_error)?;
+    if params.persist {
+        named.persist().await.map_err(persist_error)?;
+    }
+    Ok(Json(DocumentsResponse {
+        added: docs.len(),
+        doc_count,
+    }))
+}
+
+/// Query params for DELETE /documents?url=...
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteDocumentQuery {
+    pub url: String,
+    /// Name of the index to remove from (default: `default`, or the first index).
+    pub index: Option<String>,
+    /// Also save the modified index to its file.
+    #[serde(default)]
+    pub persist: bool,
+}
+
+/// Response of `DELETE /documents`.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct DeleteDocumentResponse {
+    pub deleted: bool,
+    pub doc_count: usize,
+}
+
+/// DELETE /documents?url=... -> remove a document from a running in-memory index
+/// (404 if the URL isn't indexed). Needs `--enable-writes`.
+#[utoipa::path(
+    delete,
+    path = "/documents",
+    params(DeleteDocumentQuery),
+    responses(
+        (status = 200, body = DeleteDocumentResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong API key", body = ErrorResponse),
+        (status = 404, description = "Unknown index or URL", body = ErrorResponse),
+        (status = 500, description = "Changed in memory but not saved", body = ErrorResponse),
+        (status = 501, description = "Not supported by the sqlite backend", body = ErrorResponse),
+    )
+)]
+pub async fn delete_document_handler(
+    State(state): State<AppState>,
+    params: Result<Query<DeleteDocumentQuery>, QueryRejection>,
+) -> Result<Json<DeleteDocumentResponse>, ApiError> {
+    check_writes(&state)?;
+    let params = query_params(params)?;
+    let named = state.get(params.index.as_deref())?;
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND, format!("URL {:?} is not in the index", params.url));
+    // Look the URL up on a snapshot first, so a miss never takes the write lock.
+    match &named.snapshot().index {
+        IndexBackend::Memory(index) if index.doc_id(&params.url).is_none() => return Err(not_found()),
+        IndexBackend::Memory(_) => {}
+        IndexBackend::Sqlite(_) => return Err(read_only_error()),
+    }
+    let (deleted, doc_count) = named
+        .update(|index| index.remove_document(&params.url))
+        .ok_or_else(read_only_error)?;
+    if !deleted {
+        return Err(not_found());
+    }
+    if params.persist {
+        named.persist().await.map_err(persist_error)?;
+    }
+    Ok(Json(DeleteDocumentResponse { deleted, doc_count }))
+}
+
+/// Body of `POST /admin/crawl`.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct CrawlRequest {
+    /// Start URL; the crawl stays on its site.
+    pub url: String,
+    /// Most pages to fetch (default 50, as for `crawl`).
+    pub max_pages: Option<usize>,
+    /// Most links to follow from the start URL (default 3).
+    pub max_depth: Option<u32>,
+}
+
+/// Progress of a crawl job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CrawlStatus {
+    Running,
+    /// The pages are merged into the index (and saved, with `persist=true`).
+    Done,
+    Failed,
+}
+
+/// A crawl job, as reported by `POST /admin/crawl` and `GET /admin/crawl/{id}`.
+#[derive(Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct CrawlJobInfo {
+    pub id: u64,
+    pub url: String,
+    /// Index the pages are merged into.
+    pub index: String,
+    pub status: CrawlStatus,
+    /// Pages fetched and kept so far.
+    pub pages: usize,
+    /// Documents in the index after the merge, once done.
+    pub doc_count: Option<usize>,
+    /// Why the job failed.
+    pub error: Option<String>,
+}
+
+/// Crawl jobs of this server, oldest first (a job's id is its position + 1).
+/// One runs at a time.
+#[derive(Default)]
+pub struct CrawlJobs {
+    jobs: Mutex<Vec<(CrawlJobInfo, Arc<AtomicUsize>)>>,
+}
+
+impl CrawlJobs {
+    /// Register a running job whose crawler counts pages in `pages`, or return
+    /// the id of the job still running.
+    fn start(&self, url: &str, index: &str, pages: Arc<AtomicUsize>) -> Result<CrawlJobInfo, u64> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some((running, _)) = jobs.iter().find(|(job, _)| job.status == CrawlStatus::Running) {
+            return Err(running.id);
+        }
+        let info = CrawlJobInfo {
+            id: jobs.len() as u64 + 1,
+            url: url.to_string(),
+            index: index.to_string(),
+            status: CrawlStatus::Running,
+            pages: 0,
+            doc_count: None,
+            error: None,
+        };
+        jobs.push((info.clone(), pages));
+        Ok(info)
+    }
+
+    /// Record the outcome of job `id`: the new document count, or an error.
+    fn finish(&self, id: u64, outcome: Result<usize, String>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some((info, pages)) = jobs.get_mut(id as usize - 1) else {
+            return;
+        };
+        info.pages = pages.load(Ordering::Relaxed);
+        match outcome {
+            Ok(doc_count) => {
+                info.status = CrawlStatus::Done;
+                info.doc_count = Some(doc_count);
+            }
+            Err(e) => {
+                info.status = CrawlStatus::Failed;
+                info.error = Some(e);
+            }
+        }
+    }
+
+    fn get(&self, id: u64) -> Option<CrawlJobInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        let (info, pages) = jobs.get(usize::try_from(id).ok()?.checked_sub(1)?)?;
+        let mut info = info.clone();
+        info.pages = pages.load(Ordering::Relaxed);
+        Some(info)
+    }
+}
+
+/// POST /admin/crawl -> start crawling a site in the background, then merge the
+/// pages into a running in-memory index (replacing documents with the same URL).
+/// Needs `--enable-writes`; one crawl runs at a time. Returns the job at once;
+/// follow it with `GET /admin/crawl/{id}`.
+#[utoipa::path(
+    post,
+    path = "/admin/crawl",
+    params(DocumentsQuery),
+    request_body = CrawlRequest,
+    responses(
+        (status = 202, description = "Crawl started", body = CrawlJobInfo),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong API key", body = ErrorResponse),
+        (status = 403, description = "Writes are disabled", body = ErrorResponse),
+        (status = 404, description = "Unknown index", body = ErrorResponse),
+        (status = 409, description = "A crawl is already running", body = ErrorResponse),
+        (status = 501, description = "Not supported by the sqlite backend", body = ErrorResponse),
+    )
+)]
+pub async fn crawl_handler(
+    State(state): State<AppState>,
+    params: Result<Query<DocumentsQuery>, QueryRejection>,
+    body: Result<Json<CrawlRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<CrawlJobInfo>), ApiError> {
+    check_writes(&state)?;
+    let params = query_params(params)?;
+    let Json(body) = body.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    if url::Url::parse(&body.url).is_err() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, format!("invalid URL {:?}", body.url)));
+    }
+    let name = params.index.unwrap_or_else(|| state.default_index.clone());
+    if let IndexBackend::Sqlite(_) = state.get(Some(name.as_str()))?.snapshot().index {
+        return Err(read_only_error());
+    }
+    let pages = Arc::new(AtomicUsize::new(0));
+    let job = state.crawls.start(&body.url, &name, pages.clone()).map_err(|running| {
+        let error = format!("crawl job {} is still running", running);
+        ApiError::new(StatusCode::CONFLICT, error)
+    })?;
+    let options = CrawlOptions {
+        max_pages: body.max_pages,
+        max_depth: body.max_depth,
+        progress: Some(pages),
+        ..Default::default()
+    };
+    let id = job.id;
+    tokio::spawn(async move {
+        let outcome = run_crawl_job(&state, &name, &body.url, &options, params.persist).await;
+        match &outcome {
+            Ok(doc_count) => tracing::info!(job = id, index = %name, doc_count, "crawl job done"),
+            Err(e) => tracing::warn!(job = id, index = %name, error = %e, "crawl job failed"),
+        }
+        state.crawls.finish(id, outcome);
+    });
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Crawl `url`, index the pages with the analyzer of index `name` on a blocking
+/// task and merge them in, returning the new document count.
+async fn run_crawl_job(
+    state: &AppState,
+    name: &str,
+    url: &str,
+    options: &CrawlOptions,
+    persist: bool,
+) -> Result<usize, String> {
+    let output = crawler::crawl_async(url, options, None)
+        .await
+        .map_err(|e| format!("crawl failed: {}", e))?;
+    let named = &state.indexes[name];
+    let analyzer = named.snapshot().index.analyzer().clone();
+    let crawled = tokio::task::spawn_blocking(move || IndexWithTf::build_with(&output.results, &analyzer))
+        .await
+        .map_err(|e| format!("indexing task failed: {}", e))?;
+    let ((), doc_count) = named
+        .update(|index| index.merge(crawled))
+        .ok_or("the sqlite backend is read-only while served")?;
+    if persist {
+        named
+            .persist()
+            .await
+            .map_err(|e| format!("the index was changed in memory but not saved: {}", e))?;
+    }
+    Ok(doc_count)
+}
+
+/// GET /admin/crawl/{id} -> status and progress of a crawl job.
+#[utoipa::path(
+    get,
+    path = "/admin/crawl/{id}",
+    params(("id" = u64, Path, description = "Job id returned by `POST /admin/crawl`")),
+    responses(
+        (status = 200, body = CrawlJobInfo),
+        (status = 400, description = "Invalid job id", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong API key", body = ErrorResponse),
+        (status = 404, description = "Unknown job", body = ErrorResponse),
+    )
+)]
+pub async fn crawl_status_handler(
+    State(state): State<AppState>,
+    id: Result<Path<u64>, PathRejection>,
+) -> Result<Json<CrawlJobInfo>, ApiError> {
+    let Path(id) = id.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    state
+        .crawls
+        .get(id)
+        .map(Json)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("no crawl job {}", id)))
 }
 
 /// GET / -> static HTML search form (Phase 6 frontend).
+#[utoipa::path(get, path = "/", responses((status = 200, description = "Search page", content_type = "text/html")))]
 pub async fn index_page() -> axum::response::Html<&'static str> {
-    const HTML: &str = r#"
+    const HTML: &str = r##"
 <!DOCTYPE html>
 <html lang="ja">
 <head>
@@ -50,16 +1889,22 @@ pub async fn index_page() -> axum::response::Html<&'static str> {
     input[type="search"] { width: 100%; padding: 0.5rem; font-size: 1rem; box-sizing: border-box; }
     button { margin-top: 0.5rem; padding: 0.5rem 1rem; font-size: 1rem; cursor: pointer; }
     #results { margin-top: 1.5rem; }
-    #results a { display: block; padding: 0.5rem 0; border-bottom: 1px solid #eee; color: #06c; }
-    #results a:hover { text-decoration: underline; }
+    .hit { padding: 0.75rem 0; border-bottom: 1px solid #eee; }
+    .hit a { font-size: 1.05rem; color: #06c; text-decoration: none; }
+    .hit a:hover { text-decoration: underline; }
+    .url { font-size: 0.875rem; color: #080; word-break: break-all; }
+    .snippet { margin: 0.25rem 0 0; font-size: 0.9rem; color: #333; }
+    .snippet em { font-style: normal; font-weight: bold; }
     .score { font-size: 0.875rem; color: #666; }
     .none { color: #666; }
+    .suggestion { margin: 0 0 0.5rem; }
   </style>
 </head>
 <body>
   <h1>RustySearch</h1>
   <form id="form">
-    <input type="search" name="q" id="q" placeholder="検索語を入力" autofocus>
+    <input type="search" name="q" id="q" placeholder="検索語を入力" list="completions" autocomplete="off" autofocus>
+    <datalist id="completions"></datalist>
     <button type="submit">検索</button>
   </form>
   <div id="results"></div>
@@ -67,6 +1912,27 @@ pub async fn index_page() -> axum::response::Html<&'static str> {
     const form = document.getElementById('form');
     const q = document.getElementById('q');
     const results = document.getElementById('results');
+    const completions = document.getElementById('completions');
+    const escape = (s) => s.replace(/[&<>"']/g, (c) => ({'&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;'}[c]));
+    // Snippets are plain text with <em> markers: escape everything, then restore the markers.
+    const highlight = (s) => escape(s).replace(/&lt;em&gt;/g, '<em>').replace(/&lt;\/em&gt;/g, '</em>');
+    // Type-ahead: ask /suggest once typing pauses, ignoring answers to older input.
+    let timer;
+    q.addEventListener('input', () => {
+      clearTimeout(timer);
+      timer = setTimeout(async () => {
+        const typed = q.value;
+        if (!typed.trim()) { completions.innerHTML = ''; return; }
+        try {
+          const r = await fetch('/suggest?q=' + encodeURIComponent(typed));
+          if (!r.ok || q.value !== typed) return;
+          const terms = await r.json();
+          completions.innerHTML = terms.map(t => '<option value="' + escape(t) + '">').join('');
+        } catch (err) {
+          // Completions are optional; searching still works.
+        }
+      }, 150);
+    });
     form.addEventListener('submit', async (e) => {
       e.preventDefault();
       const query = q.value.trim();
@@ -74,15 +1940,32 @@ pub async fn index_page() -> axum::response::Html<&'static str> {
       results.innerHTML = '<p class="none">検索中...</p>';
       try {
         const r = await fetch('/search?q=' + encodeURIComponent(query));
-        const hits = await r.json();
+        const page = await r.json();
+        if (!r.ok) { results.innerHTML = '<p class="none">エラー: ' + escape(page.error.message) + '</p>'; return; }
+        const hits = page.hits;
+        const suggestion = page.suggestion
+          ? '<p class="suggestion">もしかして: <a href="#" id="suggestion">' + escape(page.suggestion) + '</a></p>'
+          : '';
         if (hits.length === 0) {
-          results.innerHTML = '<p class="none">該当なし</p>';
+          results.innerHTML = suggestion + '<p class="none">該当なし</p>';
         } else {
-          results.innerHTML = hits.map(h => 
-            '<a href="' + h.url + '" target="_blank" rel="noopener">' + h.url + '</a>' +
-            '<span class="score">score: ' + h.score.toFixed(4) + '</span>'
+          results.innerHTML = suggestion + '<p class="score">' + page.total + ' 件（' + page.took_ms.toFixed(1) + ' ms）</p>' + hits.map(h =>
+            '<div class="hit">' +
+            '<a href="' + escape(h.url) + '" target="_blank" rel="noopener">' + escape(h.title || h.url) + '</a>' +
+            '<div class="url">' + escape(h.url) + '</div>' +
+            (h.snippet ? '<p class="snippet">' + highlight(h.snippet) + '</p>' : '') +
+            '<span class="score">score: ' + h.score.toFixed(4) + '</span>' +
+            '</div>'
           ).join('');
         }
+        const link = document.getElementById('suggestion');
+        if (link) {
+          link.addEventListener('click', (e) => {
+            e.preventDefault();
+            q.value = page.suggestion;
+            form.requestSubmit();
+          });
+        }
       } catch (err) {
         results.innerHTML = '<p class="none">エラー: ' + err + '</p>';
       }
@@ -90,6 +1973,6 @@ pub async fn index_page() -> axum::response::Html<&'static str> {
   </script>
 </body>
 </html>
-"#;
+"##;
     axum::response::Html(HTML)
 }
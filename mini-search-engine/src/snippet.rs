@@ -0,0 +1,80 @@
+//! Query-centered snippets: a window of stored document text around the first
+//! query term, with matched words wrapped in markers.
+
+use std::collections::HashSet;
+
+use crate::tokenize::{self, Analyzer};
+
+/// Markers wrapped around matched words when the request gives none.
+pub const DEFAULT_MARKERS: (&str, &str) = ("<em>", "</em>");
+
+/// Byte offset of the `n`th character (the text length past the end).
+fn char_offset(text: &str, n: usize) -> usize {
+    text.char_indices().nth(n).map_or(text.len(), |(i, _)| i)
+}
+
+/// Snippet of at most about `max_chars` characters of `text`, centered on the
+/// first word that analyzes (with `analyzer`) to one of `terms`. Matched words in
+/// the window are wrapped in `markers`, and cut ends are marked with "…". `None`
+/// if no word matches. Consecutive matched words separated only by whitespace (a
+/// matched phrase) share one pair of markers. Offsets always fall on character
+/// boundaries, so multi-byte text is never split inside a character.
+pub fn snippet(
+    text: &str,
+    analyzer: &dyn Analyzer,
+    terms: &HashSet<&str>,
+    max_chars: usize,
+    markers: (&str, &str),
+) -> Option<String> {
+    // (start, end) byte ranges of matched words, with adjacent words merged.
+    let mut matches: Vec<(usize, usize)> = Vec::new();
+    let mut last_position = None;
+    for token in tokenize::tokenize_with_positions(text) {
+        let (start, end) = (token.byte_offset, token.byte_offset + token.byte_len);
+        if !analyzer.analyze(&text[start..end]).iter().any(|t| terms.contains(t.as_str())) {
+            continue;
+        }
+        let adjacent = last_position.map(|p| p + 1) == Some(token.position);
+        match matches.last_mut() {
+            Some(last) if adjacent && text[last.1..start].trim().is_empty() => last.1 = end,
+            _ => matches.push((start, end)),
+        }
+        last_position = Some(token.position);
+    }
+    let &(first_start, first_end) = matches.first()?;
+
+    let total_chars = text.chars().count();
+    let first_char = text[..first_start].chars().count();
+    let start_char = first_char.saturating_sub(max_chars / 2).min(total_chars.saturating_sub(max_chars));
+    let mut start = char_offset(text, start_char);
+    let mut end = char_offset(text, start_char + max_chars).max(first_end);
+    // Don't start or end mid-word when there's a word boundary to move to.
+    if start > 0 && !text[..start].ends_with(char::is_whitespace) {
+        start = text[start..first_start].find(char::is_whitespace).map_or(first_start, |i| start + i);
+    }
+    if end < text.len() && !text[end..].starts_with(char::is_whitespace) {
+        if let Some(i) = text[first_end..end].rfind(char::is_whitespace) {
+            end = first_end + i;
+        }
+    }
+    start += text[start..end].len() - text[start..end].trim_start().len();
+    end -= text[start..end].len() - text[start..end].trim_end().len();
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push('…');
+    }
+    let mut pos = start;
+    for &(s, e) in matches.iter().filter(|&&(s, e)| s >= start && e <= end) {
+        out.push_str(&text[pos..s]);
+        out.push_str(markers.0);
+        out.push_str(&text[s..e]);
+        out.push_str(markers.1);
+        pos = e;
+    }
+    out.push_str(&text[pos..end]);
+    if end < text.len() {
+        out.push('…');
+    }
+    Some(out)
+}
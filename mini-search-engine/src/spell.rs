@@ -0,0 +1,49 @@
+//! Spelling correction: the vocabulary term closest to a misspelled query word.
+
+/// Most edits a correction may make to `word`: 1 for words of up to four
+/// characters, where two edits make almost anything else, otherwise 2.
+pub fn max_edits(word: &str) -> usize {
+    if word.chars().count() <= 4 {
+        1
+    } else {
+        2
+    }
+}
+
+/// Levenshtein distance between `a` and `b`, or None if it is more than `max`.
+/// Stops as soon as a row of the table has nothing within `max`.
+pub fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+    for (i, &ca) in a.iter().enumerate() {
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            row[j + 1] = substitution.min(prev[j + 1] + 1).min(row[j] + 1);
+        }
+        if row.iter().all(|&d| d > max) {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+    Some(prev[b.len()]).filter(|&d| d <= max)
+}
+
+/// The term of `vocabulary` closest to `word` other than `word` itself: fewest
+/// edits (at most `max_edits(word)`), then the most documents by `df`, then
+/// alphabetical. `df` is only asked about terms within reach.
+pub fn closest<'a>(word: &str, vocabulary: &'a [String], df: impl Fn(&str) -> usize) -> Option<&'a str> {
+    let max = max_edits(word);
+    vocabulary
+        .iter()
+        .filter(|term| term.as_str() != word)
+        .filter_map(|term| Some((edit_distance(word, term, max)?, term.as_str())))
+        .map(|(edits, term)| (edits, df(term), term))
+        .min_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(b.2)))
+        .map(|(_, _, term)| term)
+}
@@ -0,0 +1,327 @@
+//! SQLite index storage: postings stay on disk and are read per query.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::index::{self, DocId, DocMeta, Field, IndexReader, IndexWithTf, Posting, ReadResult, StoredDocument};
+use crate::tokenize::AnalyzerConfig;
+
+/// Schema version stored in the `meta` table.
+const SCHEMA_VERSION: u32 = 3;
+
+const SCHEMA: &str = "
+CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE documents (
+    id INTEGER PRIMARY KEY,
+    url TEXT NOT NULL UNIQUE,
+    length INTEGER,
+    -- NULL title: no metadata recorded
+    title TEXT,
+    description TEXT,
+    text TEXT,
+    last_modified TEXT,
+    -- norm of the body TF-IDF vector (cosine ranking)
+    norm REAL
+);
+-- df: the largest document frequency over all fields (orders prefix completions)
+CREATE TABLE terms (term TEXT PRIMARY KEY, df INTEGER NOT NULL) WITHOUT ROWID;
+-- field: 0 = body, 1 = title, 2 = headings, 3 = url
+CREATE TABLE postings (
+    field INTEGER NOT NULL,
+    term TEXT NOT NULL,
+    doc INTEGER NOT NULL,
+    tf INTEGER NOT NULL,
+    positions BLOB,
+    PRIMARY KEY (field, term, doc)
+) WITHOUT ROWID;
+";
+
+const FIELD_BODY: i64 = 0;
+
+/// `postings.field` value of a non-body field.
+fn field_id(field: Field) -> i64 {
+    match field {
+        Field::Title => 1,
+        Field::Headings => 2,
+        Field::Url => 3,
+    }
+}
+
+/// Positions as little-endian u32s.
+fn encode_positions(positions: &[u32]) -> Vec<u8> {
+    positions.iter().flat_map(|p| p.to_le_bytes()).collect()
+}
+
+fn decode_positions(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Write `index` as a SQLite database at `path`, replacing any existing file.
+/// The database is built in a temporary file and renamed into place.
+pub fn save(index: &IndexWithTf, path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let tmp = index::temp_path(path);
+    let _ = fs::remove_file(&tmp);
+    if let Err(e) = write_db(index, &tmp) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn write_db(index: &IndexWithTf, path: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+    {
+        let mut meta = tx.prepare("INSERT INTO meta (key, value) VALUES (?1, ?2)")?;
+        meta.execute(params!["version", SCHEMA_VERSION.to_string()])?;
+        meta.execute(params!["doc_count", index.doc_count.to_string()])?;
+        // Display of f64 round-trips exactly, so scores match the file index.
+        meta.execute(params!["avg_doc_len", index.avg_doc_len.to_string()])?;
+        meta.execute(params!["pipeline", serde_json::to_string(&index.analyzer)?])?;
+        meta.execute(params!["aliases", serde_json::to_string(&index.aliases)?])?;
+
+        let mut doc = tx.prepare(
+            "INSERT INTO documents (id, url, length, title, description, text, last_modified, norm)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for (id, url) in index.urls.iter().enumerate() {
+            if url.is_empty() {
+                continue;
+            }
+            let id = id as DocId;
+            let meta = index.docs.get(&id);
+            doc.execute(params![
+                id,
+                url,
+                index.doc_lengths.get(&id),
+                meta.map(|m| &m.title),
+                meta.map(|m| &m.description),
+                meta.map(|m| &m.text),
+                meta.and_then(|m| m.last_modified).map(|t| t.to_rfc3339()),
+                index.doc_norm(id),
+            ])?;
+        }
+
+        let mut posting = tx.prepare(
+            "INSERT INTO postings (field, term, doc, tf, positions) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (term, docs) in &index.term_tf {
+            let positions = index.positions.get(term);
+            for (&id, &tf) in docs {
+                let blob = positions.and_then(|m| m.get(&id)).map(|p| encode_positions(p));
+                posting.execute(params![FIELD_BODY, term, id, tf, blob])?;
+            }
+        }
+        for (&field, postings) in &index.fields {
+            for (term, docs) in postings {
+                for (&id, &tf) in docs {
+                    posting.execute(params![field_id(field), term, id, tf, None::<Vec<u8>>])?;
+                }
+            }
+        }
+
+        let mut term_row = tx.prepare("INSERT INTO terms (term, df) VALUES (?1, ?2)")?;
+        let field_terms = index.fields.values().flat_map(|postings| postings.keys());
+        let all_terms: BTreeSet<&String> = index.term_tf.keys().chain(field_terms).collect();
+        for term in all_terms {
+            term_row.execute(params![term, index.max_df(term)])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// A SQLite index opened for reading.
+pub struct SqliteIndex {
+    conn: Mutex<Connection>,
+    doc_count: usize,
+    avg_doc_len: f64,
+    analyzer: AnalyzerConfig,
+    vocabulary: usize,
+}
+
+impl SqliteIndex {
+    /// Open a database written by `save`.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if !path.exists() {
+            return Err(format!("{:?} does not exist", path).into());
+        }
+        let conn = Connection::open(path)?;
+        let meta: HashMap<String, String> = conn
+            .prepare("SELECT key, value FROM meta")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        let get = |key: &str| meta.get(key).ok_or_else(|| format!("SQLite index is missing meta {:?}", key));
+        let version: u32 = get("version")?.parse()?;
+        if version > SCHEMA_VERSION {
+            return Err(format!(
+                "index was written by a newer version (SQLite schema v{}; this build reads up to v{})",
+                version, SCHEMA_VERSION
+            )
+            .into());
+        }
+        if version < SCHEMA_VERSION {
+            return Err(format!(
+                "SQLite schema v{} is no longer supported (this build reads v{}); convert the index again",
+                version, SCHEMA_VERSION
+            )
+            .into());
+        }
+        let analyzer: AnalyzerConfig = serde_json::from_str(get("pipeline")?)?;
+        analyzer.standard().check()?;
+        if let Some(warning) = analyzer.standard().segmentation_mismatch() {
+            eprintln!("Warning: {}", warning);
+        }
+        let vocabulary: i64 = conn.query_row(
+            "SELECT COUNT(DISTINCT term) FROM postings WHERE field = ?1",
+            params![FIELD_BODY],
+            |row| row.get(0),
+        )?;
+        Ok(Self {
+            doc_count: get("doc_count")?.parse()?,
+            avg_doc_len: get("avg_doc_len")?.parse()?,
+            analyzer,
+            vocabulary: vocabulary as usize,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Distinct body terms, counted when the database is opened.
+    pub fn vocabulary(&self) -> usize {
+        self.vocabulary
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl IndexReader for SqliteIndex {
+    fn doc_count(&self) -> usize {
+        self.doc_count
+    }
+
+    fn avg_doc_len(&self) -> f64 {
+        self.avg_doc_len
+    }
+
+    fn analyzer(&self) -> &AnalyzerConfig {
+        &self.analyzer
+    }
+
+    fn postings(&self, term: &str) -> ReadResult<Vec<Posting>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached("SELECT doc, tf, positions FROM postings WHERE field = ?1 AND term = ?2")?;
+        let rows = stmt.query_map(params![FIELD_BODY, term], |row| {
+            let positions: Option<Vec<u8>> = row.get(2)?;
+            Ok(Posting {
+                doc: row.get(0)?,
+                tf: row.get(1)?,
+                positions: positions.as_deref().map(decode_positions).unwrap_or_default(),
+            })
+        })?;
+        let rows = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    fn field_postings(&self, field: Field, term: &str) -> ReadResult<Vec<(DocId, u32)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached("SELECT doc, tf FROM postings WHERE field = ?1 AND term = ?2")?;
+        let rows = stmt.query_map(params![field_id(field), term], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let rows = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    fn prefix_terms(&self, prefix: &str) -> ReadResult<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached(
+            "SELECT term FROM terms WHERE term >= ?1 AND term < ?2 AND substr(term, 1, length(?1)) = ?1
+             ORDER BY df DESC, term LIMIT ?3",
+        )?;
+        // Upper bound of the key range holding every term that starts with `prefix`.
+        let end = format!("{}{}", prefix, char::MAX);
+        let rows = stmt.query_map(params![prefix, end, index::MAX_PREFIX_EXPANSIONS], |row| row.get(0))?;
+        let rows = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    fn document(&self, id: DocId) -> ReadResult<Option<StoredDocument>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached(
+            "SELECT url, length, title, description, text, last_modified, norm FROM documents WHERE id = ?1",
+        )?;
+        let row = stmt
+            .query_row(params![id], |row| {
+                let title: Option<String> = row.get(2)?;
+                let last_modified: Option<String> = row.get(5)?;
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<u32>>(1)?,
+                    title,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    last_modified,
+                    row.get::<_, Option<f64>>(6)?,
+                ))
+            })
+            .optional()?;
+        let Some((url, length, title, description, text, last_modified, norm)) = row else {
+            return Ok(None);
+        };
+        let meta = match title {
+            Some(title) => Some(DocMeta {
+                title,
+                description: description.unwrap_or_default(),
+                text: text.unwrap_or_default(),
+                last_modified: last_modified
+                    .map(|t| DateTime::parse_from_rfc3339(&t).map(|t| t.with_timezone(&Utc)))
+                    .transpose()?,
+            }),
+            None => None,
+        };
+        Ok(Some(StoredDocument { url, length, meta, norm }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{load_index_with_tf, Ranker, SearchOptions};
+
+    #[test]
+    fn sqlite_and_in_memory_rankings_are_identical() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/index/v2.json");
+        let index = load_index_with_tf(&fixture).unwrap();
+        let path = std::env::temp_dir().join(format!("rustysearch-test-{}-ranking.db", std::process::id()));
+        save(&index, &path).unwrap();
+        let sqlite = SqliteIndex::open(&path).unwrap();
+        assert_eq!(sqlite.doc_count(), index.doc_count);
+        assert!(!index.search_ranked("tokio async").is_empty());
+        for query in ["rust", "tokio async", "\"error handling\"", "title:rust", "rust -tokio", "検索", "nothing"] {
+            for ranker in [Ranker::Tfidf, Ranker::Bm25, Ranker::Cosine] {
+                for prefix in [false, true] {
+                    let options = SearchOptions {
+                        ranker,
+                        prefix,
+                        ..Default::default()
+                    };
+                    let expected = index.search_ranked_with(query, &options);
+                    let loaded = IndexWithTf::for_query(&sqlite, query, &options).unwrap();
+                    assert_eq!(loaded.search_ranked_with(query, &options), expected, "{} {:?}", query, ranker);
+                }
+            }
+        }
+        drop(sqlite);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+//! Built-in stopword lists: very common words dropped at index and query time
+//! (see `tokenize::Stopwords`).
+
+/// Built-in English stopwords (already lowercase, as produced by `tokenize`).
+pub const ENGLISH: &[&str] = &[
+    "a", "about", "after", "all", "also", "am", "an", "and", "any", "are", "as", "at", "be", "because", "been",
+    "before", "being", "between", "both", "but", "by", "can", "could", "did", "do", "does", "doing", "down",
+    "during", "each", "few", "for", "from", "further", "had", "has", "have", "having", "he", "her", "here",
+    "hers", "him", "his", "how", "i", "if", "in", "into", "is", "it", "its", "itself", "just", "me", "more",
+    "most", "my", "no", "nor", "not", "now", "of", "off", "on", "once", "only", "or", "other", "our", "ours",
+    "out", "over", "own", "same", "she", "should", "so", "some", "such", "than", "that", "the", "their",
+    "theirs", "them", "then", "there", "these", "they", "this", "those", "through", "to", "too", "under",
+    "until", "up", "very", "was", "we", "were", "what", "when", "where", "which", "while", "who", "whom", "why",
+    "will", "with", "would", "you", "your", "yours",
+];
+
+/// Built-in Japanese stopwords: case, binding, conjunctive and sentence-final particles.
+pub const JAPANESE: &[&str] = &[
+    "が", "を", "に", "へ", "と", "で", "の", "から", "より", "まで", "は", "も", "こそ", "さえ", "でも", "しか",
+    "だけ", "ばかり", "など", "や", "か", "ね", "よ", "な", "ば", "て", "つつ", "ながら",
+];
@@ -0,0 +1,80 @@
+//! Query-time synonym expansion from a user-supplied dictionary.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::tokenize::Analyzer;
+
+/// Default score weight of a synonym relative to the term the user typed.
+pub const DEFAULT_WEIGHT: f64 = 0.8;
+
+/// Synonym groups: every term maps to the other members of its group.
+#[derive(Debug, Clone, Default)]
+pub struct Synonyms {
+    map: HashMap<String, Vec<String>>,
+    /// Score multiplier for expanded terms.
+    pub weight: f64,
+}
+
+impl Synonyms {
+    /// Load a dictionary: one comma-separated group per line, `#` starts a comment.
+    /// Words go through the index's analyzer so they match indexed terms.
+    /// Groups sharing a word are merged. Returns the dictionary and warnings about
+    /// merged groups, duplicates and entries that aren't a single word.
+    pub fn load(
+        path: &Path,
+        analyzer: &dyn Analyzer,
+        weight: f64,
+    ) -> Result<(Self, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
+        let text = fs::read_to_string(path)?;
+        let mut warnings = Vec::new();
+        let mut groups: Vec<BTreeSet<String>> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut group = BTreeSet::new();
+            for entry in line.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                match analyzer.analyze(entry).as_slice() {
+                    [word] => {
+                        if !group.insert(word.clone()) {
+                            warnings.push(format!("line {}: duplicate {:?}", n + 1, entry));
+                        }
+                    }
+                    _ => warnings.push(format!("line {}: {:?} is not a single indexable word; skipped", n + 1, entry)),
+                }
+            }
+            if group.len() < 2 {
+                continue;
+            }
+            // Merge every existing group that shares a word with this one.
+            let (overlapping, mut rest): (Vec<_>, Vec<_>) =
+                groups.into_iter().partition(|g: &BTreeSet<String>| !g.is_disjoint(&group));
+            if !overlapping.is_empty() {
+                warnings.push(format!("line {}: overlaps an earlier group; merged", n + 1));
+            }
+            for g in overlapping {
+                group.extend(g);
+            }
+            rest.push(group);
+            groups = rest;
+        }
+        let mut map = HashMap::new();
+        for group in groups {
+            for word in &group {
+                let others = group.iter().filter(|w| *w != word).cloned().collect();
+                map.insert(word.clone(), others);
+            }
+        }
+        Ok((Self { map, weight }, warnings))
+    }
+
+    /// Other members of `term`'s group.
+    pub fn expand(&self, term: &str) -> &[String] {
+        self.map.get(term).map_or(&[], Vec::as_slice)
+    }
+
+    /// Number of words with synonyms.
+    pub fn word_count(&self) -> usize {
+        self.map.len()
+    }
+}
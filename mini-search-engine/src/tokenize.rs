@@ -1,12 +1,1370 @@
-//! Text to word tokenization: split, strip punctuation, lowercase.
+//! Text to word tokenization: split, strip punctuation, lowercase. CJK text can
+//! be split into character bigrams instead.
+
+use rust_stemmers::{Algorithm, Stemmer};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use unicode_normalization::{is_nfkc_quick, IsNormalized, UnicodeNormalization as _};
+use unicode_segmentation::UnicodeSegmentation as _;
+
+use crate::stopwords;
+
+/// A word of some text and where it occurs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    /// The lowercased word.
+    pub text: String,
+    /// Index of the token among the text's tokens.
+    pub position: u32,
+    /// Byte range of the word in the original text (before lowercasing, which
+    /// can change the length), so `&text[byte_offset..byte_offset + byte_len]`
+    /// is the word as written.
+    pub byte_offset: usize,
+    pub byte_len: usize,
+}
 
 /// Split text into words: by whitespace, strip non-alphanumeric, lowercase.
 pub fn tokenize(text: &str) -> Vec<String> {
+    tokenize_with_positions(text).into_iter().map(|token| token.text).collect()
+}
+
+/// Like `tokenize`, keeping each word's position and byte range in `text`.
+pub fn tokenize_with_positions(text: &str) -> Vec<Token> {
     text.split_whitespace()
-        .map(|s| {
-            s.trim_matches(|c: char| !c.is_alphanumeric())
-                .to_lowercase()
-        })
+        .map(|s| s.trim_matches(|c: char| !c.is_alphanumeric()))
         .filter(|s| !s.is_empty())
+        .enumerate()
+        .map(|(i, word)| Token {
+            text: word.to_lowercase(),
+            position: i as u32,
+            byte_offset: word.as_ptr() as usize - text.as_ptr() as usize,
+            byte_len: word.len(),
+        })
         .collect()
 }
+
+/// True for Han, Hiragana and Katakana characters (including the iteration mark
+/// and halfwidth Katakana), which are written without spaces between words.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3005}'                      // 々
+        | '\u{3040}'..='\u{30FF}'       // Hiragana, Katakana
+        | '\u{31F0}'..='\u{31FF}'       // Katakana phonetic extensions
+        | '\u{3400}'..='\u{4DBF}'       // CJK extension A
+        | '\u{4E00}'..='\u{9FFF}'       // CJK unified ideographs
+        | '\u{F900}'..='\u{FAFF}'       // CJK compatibility ideographs
+        | '\u{FF66}'..='\u{FF9F}'       // halfwidth Katakana
+        | '\u{20000}'..='\u{2FFFF}'     // CJK extensions B and later
+    )
+}
+
+/// Like `tokenize`, but each run of CJK characters becomes overlapping character
+/// bigrams ("検索エンジン" -> "検索", "索エ", "エン", "ンジ", "ジン"); a run of one
+/// character stays a unigram. Other text is tokenized as usual, so "Rustで検索"
+/// gives "rust", "で検", "検索".
+pub fn tokenize_cjk(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text.split_whitespace() {
+        let chars: Vec<char> = word.chars().collect();
+        let mut start = 0;
+        while start < chars.len() {
+            let cjk = is_cjk(chars[start]);
+            let end = chars[start..]
+                .iter()
+                .position(|&c| is_cjk(c) != cjk)
+                .map_or(chars.len(), |n| start + n);
+            let run = &chars[start..end];
+            if cjk {
+                push_cjk_run(run, &mut tokens);
+            } else {
+                tokens.extend(tokenize(&run.iter().collect::<String>()));
+            }
+            start = end;
+        }
+    }
+    tokens
+}
+
+/// A run of CJK characters as overlapping bigrams, or a unigram for a run of one.
+fn push_cjk_run(run: &[char], tokens: &mut Vec<String>) {
+    if run.is_empty() {
+        return;
+    }
+    if run.len() == 1 {
+        tokens.push(run[0].to_string());
+    } else {
+        tokens.extend(run.windows(2).map(|pair| pair.iter().collect::<String>()));
+    }
+}
+
+/// Split text into lowercase words at Unicode word boundaries (UAX #29), which
+/// keeps words with inner punctuation whole ("can't", "3.14") and separates words
+/// that aren't spaced ("Rustで" -> "rust", "で"). Words joined by single hyphens
+/// stay one token ("state-of-the-art"), as with `tokenize`. Han and Kana have no
+/// word boundaries to find, so adjacent CJK characters become bigrams as in
+/// `tokenize_cjk`: "Rustで検索" gives "rust", "で検", "検索". Segments without a
+/// letter or digit (punctuation, emoji) are dropped.
+pub fn tokenize_unicode(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut run: Vec<char> = Vec::new();
+    let mut run_end = 0;
+    let mut hyphenated: Vec<&str> = Vec::new();
+    let mut hyphenated_end = 0;
+    for (start, word) in text.unicode_word_indices() {
+        let cjk = word.chars().all(is_cjk);
+        if !cjk && !hyphenated.is_empty() && HYPHENS.contains(&&text[hyphenated_end..start]) {
+            hyphenated.push(word);
+            hyphenated_end = start + word.len();
+            continue;
+        }
+        if !hyphenated.is_empty() {
+            tokens.push(hyphenated.join("-").to_lowercase());
+            hyphenated.clear();
+        }
+        if !cjk || start != run_end {
+            push_cjk_run(&run, &mut tokens);
+            run.clear();
+        }
+        if cjk {
+            run.extend(word.chars());
+            run_end = start + word.len();
+        } else {
+            hyphenated.push(word);
+            hyphenated_end = start + word.len();
+        }
+    }
+    if !hyphenated.is_empty() {
+        tokens.push(hyphenated.join("-").to_lowercase());
+    }
+    push_cjk_run(&run, &mut tokens);
+    tokens
+}
+
+/// Hyphen-minus and the Unicode hyphens, which join words into one.
+const HYPHENS: [&str; 3] = ["-", "\u{2010}", "\u{2011}"];
+
+/// Apostrophes other than `'`: right and left single quotation marks (as word
+/// processors type them), the modifier letter apostrophe and the full-width one.
+const TYPOGRAPHIC_APOSTROPHES: [char; 4] = ['\u{2019}', '\u{2018}', '\u{02BC}', '\u{FF07}'];
+
+/// What the pipeline does with apostrophes and hyphens inside tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum WordPunctuation {
+    /// Tokens as the tokenizer made them ("don't", "state-of-the-art").
+    #[default]
+    Keep,
+    /// In order, the same for documents and queries:
+    /// 1. Typographic apostrophes become `'` and the Unicode hyphens `-`.
+    /// 2. A hyphenated token is kept whole and followed by its parts:
+    ///    "state-of-the-art" -> "state-of-the-art", "state", "of", "the", "art".
+    /// 3. In each part, a final possessive `'s` is dropped ("rust's" -> "rust"),
+    ///    then every other apostrophe ("don't" -> "dont", "o'neill" -> "oneill").
+    ///
+    /// Parts left empty are skipped. Stopwords are removed afterwards, so they
+    /// apply to the parts too.
+    Split,
+}
+
+impl WordPunctuation {
+    pub fn apply(self, tokens: Vec<String>) -> Vec<String> {
+        if self == WordPunctuation::Keep {
+            return tokens;
+        }
+        let mut split = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            self.split_into(token, &mut split);
+        }
+        split
+    }
+
+    /// Push what `token` becomes to `out`.
+    fn split_into(self, token: String, out: &mut Vec<String>) {
+        if self == WordPunctuation::Keep {
+            out.push(token);
+            return;
+        }
+        let token: String = token
+            .chars()
+            .map(|c| match c {
+                c if TYPOGRAPHIC_APOSTROPHES.contains(&c) => '\'',
+                '\u{2010}' | '\u{2011}' => '-',
+                c => c,
+            })
+            .collect();
+        let parts: Vec<String> = token.split('-').map(without_apostrophes).filter(|part| !part.is_empty()).collect();
+        if parts.len() > 1 {
+            out.push(parts.join("-"));
+        }
+        out.extend(parts);
+    }
+}
+
+/// A word without surrounding punctuation, a final possessive `'s` and other apostrophes.
+fn without_apostrophes(word: &str) -> String {
+    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+    word.strip_suffix("'s").unwrap_or(word).replace('\'', "")
+}
+
+/// Rules for numbers, versions and dates, which the tokenizers and `WordPunctuation`
+/// otherwise handle like words. Each rule can be turned off on its own, as
+/// corpora differ in what they want.
+///
+/// The rules run on each token before `WordPunctuation` and stopwords, the same
+/// for documents and queries, and the first that applies wins:
+///
+/// | Rule        | Token                                                      | Becomes                          |
+/// |-------------|------------------------------------------------------------|----------------------------------|
+/// | `dates`     | ISO date `YYYY-MM-DD` (month 01-12, day 01-31)             | `2024-05-01`, `2024`             |
+/// | `versions`  | digit groups joined by `.` or `-`, optionally after a `v`  | `v1.2.3`, `1.0-2` (never split)  |
+/// | `thousands` | `,` between a digit and a group of exactly three digits    | `1,000,000` -> `1000000`         |
+///
+/// A date is also version-like, so with `dates` off and `versions` on it stays
+/// whole without a year token. Tokens no rule takes go on to `WordPunctuation`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NumberRules {
+    pub thousands: bool,
+    pub versions: bool,
+    pub dates: bool,
+}
+
+impl NumberRules {
+    /// Apply the rules to `tokens`, handing the tokens they don't take to `punctuation`.
+    pub fn apply(self, tokens: Vec<String>, punctuation: WordPunctuation) -> Vec<String> {
+        let mut out = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            if self.dates && is_iso_date(&token) {
+                let year = token[..4].to_string();
+                out.push(token);
+                out.push(year);
+            } else if self.versions && is_version(&token) {
+                out.push(token);
+            } else if self.thousands {
+                punctuation.split_into(without_thousands_separators(token), &mut out);
+            } else {
+                punctuation.split_into(token, &mut out);
+            }
+        }
+        out
+    }
+}
+
+/// True for `YYYY-MM-DD` with a month of 01-12 and a day of 01-31.
+fn is_iso_date(token: &str) -> bool {
+    let number = |part: &str, len: usize, range: std::ops::RangeInclusive<u32>| {
+        part.len() == len && part.bytes().all(|b| b.is_ascii_digit()) && part.parse().is_ok_and(|n| range.contains(&n))
+    };
+    match token.split('-').collect::<Vec<_>>()[..] {
+        [year, month, day] => number(year, 4, 0..=9999) && number(month, 2, 1..=12) && number(day, 2, 1..=31),
+        _ => false,
+    }
+}
+
+/// True for two or more digit groups joined by single `.` or `-`, optionally after a `v`.
+fn is_version(token: &str) -> bool {
+    let digits = token.strip_prefix('v').unwrap_or(token);
+    digits.contains(['.', '-'])
+        && digits.split(['.', '-']).all(|group| !group.is_empty() && group.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// `token` without commas between a digit and a group of exactly three digits.
+fn without_thousands_separators(token: String) -> String {
+    if !token.contains(',') {
+        return token;
+    }
+    let bytes = token.as_bytes();
+    let separator = |i: usize| {
+        i > 0
+            && bytes[i - 1].is_ascii_digit()
+            && bytes.get(i + 1..i + 4).is_some_and(|group| group.iter().all(u8::is_ascii_digit))
+            && !bytes.get(i + 4).is_some_and(u8::is_ascii_digit)
+    };
+    token.char_indices().filter(|&(i, c)| !(c == ',' && separator(i))).map(|(_, c)| c).collect()
+}
+
+/// `tokens` with the parts of identifiers in `text` after them. Tokens are already
+/// lowercase, so identifiers are found among the words of `text` (at Unicode word
+/// boundaries) and matched to tokens by their lowercase form: "JoinSet" gives
+/// "joinset", "join", "set", and "join_next" gives "join_next", "join", "next".
+fn with_identifier_parts(text: &str, tokens: Vec<String>) -> Vec<String> {
+    let identifiers: HashMap<String, Vec<String>> = text
+        .unicode_words()
+        .filter_map(|word| {
+            let token = word.to_lowercase();
+            let parts = identifier_parts(word);
+            (!parts.is_empty() && parts != [token.as_str()]).then_some((token, parts))
+        })
+        .collect();
+    if identifiers.is_empty() {
+        return tokens;
+    }
+    let mut out = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let parts = identifiers.get(&token);
+        out.push(token);
+        out.extend(parts.into_iter().flatten().cloned());
+    }
+    out
+}
+
+/// Lowercase parts of an identifier, split at underscores, where a lowercase
+/// letter meets a capital ("joinSet" -> "join", "set"), before the last capital of
+/// a run followed by a lowercase letter ("HTTPServer" -> "http", "server"), and
+/// between letters and digits ("utf8" -> "utf", "8"). Other punctuation stays in
+/// its part, so "3.14" is one part. A word of only underscores has none.
+fn identifier_parts(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            if !part.is_empty() {
+                parts.push(std::mem::take(&mut part).to_lowercase());
+            }
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|j| chars[j]).filter(|p| p.is_alphanumeric() && c.is_alphanumeric());
+        let boundary = prev.is_some_and(|p| {
+            (p.is_lowercase() && c.is_uppercase())
+                || (p.is_uppercase() && c.is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase()))
+                || p.is_alphabetic() != c.is_alphabetic()
+        });
+        if boundary && !part.is_empty() {
+            parts.push(std::mem::take(&mut part).to_lowercase());
+        }
+        part.push(c);
+    }
+    if !part.is_empty() {
+        parts.push(part.to_lowercase());
+    }
+    parts
+}
+
+/// Version of the Unicode word boundary rules `tokenize_unicode` follows, as "major.minor.update".
+pub fn segmentation_unicode_version() -> String {
+    let (major, minor, update) = unicode_segmentation::UNICODE_VERSION;
+    format!("{}.{}.{}", major, minor, update)
+}
+
+/// Unicode normalization applied to text before it is tokenized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// Text as written.
+    #[default]
+    None,
+    /// NFKC: composes decomposed text (as macOS sends it) and folds compatibility
+    /// forms, such as full-width Latin letters and digits ("Ｒｕｓｔ１２３" ->
+    /// "Rust123") and half-width Katakana ("ｶﾀｶﾅ" -> "カタカナ").
+    Nfkc,
+}
+
+impl Normalization {
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            Normalization::Nfkc if is_nfkc_quick(text.chars()) != IsNormalized::Yes => {
+                Cow::Owned(text.nfkc().collect())
+            }
+            _ => Cow::Borrowed(text),
+        }
+    }
+}
+
+/// How text is split into tokens, before stopwords and stemming.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    /// Whitespace-separated words (`tokenize`).
+    #[default]
+    Whitespace,
+    /// Whitespace-separated words, with CJK runs as character bigrams (`tokenize_cjk`).
+    Cjk,
+    /// Unicode word boundaries, with CJK runs as character bigrams (`tokenize_unicode`).
+    Unicode,
+}
+
+impl Tokenizer {
+    pub fn tokenize(self, text: &str) -> Vec<String> {
+        match self {
+            Tokenizer::Whitespace => tokenize(text),
+            Tokenizer::Cjk => tokenize_cjk(text),
+            Tokenizer::Unicode => tokenize_unicode(text),
+        }
+    }
+
+    /// Tokenize `text`, add identifier parts if `split_identifiers`, apply `numbers`
+    /// and `punctuation` and drop `stopwords`. Further stages compose on the result,
+    /// as `StandardAnalyzer::analyze` adds token filtering and stemming.
+    pub fn tokenize_filtered(
+        self,
+        text: &str,
+        split_identifiers: bool,
+        numbers: NumberRules,
+        punctuation: WordPunctuation,
+        stopwords: &Stopwords,
+    ) -> Vec<String> {
+        let mut tokens = self.tokenize(text);
+        if split_identifiers {
+            tokens = with_identifier_parts(text, tokens);
+        }
+        stopwords.remove_from(numbers.apply(tokens, punctuation))
+    }
+}
+
+/// Sanity limits that drop junk tokens (base64 blobs, hashes, CSS class soup,
+/// stray letters) before stemming. Lengths count characters, not bytes, and a
+/// token over a limit is dropped rather than truncated, so it can't collide with
+/// another. `None` disables a check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TokenFilter {
+    /// Longest token kept, in characters.
+    #[serde(default)]
+    pub max_len: Option<usize>,
+    /// Highest fraction of digits in a kept token (0-1).
+    #[serde(default)]
+    pub max_digit_ratio: Option<f64>,
+    /// Tokens made only of hex digits are dropped when longer than this.
+    #[serde(default)]
+    pub max_hex_len: Option<usize>,
+    /// Shortest token kept, in characters. A single CJK character between other
+    /// scripts is a one-character token, so 2 drops those too.
+    #[serde(default)]
+    pub min_len: Option<usize>,
+}
+
+/// Tokens dropped by a `TokenFilter`, by reason (occurrences, not distinct tokens).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenRejections {
+    pub too_short: usize,
+    pub too_long: usize,
+    pub digits: usize,
+    pub hex: usize,
+}
+
+impl TokenRejections {
+    pub fn total(&self) -> usize {
+        self.too_short + self.too_long + self.digits + self.hex
+    }
+}
+
+impl std::ops::AddAssign for TokenRejections {
+    fn add_assign(&mut self, other: Self) {
+        self.too_short += other.too_short;
+        self.too_long += other.too_long;
+        self.digits += other.digits;
+        self.hex += other.hex;
+    }
+}
+
+impl TokenFilter {
+    /// True if `token` passes every check; otherwise counts it in `rejected`.
+    fn accepts(&self, token: &str, rejected: &mut TokenRejections) -> bool {
+        let len = token.chars().count();
+        if self.min_len.is_some_and(|min| len < min) {
+            rejected.too_short += 1;
+            return false;
+        }
+        if self.max_len.is_some_and(|max| len > max) {
+            rejected.too_long += 1;
+            return false;
+        }
+        if let Some(max) = self.max_digit_ratio {
+            let digits = token.chars().filter(|c| c.is_ascii_digit()).count();
+            if digits as f64 > max * len as f64 {
+                rejected.digits += 1;
+                return false;
+            }
+        }
+        if self.max_hex_len.is_some_and(|max| len > max && token.chars().all(|c| c.is_ascii_hexdigit())) {
+            rejected.hex += 1;
+            return false;
+        }
+        true
+    }
+}
+
+/// Where a `Stopwords` list came from, recorded with it in the index.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StopwordList {
+    /// No stopwords.
+    #[default]
+    None,
+    /// The built-in English list (`stopwords::ENGLISH`).
+    English,
+    /// The built-in Japanese particles (`stopwords::JAPANESE`).
+    Japanese,
+    /// A file, by the path it was loaded from.
+    File(String),
+    /// Words given directly.
+    Custom,
+}
+
+/// A built-in stopword list, for `--stopword-language`. The Japanese particles
+/// only match whole tokens: with the `cjk` and `unicode` tokenizers, which pair
+/// CJK characters into bigrams, only particles standing alone are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StopwordLanguage {
+    English,
+    Japanese,
+}
+
+/// Words dropped from documents and queries, with where they came from. Words
+/// are stored lowercase, as `tokenize` produces them, and looked up in O(1).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stopwords {
+    list: StopwordList,
+    words: HashSet<String>,
+}
+
+impl Stopwords {
+    pub fn builtin(language: StopwordLanguage) -> Self {
+        let (list, words) = match language {
+            StopwordLanguage::English => (StopwordList::English, stopwords::ENGLISH),
+            StopwordLanguage::Japanese => (StopwordList::Japanese, stopwords::JAPANESE),
+        };
+        Self {
+            list,
+            words: words.iter().map(|word| word.to_string()).collect(),
+        }
+    }
+
+    /// Load a stopword list: one or more words per line, `#` starts a comment.
+    /// Words are tokenized like document text (with `WordPunctuation::Split`), so
+    /// case and punctuation don't matter.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self {
+            list: StopwordList::File(path.display().to_string()),
+            words: text
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or(""))
+                .flat_map(|line| WordPunctuation::Split.apply(tokenize(line)))
+                .collect(),
+        })
+    }
+
+    /// Whether `word` is a stopword, ignoring case like `tokenize`.
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(word)
+            || (word.chars().any(char::is_uppercase) && self.words.contains(&word.to_lowercase()))
+    }
+
+    /// `tokens` without the stopwords: the stopword stage, after any tokenizer.
+    pub fn remove_from(&self, mut tokens: Vec<String>) -> Vec<String> {
+        tokens.retain(|token| !self.contains(token));
+        tokens
+    }
+
+    /// The words, sorted.
+    fn sorted(&self) -> Vec<&str> {
+        let mut words: Vec<&str> = self.words.iter().map(String::as_str).collect();
+        words.sort_unstable();
+        words
+    }
+}
+
+/// `english (127 words)`, `file stop.txt (12 words)`, `none`.
+impl std::fmt::Display for Stopwords {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.list {
+            StopwordList::None => return write!(f, "none"),
+            StopwordList::English => write!(f, "english")?,
+            StopwordList::Japanese => write!(f, "japanese")?,
+            StopwordList::File(path) => write!(f, "file {}", path)?,
+            StopwordList::Custom => write!(f, "custom")?,
+        }
+        write!(f, " ({} words)", self.words.len())
+    }
+}
+
+/// Words given directly, labelled as a built-in list when they are exactly one.
+impl From<Vec<String>> for Stopwords {
+    fn from(words: Vec<String>) -> Self {
+        let words: HashSet<String> = words.into_iter().collect();
+        let list = [StopwordLanguage::English, StopwordLanguage::Japanese]
+            .into_iter()
+            .map(Self::builtin)
+            .find(|builtin| builtin.words == words)
+            .map_or(StopwordList::Custom, |builtin| builtin.list);
+        let list = if words.is_empty() { StopwordList::None } else { list };
+        Self { list, words }
+    }
+}
+
+/// Stored form of `Stopwords`, words sorted so saved indexes are reproducible.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredStopwords {
+    list: StopwordList,
+    words: Vec<String>,
+}
+
+impl serde::Serialize for Stopwords {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let stored = StoredStopwords {
+            list: self.list.clone(),
+            words: self.sorted().into_iter().map(String::from).collect(),
+        };
+        stored.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Stopwords {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let stored = StoredStopwords::deserialize(deserializer)?;
+        Ok(Self {
+            list: stored.list,
+            words: stored.words.into_iter().collect(),
+        })
+    }
+}
+
+/// Turns text into index terms. An index analyzes its documents and queries with
+/// one analyzer, recorded in the index as an `AnalyzerConfig` so that every
+/// reader rebuilds the same one. Served indexes are queried from many threads.
+pub trait Analyzer: Send + Sync {
+    /// Terms of `text`, counting the tokens the token filter drops in `rejected`.
+    fn analyze_counting(&self, text: &str, rejected: &mut TokenRejections) -> Vec<String>;
+
+    /// `text` as analysis sees it before tokenizing. Queries are normalized whole
+    /// first, so their quotes, colons and operators are read the same way.
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str>;
+
+    /// This analyzer without stopword removal, for queries made only of stopwords.
+    fn keeping_stopwords(&self) -> Box<dyn Analyzer>;
+
+    /// Configuration that rebuilds this analyzer, as stored in the index.
+    fn config(&self) -> AnalyzerConfig;
+
+    /// Terms of `text`.
+    fn analyze(&self, text: &str) -> Vec<String> {
+        self.analyze_counting(text, &mut TokenRejections::default())
+    }
+
+    /// Like `analyze`, with positions counted after stopwords are dropped, so a
+    /// phrase that spans a stopword still matches its remaining words.
+    /// Tokens the token filter drops are counted in `rejected`.
+    fn analyze_with_positions(&self, text: &str, rejected: &mut TokenRejections) -> Vec<(String, u32)> {
+        self.analyze_counting(text, rejected)
+            .into_iter()
+            .enumerate()
+            .map(|(i, word)| (word, i as u32))
+            .collect()
+    }
+}
+
+/// Analyzers to build an index with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AnalyzerKind {
+    /// `StandardAnalyzer`.
+    #[default]
+    Standard,
+    /// `CjkBigramAnalyzer`.
+    CjkBigram,
+}
+
+impl AnalyzerKind {
+    /// An analyzer of this kind around the `standard` stages.
+    pub fn build(self, standard: StandardAnalyzer) -> AnalyzerConfig {
+        match self {
+            AnalyzerKind::Standard => AnalyzerConfig::Standard(standard),
+            AnalyzerKind::CjkBigram => AnalyzerConfig::CjkBigram(CjkBigramAnalyzer { standard }),
+        }
+    }
+}
+
+/// The analyzer an index was built with, tagged with its kind.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnalyzerConfig {
+    Standard(StandardAnalyzer),
+    CjkBigram(CjkBigramAnalyzer),
+}
+
+impl AnalyzerConfig {
+    /// The standard stages the analyzer runs (for `CjkBigram`, on text other than CJK).
+    pub fn standard(&self) -> &StandardAnalyzer {
+        match self {
+            AnalyzerConfig::Standard(standard) => standard,
+            AnalyzerConfig::CjkBigram(cjk) => &cjk.standard,
+        }
+    }
+
+    /// The stages in which the analyzer this build rebuilds from the recorded
+    /// configuration (`StandardAnalyzer::rebuilt`) differs from it, if any: queries
+    /// would then be analyzed unlike the documents were.
+    pub fn mismatch(&self) -> Option<String> {
+        let recorded = self.standard();
+        let stages = recorded.differing_stages(&recorded.rebuilt());
+        (!stages.is_empty()).then(|| format!("this build's analyzer differs from the index's in {}", stages.join(", ")))
+    }
+
+    /// The analyzer itself.
+    fn analyzer(&self) -> &dyn Analyzer {
+        match self {
+            AnalyzerConfig::Standard(standard) => standard,
+            AnalyzerConfig::CjkBigram(cjk) => cjk,
+        }
+    }
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        AnalyzerConfig::Standard(StandardAnalyzer::default())
+    }
+}
+
+impl From<StandardAnalyzer> for AnalyzerConfig {
+    fn from(standard: StandardAnalyzer) -> Self {
+        AnalyzerConfig::Standard(standard)
+    }
+}
+
+impl Analyzer for AnalyzerConfig {
+    fn analyze_counting(&self, text: &str, rejected: &mut TokenRejections) -> Vec<String> {
+        self.analyzer().analyze_counting(text, rejected)
+    }
+
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.analyzer().normalize(text)
+    }
+
+    fn keeping_stopwords(&self) -> Box<dyn Analyzer> {
+        self.analyzer().keeping_stopwords()
+    }
+
+    fn config(&self) -> AnalyzerConfig {
+        self.clone()
+    }
+}
+
+/// Searchable Japanese and Chinese without a dictionary: each run of Han,
+/// Hiragana and Katakana becomes overlapping character bigrams, and the text
+/// between runs goes through the `standard` stages, in the order they occur.
+/// "Rustの非同期処理" gives "rust", "の非", "非同", "同期", "期処", "処理"; a run of
+/// one character stays a unigram ("Rust と Go" gives "rust", "と", "go").
+///
+/// Text is always NFKC-normalized first, whatever the standard stages say, so
+/// full-width Latin letters and digits ("Ｒｕｓｔ１２３") are analyzed, and
+/// lowercased, as ASCII, and half-width Katakana joins the full-width runs.
+/// Bigrams skip stopwords, the token filter and stemming, which are meant for words.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CjkBigramAnalyzer {
+    pub standard: StandardAnalyzer,
+}
+
+impl Analyzer for CjkBigramAnalyzer {
+    fn analyze_counting(&self, text: &str, rejected: &mut TokenRejections) -> Vec<String> {
+        let text = self.normalize(text);
+        let mut terms = Vec::new();
+        let mut rest = text.as_ref();
+        while let Some(start) = rest.find(is_cjk) {
+            terms.extend(self.standard.analyze_counting(&rest[..start], rejected));
+            let run = &rest[start..];
+            let end = run.find(|c: char| !is_cjk(c)).unwrap_or(run.len());
+            push_cjk_run(&run[..end].chars().collect::<Vec<_>>(), &mut terms);
+            rest = &run[end..];
+        }
+        terms.extend(self.standard.analyze_counting(rest, rejected));
+        terms
+    }
+
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        Normalization::Nfkc.apply(text)
+    }
+
+    fn keeping_stopwords(&self) -> Box<dyn Analyzer> {
+        Box::new(CjkBigramAnalyzer {
+            standard: StandardAnalyzer {
+                stopwords: Stopwords::default(),
+                ..self.standard.clone()
+            },
+        })
+    }
+
+    fn config(&self) -> AnalyzerConfig {
+        AnalyzerConfig::CjkBigram(self.clone())
+    }
+}
+
+/// The standard analyzer: normalization, a tokenizer, identifier splitting, number
+/// and punctuation rules, stopwords, junk token limits and stemming, in that order. The default
+/// only splits on whitespace; `with_*` add or configure the stages.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StandardAnalyzer {
+    /// Words dropped from documents and queries, and where they came from.
+    pub stopwords: Stopwords,
+    /// Stemmer language code (`en`), applied after stopword removal.
+    pub stemming: Option<String>,
+    /// How text is split into tokens.
+    pub tokenizer: Tokenizer,
+    /// Junk token limits.
+    pub token_filter: TokenFilter,
+    /// Unicode normalization before tokenizing.
+    pub normalization: Normalization,
+    /// With the `Unicode` tokenizer, the Unicode version of the word boundary rules
+    /// documents were split with (`segmentation_unicode_version`).
+    pub unicode_version: Option<String>,
+    /// Apostrophes and hyphens inside tokens.
+    pub word_punctuation: WordPunctuation,
+    /// Number, version and date rules.
+    pub number_rules: NumberRules,
+    /// Also index the parts of identifiers (`with_split_identifiers`).
+    pub split_identifiers: bool,
+}
+
+/// Snowball algorithm for a stemming language code.
+fn stem_algorithm(code: &str) -> Option<Algorithm> {
+    match code {
+        "en" => Some(Algorithm::English),
+        _ => None,
+    }
+}
+
+impl StandardAnalyzer {
+    /// Analyzer that drops the given stopwords.
+    pub fn with_stopwords(stopwords: Stopwords) -> Self {
+        Self {
+            stopwords,
+            ..Self::default()
+        }
+    }
+
+    /// Also stem words in the given language (`None` = no stemming).
+    pub fn with_stemming(mut self, language: Option<String>) -> Self {
+        self.stemming = language;
+        self
+    }
+
+    /// Split text with the given tokenizer.
+    pub fn with_tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = tokenizer;
+        self.unicode_version = (tokenizer == Tokenizer::Unicode).then(segmentation_unicode_version);
+        self
+    }
+
+    /// Drop tokens that fail `filter`.
+    pub fn with_token_filter(mut self, filter: TokenFilter) -> Self {
+        self.token_filter = filter;
+        self
+    }
+
+    /// Handle apostrophes and hyphens inside tokens with `punctuation`.
+    pub fn with_word_punctuation(mut self, punctuation: WordPunctuation) -> Self {
+        self.word_punctuation = punctuation;
+        self
+    }
+
+    /// Keep numbers, versions and dates together by `rules`.
+    pub fn with_number_rules(mut self, rules: NumberRules) -> Self {
+        self.number_rules = rules;
+        self
+    }
+
+    /// Follow identifiers ("JoinSet", "join_next") with their parts if `split`.
+    pub fn with_split_identifiers(mut self, split: bool) -> Self {
+        self.split_identifiers = split;
+        self
+    }
+
+    /// Normalize text with `normalization` before tokenizing it.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Fail if this build can't apply the analyzer (e.g. an index saved with a
+    /// stemmer we don't have), since queries would silently stop matching.
+    pub fn check(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.stemming.as_deref() {
+            Some(code) if stem_algorithm(code).is_none() => {
+                Err(format!("index was built with stemming {:?}, which this build does not support", code).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A warning if documents were split by other word boundary rules than this
+    /// build's: queries are then split differently, and some stop matching until
+    /// the index is rebuilt. Nothing is wrong otherwise.
+    pub fn segmentation_mismatch(&self) -> Option<String> {
+        if self.tokenizer != Tokenizer::Unicode {
+            return None;
+        }
+        let current = segmentation_unicode_version();
+        match self.unicode_version.as_deref() {
+            Some(version) if version == current => None,
+            version => Some(format!(
+                "index was split with Unicode {} word boundaries, this build uses Unicode {}; rebuild it if queries miss",
+                version.unwrap_or("(unknown)"),
+                current
+            )),
+        }
+    }
+
+    /// The analyzer this build puts together from this configuration: the built-in
+    /// stopword lists and word boundary rules as they are now, and only the stemmers it has.
+    pub fn rebuilt(&self) -> Self {
+        let stopwords = match self.stopwords.list {
+            StopwordList::English => Stopwords::builtin(StopwordLanguage::English),
+            StopwordList::Japanese => Stopwords::builtin(StopwordLanguage::Japanese),
+            _ => self.stopwords.clone(),
+        };
+        Self {
+            stopwords,
+            stemming: self.stemming.clone().filter(|code| stem_algorithm(code).is_some()),
+            unicode_version: (self.tokenizer == Tokenizer::Unicode).then(segmentation_unicode_version),
+            ..self.clone()
+        }
+    }
+
+    /// Names of the stages `other` configures differently.
+    fn differing_stages(&self, other: &Self) -> Vec<&'static str> {
+        [
+            ("stopwords", self.stopwords != other.stopwords),
+            ("stemming", self.stemming != other.stemming),
+            ("tokenizer", self.tokenizer != other.tokenizer),
+            ("token filter", self.token_filter != other.token_filter),
+            ("normalization", self.normalization != other.normalization),
+            ("word boundaries", self.unicode_version != other.unicode_version),
+            ("word punctuation", self.word_punctuation != other.word_punctuation),
+            ("number rules", self.number_rules != other.number_rules),
+            ("identifier splitting", self.split_identifiers != other.split_identifiers),
+        ]
+        .into_iter()
+        .filter_map(|(stage, differs)| differs.then_some(stage))
+        .collect()
+    }
+}
+
+impl Analyzer for StandardAnalyzer {
+    /// Normalize, tokenize, add identifier parts, apply the number rules, split
+    /// punctuated words, drop stopwords and junk tokens, and stem.
+    fn analyze_counting(&self, text: &str, rejected: &mut TokenRejections) -> Vec<String> {
+        let stemmer = self.stemming.as_deref().and_then(stem_algorithm).map(Stemmer::create);
+        self.tokenizer
+            .tokenize_filtered(
+                &self.normalization.apply(text),
+                self.split_identifiers,
+                self.number_rules,
+                self.word_punctuation,
+                &self.stopwords,
+            )
+            .into_iter()
+            .filter(|w| self.token_filter.accepts(w, rejected))
+            .map(|w| match &stemmer {
+                Some(stemmer) => stemmer.stem(&w).into_owned(),
+                None => w,
+            })
+            .collect()
+    }
+
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.normalization.apply(text)
+    }
+
+    fn keeping_stopwords(&self) -> Box<dyn Analyzer> {
+        Box::new(StandardAnalyzer {
+            stopwords: Stopwords::default(),
+            ..self.clone()
+        })
+    }
+
+    fn config(&self) -> AnalyzerConfig {
+        self.clone().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english_stemming() -> StandardAnalyzer {
+        StandardAnalyzer::default().with_stemming(Some("en".to_string()))
+    }
+
+    #[test]
+    fn stemming_unifies_plurals_and_verb_forms() {
+        let analyzer = english_stemming();
+        for (a, b) in [
+            ("index", "indexes"),
+            ("page", "pages"),
+            ("crawl", "crawling"),
+            ("crawl", "crawled"),
+            ("rank", "ranking ranked"),
+        ] {
+            let (a, b) = (analyzer.analyze(a), analyzer.analyze(b));
+            assert!(b.iter().all(|stem| a.contains(stem)), "{:?} vs {:?}", a, b);
+        }
+        assert_eq!(StandardAnalyzer::default().analyze("crawling"), ["crawling"]);
+    }
+
+    #[test]
+    fn unknown_stemming_language_fails_the_check() {
+        assert!(english_stemming().check().is_ok());
+        let error = StandardAnalyzer::default().with_stemming(Some("xx".to_string())).check().unwrap_err();
+        assert!(error.to_string().contains("\"xx\""), "{}", error);
+    }
+
+    #[test]
+    fn nfkc_folds_full_width_half_width_and_decomposed_forms() {
+        let cases = [
+            ("Ｒｕｓｔ", "Rust"),
+            ("１２３", "123"),
+            ("Ｔｏｋｉｏ　１．０", "Tokio 1.0"),
+            ("ｶﾀｶﾅ", "カタカナ"),
+            ("ﾃﾞｰﾀﾍﾞｰｽ", "データベース"),
+            ("cafe\u{301}", "caf\u{e9}"),
+            ("\u{30ab}\u{3099}", "\u{30ac}"),
+            ("ﬁle", "file"),
+        ];
+        for (text, normalized) in cases {
+            assert_eq!(Normalization::Nfkc.apply(text), normalized, "{}", text);
+            assert_eq!(Normalization::None.apply(text), text);
+        }
+        assert!(matches!(Normalization::Nfkc.apply("already normal"), Cow::Borrowed(_)));
+
+        let analyzer = StandardAnalyzer::default().with_normalization(Normalization::Nfkc);
+        for (query, indexed) in [("Ｒｕｓｔ １２３", "rust 123"), ("cafe\u{301}", "caf\u{e9}"), ("ｶﾀｶﾅ", "カタカナ")] {
+            assert_eq!(analyzer.analyze(query), analyzer.analyze(indexed), "{}", query);
+        }
+        assert_eq!(StandardAnalyzer::default().analyze("Ｒｕｓｔ"), ["ｒｕｓｔ"]);
+    }
+
+    #[test]
+    fn unicode_segmentation_table() {
+        let cases: [(&str, &[&str]); 14] = [
+            // English
+            ("Hello, World!", &["hello", "world"]),
+            ("Don't panic: it's fine.", &["don't", "panic", "it's", "fine"]),
+            ("state-of-the-art search", &["state-of-the-art", "search"]),
+            ("(parenthesized) \"quoted\"", &["parenthesized", "quoted"]),
+            // Japanese: Han and Kana runs become bigrams, a lone character a unigram.
+            ("検索エンジン", &["検索", "索エ", "エン", "ンジ", "ジン"]),
+            ("東京、大阪", &["東京", "大阪"]),
+            ("猫", &["猫"]),
+            // Mixed scripts
+            ("Rustで検索", &["rust", "で検", "検索"]),
+            ("Tokioのランタイム v1", &["tokio", "のラ", "ラン", "ンタ", "タイ", "イム", "v1"]),
+            ("Ünïcödé café", &["ünïcödé", "café"]),
+            // Emoji and symbols are dropped.
+            ("rust 🦀 is fun 🎉!", &["rust", "is", "fun"]),
+            // Numbers keep their inner punctuation; hyphens join as in words.
+            ("3.14 1,000 2024-05-01", &["3.14", "1,000", "2024-05-01"]),
+            ("42nd 3rd", &["42nd", "3rd"]),
+            ("", &[]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(tokenize_unicode(text), expected, "{}", text);
+            assert_eq!(Tokenizer::Unicode.tokenize(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn unicode_version_mismatch_warns() {
+        let analyzer = StandardAnalyzer::default().with_tokenizer(Tokenizer::Unicode);
+        assert_eq!(analyzer.unicode_version, Some(segmentation_unicode_version()));
+        assert_eq!(analyzer.segmentation_mismatch(), None);
+        let older = StandardAnalyzer {
+            unicode_version: Some("9.0.0".to_string()),
+            ..analyzer.clone()
+        };
+        assert!(older.segmentation_mismatch().unwrap().contains("Unicode 9.0.0"));
+        let unknown = StandardAnalyzer {
+            unicode_version: None,
+            ..analyzer
+        };
+        assert!(unknown.segmentation_mismatch().unwrap().contains("(unknown)"));
+        assert_eq!(StandardAnalyzer::default().segmentation_mismatch(), None);
+    }
+
+    #[test]
+    fn mismatch_compares_the_whole_recorded_analyzer() {
+        let current = StandardAnalyzer::with_stopwords(Stopwords::builtin(StopwordLanguage::English))
+            .with_stemming(Some("en".to_string()))
+            .with_tokenizer(Tokenizer::Unicode);
+        assert_eq!(AnalyzerConfig::from(current.clone()).mismatch(), None);
+        let japanese = StandardAnalyzer::with_stopwords(Stopwords::builtin(StopwordLanguage::Japanese));
+        assert_eq!(AnalyzerConfig::from(japanese).mismatch(), None);
+
+        let mut stale = Stopwords::builtin(StopwordLanguage::English);
+        stale.words.remove("the");
+        let older = StandardAnalyzer {
+            stopwords: stale,
+            unicode_version: Some("9.0.0".to_string()),
+            ..current.clone()
+        };
+        let mismatch = AnalyzerConfig::CjkBigram(CjkBigramAnalyzer { standard: older }).mismatch().unwrap();
+        assert!(mismatch.ends_with("stopwords, word boundaries"), "{}", mismatch);
+
+        // Lists from files and custom words are kept as recorded.
+        let custom = StandardAnalyzer::with_stopwords(vec!["foo".to_string()].into());
+        assert_eq!(AnalyzerConfig::from(custom).mismatch(), None);
+    }
+
+    #[test]
+    fn stopwords_match_case_insensitively_and_record_their_list() {
+        let english = Stopwords::builtin(StopwordLanguage::English);
+        assert!(english.contains("the") && english.contains("The") && english.contains("THE"));
+        assert!(!english.contains("rust"));
+        let tokens = Tokenizer::Whitespace.tokenize_filtered(
+            "The Rust and the Tokio",
+            false,
+            NumberRules::default(),
+            WordPunctuation::Keep,
+            &english,
+        );
+        assert_eq!(tokens, ["rust", "tokio"]);
+        let japanese = Stopwords::builtin(StopwordLanguage::Japanese);
+        assert_eq!(japanese.remove_from(tokenize("東京 と 大阪")), ["東京", "大阪"]);
+
+        assert_eq!(Stopwords::from(vec!["the".to_string(), "a".to_string()]).to_string(), "custom (2 words)");
+        let words: Vec<String> = stopwords::JAPANESE.iter().map(|word| word.to_string()).collect();
+        assert_eq!(Stopwords::from(words), japanese);
+        assert_eq!(Stopwords::from(Vec::new()).to_string(), "none");
+        let json = serde_json::to_string(&english).unwrap();
+        assert!(json.starts_with(r#"{"list":"english","words":["a","about","#), "{}", json);
+        assert_eq!(serde_json::from_str::<Stopwords>(&json).unwrap(), english);
+
+        let path = std::env::temp_dir().join(format!("rustysearch-test-{}-stopwords.txt", std::process::id()));
+        std::fs::write(&path, "# project words\nFoo, Bar\n\nbaz # trailing comment\n").unwrap();
+        let loaded = Stopwords::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(["foo", "Bar", "baz"].iter().all(|word| loaded.contains(word)));
+        assert!(!loaded.contains("project") && !loaded.contains("trailing"));
+        assert_eq!(loaded.to_string(), format!("file {} (3 words)", path.display()));
+    }
+
+    #[test]
+    fn a_query_of_only_stopwords_keeps_them() {
+        let analyzer = StandardAnalyzer::with_stopwords(Stopwords::builtin(StopwordLanguage::English));
+        assert!(analyzer.analyze("The Who").is_empty());
+        assert!(analyzer.analyze("to be or not to be").is_empty());
+        assert_eq!(analyzer.keeping_stopwords().analyze("The Who"), ["the", "who"]);
+        assert_eq!(analyzer.keeping_stopwords().analyze("to be or not"), ["to", "be", "or", "not"]);
+        let cjk = CjkBigramAnalyzer { standard: analyzer };
+        assert_eq!(cjk.analyze("the で"), ["で"]);
+        assert_eq!(cjk.keeping_stopwords().analyze("the で"), ["the", "で"]);
+    }
+
+    #[test]
+    fn word_punctuation_matrix() {
+        // (token, with Keep, with Split)
+        let cases: [(&str, &[&str], &[&str]); 12] = [
+            ("state-of-the-art", &["state-of-the-art"], &["state-of-the-art", "state", "of", "the", "art"]),
+            ("e-mail", &["e-mail"], &["e-mail", "e", "mail"]),
+            ("don't", &["don't"], &["dont"]),
+            ("don\u{2019}t", &["don\u{2019}t"], &["dont"]),
+            ("rust's", &["rust's"], &["rust"]),
+            ("rust\u{2019}s", &["rust\u{2019}s"], &["rust"]),
+            ("o'neill", &["o'neill"], &["oneill"]),
+            ("o'neill's", &["o'neill's"], &["oneill"]),
+            ("rock\u{2011}n\u{2019}roll", &["rock\u{2011}n\u{2019}roll"], &["rock-nroll", "rock", "nroll"]),
+            ("well--known", &["well--known"], &["well-known", "well", "known"]),
+            ("-'-", &["-'-"], &[]),
+            ("plain", &["plain"], &["plain"]),
+        ];
+        for (token, keep, split) in cases {
+            assert_eq!(WordPunctuation::Keep.apply(vec![token.to_string()]), keep, "{}", token);
+            assert_eq!(WordPunctuation::Split.apply(vec![token.to_string()]), split, "{}", token);
+        }
+
+        // Documents and queries go through the same rules, so either form finds the other.
+        let analyzer = StandardAnalyzer::default().with_word_punctuation(WordPunctuation::Split);
+        let document = analyzer.analyze("A state-of-the-art crawler; don\u{2019}t panic.");
+        for query in ["art", "state-of-the-art", "dont", "don't", "don\u{2019}t"] {
+            let terms = analyzer.analyze(query);
+            assert!(!terms.is_empty() && terms.iter().all(|term| document.contains(term)), "{}", query);
+        }
+        assert!(!StandardAnalyzer::default().analyze("state-of-the-art").contains(&"art".to_string()));
+        // Stopwords are removed after splitting, parts included.
+        let analyzer = StandardAnalyzer::with_stopwords(Stopwords::builtin(StopwordLanguage::English))
+            .with_word_punctuation(WordPunctuation::Split);
+        assert_eq!(analyzer.analyze("state-of-the-art"), ["state-of-the-art", "state", "art"]);
+    }
+
+    #[test]
+    fn token_offsets_slice_the_original_text() {
+        let text = "Grüße, 東京! «ÉCOLE» İstanbul 🦀 naïve";
+        let tokens = tokenize_with_positions(text);
+        let spans: Vec<(&str, &str, u32, usize, usize)> = tokens
+            .iter()
+            .map(|t| {
+                let word = &text[t.byte_offset..t.byte_offset + t.byte_len];
+                (t.text.as_str(), word, t.position, t.byte_offset, t.byte_len)
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                ("grüße", "Grüße", 0, 0, 7),
+                ("東京", "東京", 1, 9, 6),
+                ("école", "ÉCOLE", 2, 19, 6),
+                // Lowercasing İ adds a combining dot, so the token is longer than the word.
+                ("i\u{307}stanbul", "İstanbul", 3, 28, 9),
+                ("naïve", "naïve", 4, 43, 6),
+            ]
+        );
+        assert_eq!(tokenize(text), tokens.into_iter().map(|t| t.text).collect::<Vec<_>>());
+
+        for text in ["", "   ", "!!! ???", "  ａｂｃ  ｄ", "Ünïcödé\tcafé\n日本語 テキスト"] {
+            let tokens = tokenize_with_positions(text);
+            for (i, token) in tokens.iter().enumerate() {
+                let word = &text[token.byte_offset..token.byte_offset + token.byte_len];
+                assert_eq!(word.to_lowercase(), token.text, "{:?}", text);
+                assert_eq!(token.position, i as u32);
+            }
+            assert_eq!(tokenize(text), tokens.into_iter().map(|t| t.text).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn number_rules_table() {
+        let rules = [
+            NumberRules::default(),
+            NumberRules { thousands: true, ..NumberRules::default() },
+            NumberRules { versions: true, ..NumberRules::default() },
+            NumberRules { dates: true, ..NumberRules::default() },
+            NumberRules { thousands: true, versions: true, dates: true },
+        ];
+        // Token, then its terms with no rules, only `thousands`, only `versions`,
+        // only `dates` and all of them, before `WordPunctuation::Split`.
+        let split_date: &[&str] = &["2024-05-01", "2024", "05", "01"];
+        let cases: [(&str, [&[&str]; 5]); 15] = [
+            ("2024", [&["2024"]; 5]),
+            ("1,000", [&["1,000"], &["1000"], &["1,000"], &["1,000"], &["1000"]]),
+            ("1,000,000", [&["1,000,000"], &["1000000"], &["1,000,000"], &["1,000,000"], &["1000000"]]),
+            ("12,34", [&["12,34"]; 5]),
+            ("1,0000", [&["1,0000"]; 5]),
+            ("a,000", [&["a,000"]; 5]),
+            ("v1.2.3", [&["v1.2.3"]; 5]),
+            ("1..2", [&["1..2"]; 5]),
+            ("1.0-2", [&["1.0-2", "1.0", "2"], &["1.0-2", "1.0", "2"], &["1.0-2"], &["1.0-2", "1.0", "2"], &["1.0-2"]]),
+            ("rust-1.0", [&["rust-1.0", "rust", "1.0"]; 5]),
+            ("2024-05-01", [split_date, split_date, &["2024-05-01"], &["2024-05-01", "2024"], &["2024-05-01", "2024"]]),
+            (
+                "2024-12-31",
+                [
+                    &["2024-12-31", "2024", "12", "31"],
+                    &["2024-12-31", "2024", "12", "31"],
+                    &["2024-12-31"],
+                    &["2024-12-31", "2024"],
+                    &["2024-12-31", "2024"],
+                ],
+            ),
+            (
+                "2024-13-01",
+                [
+                    &["2024-13-01", "2024", "13", "01"],
+                    &["2024-13-01", "2024", "13", "01"],
+                    &["2024-13-01"],
+                    &["2024-13-01", "2024", "13", "01"],
+                    &["2024-13-01"],
+                ],
+            ),
+            (
+                "2024-5-1",
+                [
+                    &["2024-5-1", "2024", "5", "1"],
+                    &["2024-5-1", "2024", "5", "1"],
+                    &["2024-5-1"],
+                    &["2024-5-1", "2024", "5", "1"],
+                    &["2024-5-1"],
+                ],
+            ),
+            (
+                "1,000-2,000",
+                [
+                    &["1,000-2,000", "1,000", "2,000"],
+                    &["1000-2000", "1000", "2000"],
+                    &["1,000-2,000", "1,000", "2,000"],
+                    &["1,000-2,000", "1,000", "2,000"],
+                    &["1000-2000", "1000", "2000"],
+                ],
+            ),
+        ];
+        for (token, expected) in cases {
+            for (rules, expected) in rules.iter().zip(expected) {
+                let terms = rules.apply(vec![token.to_string()], WordPunctuation::Split);
+                assert_eq!(terms, expected, "{} with {:?}", token, rules);
+            }
+        }
+        // With `Keep`, tokens no rule takes are left alone.
+        assert_eq!(NumberRules::default().apply(vec!["2024-05-01".to_string()], WordPunctuation::Keep), ["2024-05-01"]);
+
+        // The rules apply to the tokens of each tokenizer, in documents and queries alike.
+        let analyzer = StandardAnalyzer::default().with_tokenizer(Tokenizer::Unicode).with_number_rules(rules[4]);
+        assert_eq!(
+            analyzer.analyze("Released v1.2.3 on 2024-05-01 for $1,000."),
+            ["released", "v1.2.3", "on", "2024-05-01", "2024", "for", "1000"]
+        );
+    }
+
+    #[test]
+    fn cjk_bigram_outputs() {
+        let analyzer = CjkBigramAnalyzer::default();
+        let cases: [(&str, &[&str]); 9] = [
+            ("Rustの非同期処理", &["rust", "の非", "非同", "同期", "期処", "処理"]),
+            ("Rust と Go", &["rust", "と", "go"]),
+            ("猫", &["猫"]),
+            ("東京タワーとTokyo Tower", &["東京", "京タ", "タワ", "ワー", "ーと", "tokyo", "tower"]),
+            ("日本語、English。中文", &["日本", "本語", "english", "中文"]),
+            // Full-width Latin is folded and lowercased; half-width Katakana joins the run.
+            ("ＲＵＳＴ１２３の本", &["rust123", "の本"]),
+            ("ｶﾀｶﾅ検索", &["カタ", "タカ", "カナ", "ナ検", "検索"]),
+            ("人々", &["人々"]),
+            ("", &[]),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(analyzer.analyze(text), expected, "{}", text);
+        }
+
+        // Stopwords and stemming apply to the words between runs, not to the runs,
+        // even a lone particle.
+        let analyzer = CjkBigramAnalyzer {
+            standard: StandardAnalyzer::with_stopwords(Stopwords::from(vec!["the".into(), "of".into(), "の".into()]))
+                .with_stemming(Some("en".to_string())),
+        };
+        assert_eq!(analyzer.analyze("The crawling of 検索 の"), ["crawl", "検索", "の"]);
+        let config = AnalyzerKind::CjkBigram.build(StandardAnalyzer::default());
+        assert_eq!(config.analyze("Rustの非同期"), ["rust", "の非", "非同", "同期"]);
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.starts_with(r#"{"cjk-bigram":"#), "{}", json);
+        assert_eq!(serde_json::from_str::<AnalyzerConfig>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn identifier_parts_table() {
+        let cases: [(&str, &[&str]); 14] = [
+            ("JoinSet", &["join", "set"]),
+            ("join_next", &["join", "next"]),
+            ("joinSet", &["join", "set"]),
+            ("HTTPServer", &["http", "server"]),
+            ("getHTTPResponse", &["get", "http", "response"]),
+            ("ABC", &["abc"]),
+            ("utf8", &["utf", "8"]),
+            ("UTF8Decoder", &["utf", "8", "decoder"]),
+            ("snake_case_42", &["snake", "case", "42"]),
+            ("_private", &["private"]),
+            ("a__b", &["a", "b"]),
+            ("3.14", &["3.14"]),
+            ("plain", &["plain"]),
+            ("___", &[]),
+        ];
+        for (word, expected) in cases {
+            assert_eq!(identifier_parts(word), expected, "{}", word);
+        }
+
+        let analyzer = StandardAnalyzer::default().with_split_identifiers(true);
+        assert_eq!(analyzer.analyze("JoinSet"), ["joinset", "join", "set"]);
+        assert_eq!(analyzer.analyze("join_next()"), ["join_next", "join", "next"]);
+        assert_eq!(
+            analyzer.analyze("HTTPServer speaks utf8"),
+            ["httpserver", "http", "server", "speaks", "utf8", "utf", "8"]
+        );
+        assert!(analyzer.analyze("___ -- __").is_empty());
+        // Opt-in: the default analyzer keeps identifiers whole.
+        assert_eq!(StandardAnalyzer::default().analyze("JoinSet join_next"), ["joinset", "join_next"]);
+    }
+}